@@ -1,194 +1,11994 @@
-use std::{cell::RefCell, collections::HashMap};
-
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-use ic_cdk::api::time;
+use candid::{Decode, Encode, Nat, Principal};
+use ic_cdk::api::canister_balance128;
+use ic_cdk::api::management_canister::http_request::{
+    http_request as outcall, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+    HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, raw_rand, CanisterInstallMode, CanisterSettings,
+    CreateCanisterArgument, InstallCodeArgument,
+};
+use ic_certified_map::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, DefaultMemoryImpl, Memory as _, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+use validation::{
+    validate_category, validate_extra_questions, validate_image_url, validate_locale,
+    validate_max_selections, validate_metadata, validate_opens_at, validate_option_description,
+    validate_option_label, validate_options_for, validate_question, validate_tags,
+};
+
+// `cargo test` runs outside a canister, where these trap
+#[cfg(not(test))]
+use ic_cdk::api::{caller, data_certificate, is_controller, set_certified_data, time};
+#[cfg(not(test))]
+use ic_cdk::notify;
+#[cfg(not(test))]
+use ic_cdk_timers::set_timer;
+#[cfg(test)]
+use tests::system::{
+    caller, data_certificate, is_controller, notify, set_certified_data, set_timer, time,
+};
+
+mod validation;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
+type ConfigCell = Cell<Config, Memory>;
+// a vote log entry together with its (quiz id, seq) key
+type LoggedVote = ((u64, u64), Vote);
+type IdempotencyKey = (StorablePrincipal, Blob<MAX_IDEMPOTENCY_KEY_LEN>);
+// (caller, RateAction as u8, when)
+type RateKey = (StorablePrincipal, u8, u64);
+// what an ICRC-1 `icrc1_transfer` call comes back with
+type TransferResult = Result<Nat, TransferError>;
+// what the badge collection's `icrc7_mint` comes back with
+type MintResult = Result<Nat, MintError>;
+// what `get_shard_tallies` returns
+type ShardTallies = Result<Vec<(String, u32)>, Error>;
+// what a shard's `get_quiz` and `create_quiz_for` return
+type QuizResult = Result<Quiz, Error>;
+
+const MAX_FUZZY_DISTANCE: u32 = 8;
+const MAX_NONCE_LEN: usize = 64;
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+// invite codes are this many random bytes, hex-encoded
+const INVITE_CODE_BYTES: usize = 8;
+// a retry later than this creates a new quiz
+const IDEMPOTENCY_TTL_NS: u64 = NANOS_PER_DAY;
+const FEED_GRAVITY: f64 = 1.5;
+const MAX_TAGS: usize = 10;
+// every vote scans the allowlist, so it stays short
+const MAX_ALLOWED_VOTERS: usize = 20;
+// decimal places for shares and percentages unless Config says otherwise
+const DEFAULT_RESULT_PRECISION: u8 = 2;
+const MAX_RESULT_PRECISION: u8 = 6;
+// oldest journal entries of a quiz are dropped past this
+const MAX_HISTORY_ENTRIES: u64 = 50;
+// longer journal descriptions are cut off with "..."
+const MAX_CHANGE_DESCRIPTION_LEN: usize = 512;
+// past this a ballot stops being readable
+const MAX_OPTIONS: usize = 16;
+const MIN_ASCII_CHART_WIDTH: u32 = 10;
+const MAX_ASCII_CHART_WIDTH: u32 = 80;
+// default for Config::max_question_len, counted in chars
+const DEFAULT_MAX_QUESTION_LEN: u64 = 500;
+// default for Config::min_option_len, counted in chars after trimming
+const MIN_OPTION_LEN: u64 = 1;
+// default for Config::max_option_len, counted the same way
+const DEFAULT_MAX_OPTION_LEN: u64 = 100;
+// fewer than this and there's nothing to choose between
+const MIN_OPTIONS: usize = 2;
+// metadata is for small hints, not payloads
+const MAX_METADATA_LEN: usize = 256;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_HOUR: u64 = 3_600 * NANOS_PER_SEC;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+// what a quiz without a `default_locale` is written in
+const DEFAULT_LOCALE: &str = "en";
+const MAX_TRANSLATIONS: usize = 20;
+const MAX_LOCALE_LEN: usize = 35;
+// how far back `get_trending_quizzes` counts answers
+const TRENDING_WINDOW_HOURS: u64 = 24;
+// create_quizzes/purge_quizzes reject larger batches outright
+const MAX_BATCH_SIZE: usize = 100;
+// keeps an export chunk well below the 2 MB reply limit
+const MAX_EXPORT_CHUNK_BYTES: u32 = 1_500_000;
+const CERTIFIED_RESULTS_LABEL: &[u8] = b"results";
+// total votes at which a quiz's webhook hears about it
+const VOTE_MILESTONES: [u64; 4] = [10, 100, 1_000, 10_000];
+const MAX_WEBHOOK_URL_LEN: usize = 512;
+const MAX_OPTION_DESCRIPTION_LEN: usize = 280;
+const MAX_IMAGE_URL_LEN: usize = 512;
+// a delivery is dropped after this many failed attempts
+const MAX_WEBHOOK_ATTEMPTS: u32 = 5;
+// doubled after every failed attempt
+const WEBHOOK_RETRY_BASE_NS: u64 = 60_000_000_000;
+// the transform throws the body away, but it's paid for up to this size
+const WEBHOOK_MAX_RESPONSE_BYTES: u64 = 2_048;
+// well above what a small outcall costs on a 13-node subnet; the rest is refunded
+const WEBHOOK_CYCLES: u128 = 200_000_000;
+// every canister hands out quiz ids from its own block of this size, starting
+// at its `id_base`
+const SHARD_ID_RANGE: u64 = 1 << 40;
+// what a new shard starts out with
+const SHARD_CYCLES: u128 = 2_000_000_000_000;
+const WASM_PAGE_BYTES: u64 = 65_536;
+// one past the highest MemoryId handed out in `thread_local!`
+const MEMORY_ID_COUNT: u8 = 41;
+// what each MemoryId holds, indexed by id
+const MEMORY_REGIONS: [&str; MEMORY_ID_COUNT as usize] = [
+    "quiz_id_counter",
+    "quizzes",
+    "vote_log",
+    "set_id_counter",
+    "sets",
+    "snapshots",
+    "user_stats",
+    "used_nonces",
+    "config",
+    "banned",
+    "history",
+    "attempts",
+    "leaderboard",
+    "admins",
+    "owner_index",
+    "tag_index",
+    "word_index",
+    "events",
+    "events_by_quiz",
+    "idempotency_keys",
+    "ranked_ballots",
+    "text_responses",
+    "rate_log",
+    "payouts",
+    "webhook_queue",
+    "shards",
+    "shard_wasm",
+    "routes",
+    "template_id_counter",
+    "templates",
+    "comment_id_counter",
+    "comments",
+    "started_attempts",
+    "activity",
+    "recent_answers",
+    "last_answered",
+    "answered_order",
+    "receipts",
+    "final_results",
+    "badges",
+    "anonymous_ballots",
+];
+// Stable memory writes are refused past this, well short of the subnet's
+// limit, so a full canister answers StorageFull instead of trapping mid-write.
+// `Config::storage_budget_bytes` overrides it.
+const DEFAULT_STORAGE_BUDGET_BYTES: u64 = 64 * 1024 * 1024 * 1024;
+// room left for the write being let through: the memory manager grows a
+// region by whole 8 MiB buckets
+const STORAGE_HEADROOM_BYTES: u64 = 64 * 1024 * 1024;
+// bump together with a new step in `migrate`
+const SCHEMA_VERSION: u32 = 3;
+// list_quizzes clamps larger limits to this
+const MAX_PAGE_SIZE: u64 = 100;
+// leaderboard queries clamp larger top_n to this
+const MAX_LEADERBOARD_SIZE: u64 = 100;
+// counting the first
+const MAX_QUESTIONS: usize = 5;
+// in chars, after trimming
+const MAX_TEXT_RESPONSE_LEN: usize = 1000;
+// in chars, after trimming
+const MAX_COMMENT_LEN: usize = 500;
+// in chars, after trimming
+const MAX_CATEGORY_LEN: usize = 50;
 
+// Fields added after the first release are `Option`, so records stored
+// before they existed still decode.
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct Quiz {
     id: u64,
     question: String,
     options: Vec<String>,
-    answers: HashMap<String, u32>,
+    // A BTreeMap so encoding is the same every time, which certified and
+    // replicated responses rely on. Candid encodes maps as a vec of pairs
+    // either way, so records written back when this was a HashMap decode as is.
+    answers: BTreeMap<String, u32>,
     created_at: u64,
     updated_at: Option<u64>,
+    // None is false
+    lock_on_first_vote: Option<bool>,
+    locked: Option<bool>,
+    // None is Open
+    status: Option<QuizStatus>,
+    expires_at: Option<u64>,
+    // None is true
+    allow_vote_change: Option<bool>,
+    // None derives one from the id
+    display_seed: Option<u64>,
+    // None is false
+    multi_select: Option<bool>,
+    // None is no tags
+    tags: Option<Vec<String>>,
+    max_total_votes: Option<u64>,
+    // None means anyone may vote
+    allowed_voters: Option<Vec<Principal>>,
+    // the single answer key of older records; `migrate` folds it into
+    // `correct_options`, so it's always None after that
+    correct_option: Option<String>,
+    // the creator, who alone may change the quiz besides controllers; None
+    // for quizzes created before owners were recorded
+    owner: Option<Principal>,
+    // opaque JSON for frontends, e.g. a theme or an external id
+    metadata: Option<String>,
+    // goes up with every `start_new_round`; None is 0
+    round: Option<u32>,
+    // None is unfeatured
+    featured: Option<bool>,
+    // archived quizzes take no votes, aren't listed, and only their owner and
+    // admins can read them
+    archived: Option<bool>,
+    // option text -> "#RRGGBB", for charts
+    option_colors: Option<BTreeMap<String, String>>,
+    // (quiz id, option): only shown in a set once the voter picked that option
+    prerequisite: Option<(u64, String)>,
+    // None means no cap; new quizzes start at 1, so the creator opts in to
+    // heavier votes with `set_max_weight`
+    max_weight_per_vote: Option<u32>,
+    // None is a poll
+    kind: Option<QuizKind>,
+    // what graded quizzes are scored against; any kind but ranked and
+    // open-text may have one. Hidden from voters until the quiz closes
+    correct_options: Option<Vec<String>>,
+    // Questions after the first, which stays in `question`, `options` and
+    // `answers` so single-question records still decode. None for those.
+    extra_questions: Option<Vec<Question>>,
+    // anonymous callers may answer only if this and Config::allow_anonymous
+    // are both true; None is false
+    allow_anonymous: Option<bool>,
+    // who may see the tallies before the quiz closes; None is Always
+    results_visibility: Option<ResultsVisibility>,
+    // one per quiz, normalized like tags; None is uncategorized
+    category: Option<String>,
+    // how many options one multi-select ballot may pick; None is all of them
+    max_selections: Option<u32>,
+    // None is Public
+    visibility: Option<QuizVisibility>,
+    // lets callers outside `allowed_voters` into a private quiz; managers only
+    invite_code: Option<String>,
+    // when a scheduled draft opens by itself
+    opens_at: Option<u64>,
+    // the SCHEMA_VERSION the record was last written under; None is 0, i.e.
+    // written before versioning
+    schema_version: Option<u32>,
+    // `get_quiz_for_voting` gives every voter their own option order instead
+    // of the shared display order; None is false
+    shuffle_per_voter: Option<bool>,
+    token_gate: Option<TokenGate>,
+    // graded quizzes only; set with `set_prize`
+    prize: Option<Prize>,
+    // POSTed the results when the quiz closes or passes a VOTE_MILESTONES
+    // entry; managers only
+    webhook_url: Option<String>,
+    // one per entry of `options`, in the same order
+    option_details: Option<Vec<QuizOption>>,
+    // the id the next new option gets; ids are never reused
+    next_option_id: Option<u32>,
+    // graded quizzes only: how long after `start_attempt` a participant's
+    // answer is still taken
+    time_limit_secs: Option<u64>,
+    // what `question` and `options` are written in; None is DEFAULT_LOCALE
+    default_locale: Option<String>,
+    // locale -> the quiz in that language; votes still name the options in
+    // `options`, so every locale shares one set of tallies
+    translations: Option<BTreeMap<String, LocalizedContent>>,
 }
 
-impl Storable for Quiz {
-    fn to_bytes(&self) -> Cow<[u8]> {
+// One translation of a quiz. `options` is keyed by option id; options without
+// an entry show their default label.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct LocalizedContent {
+    question: String,
+    options: BTreeMap<u32, String>,
+}
+
+// what `get_quiz_localized` returns
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct LocalizedQuiz {
+    // the locale the text is in, which can differ from the one asked for
+    locale: String,
+    question: String,
+    // one per entry of `quiz.options`, in the same order; vote with those
+    options: Vec<String>,
+    quiz: Quiz,
+}
+
+// An option's stable id and what's shown alongside its label. The id stays
+// put when `edit_option` relabels the option, so clients can hold on to it.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct QuizOption {
+    id: u32,
+    label: String,
+    description: Option<String>,
+    image_url: Option<String>,
+}
+
+// one question of a multi-question quiz, with its own options and tallies
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Question {
+    text: String,
+    options: Vec<String>,
+    answers: BTreeMap<String, u32>,
+}
+
+impl Question {
+    fn new(text: String, options: Vec<String>) -> Self {
+        let answers = options.iter().cloned().map(|option| (option, 0)).collect();
+        Self {
+            text,
+            options,
+            answers,
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+enum QuizStatus {
+    #[default]
+    Open,
+    Closed,
+    // being prepared; takes no votes until `open_quiz`
+    Draft,
+}
+
+// voters need at least `min_balance` on `ledger`, an ICRC-1 ledger
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct TokenGate {
+    ledger: Principal,
+    min_balance: Nat,
+}
+
+// an ICRC-1 account
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+// Paid out of the quiz's own subaccount of this canister (see
+// `get_prize_account`), split evenly between the top `winners` scorers.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Prize {
+    ledger: Principal,
+    amount: Nat,
+    winners: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Payout {
+    amount: Nat,
+    status: PayoutStatus,
+    attempts: u32,
+    // sent as the transfer's `created_at_time`, so the ledger recognises a
+    // retry of a transfer that did go through as a duplicate
+    created_at_time: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum PayoutStatus {
+    Pending,
+    // a transfer call is out; skipped by other `distribute_rewards` calls
+    InFlight,
+    Paid { block_index: Nat },
+    // retried by the next `distribute_rewards`
+    Failed { reason: String },
+}
+
+impl Storable for Payout {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-impl BoundedStorable for Quiz {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PayoutRecord {
+    winner: Principal,
+    payout: Payout,
 }
 
-thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
+// One per participant and quiz. The token id is fixed by both, so a retried
+// mint can't produce a second token.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Badge {
+    collection: Principal,
+    token_id: Nat,
+    status: BadgeStatus,
+    attempts: u32,
+}
 
-    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
-            .expect("Cannot create a counter")
-    );
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum BadgeStatus {
+    // a mint call is out; `claim_badge` waits for it
+    Minting,
+    Minted { minted_at: u64 },
+    // retried by the next `claim_badge`
+    Failed { reason: String },
+}
 
-    static STORAGE: RefCell<StableBTreeMap<u64, Quiz, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
-    ));
+impl Storable for Badge {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-impl Quiz {
-    fn new(id: u64, question: String, options: Vec<String>) -> Self {
-        let answers = options.iter().cloned().map(|option| (option, 0)).collect();
-        Self {
-            id,
-            question,
-            options,
-            answers,
-            created_at: time(),
-            updated_at: None,
+// ICRC-7 leaves minting to each collection; this is the `icrc7_mint` the
+// badge collection is expected to expose
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct MintArg {
+    token_id: Nat,
+    owner: Account,
+    metadata: Vec<(String, MetadataValue)>,
+}
+
+// the part of ICRC-3's `Value` badges use
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum MetadataValue {
+    Nat(Nat),
+    Text(String),
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Debug)]
+enum MintError {
+    Unauthorized,
+    TokenIdAlreadyExists,
+    InvalidRecipient,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AggregatedResults {
+    quiz_id: u64,
+    // (option, votes) in the quiz's option order
+    results: Vec<(String, u64)>,
+    total_votes: u64,
+    shards_queried: u32,
+    failed_shards: Vec<Principal>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct WebhookDelivery {
+    quiz_id: u64,
+    url: String,
+    // JSON
+    body: String,
+    attempts: u32,
+    next_attempt_at: u64,
+}
+
+impl Storable for WebhookDelivery {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// what a voter brought along besides their ballot
+#[derive(Clone, Copy, Default)]
+struct Admission<'a> {
+    invite_code: Option<&'a str>,
+    // `answer_quiz` checked the voter's balance against the token gate
+    token_gate_passed: bool,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+enum QuizVisibility {
+    #[default]
+    Public,
+    // reachable by id, but left out of listings
+    Unlisted,
+    // unlisted, and only participants, holders of the invite code and the
+    // quiz's managers may see or answer it
+    Private,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+enum ResultsVisibility {
+    #[default]
+    Always,
+    // once the caller has voted
+    AfterVoting,
+    AfterClose,
+}
+
+impl Storable for Quiz {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// What STORAGE actually holds. A record that no longer decodes is kept as its
+// raw bytes instead of trapping, so one corrupt entry can't brick every read.
+// Records from older schema versions are migrated as they're read, and for
+// good by `migrate_all` in `post_upgrade`.
+#[derive(Clone)]
+enum StoredQuiz {
+    Quiz(Box<Quiz>),
+    Corrupt(Vec<u8>),
+}
+
+impl StoredQuiz {
+    fn into_quiz(self, id: u64) -> Option<Quiz> {
+        match self {
+            StoredQuiz::Quiz(quiz) => Some(migrate(*quiz)),
+            StoredQuiz::Corrupt(bytes) => {
+                ic_cdk::println!(
+                    "skipping corrupt quiz record id={} ({} bytes)",
+                    id,
+                    bytes.len()
+                );
+                None
+            }
         }
     }
 }
 
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct QuizPayload {
-    question: String,
-    options: Vec<String>,
+impl Storable for StoredQuiz {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        match self {
+            StoredQuiz::Quiz(quiz) => quiz.to_bytes(),
+            StoredQuiz::Corrupt(bytes) => Cow::Borrowed(bytes),
+        }
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match Decode!(bytes.as_ref(), Quiz) {
+            Ok(quiz) => StoredQuiz::Quiz(Box::new(quiz)),
+            Err(_) => StoredQuiz::Corrupt(bytes.into_owned()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::query]
-fn get_all_quiz() -> Result<Vec<Quiz>, Error> {
-    let quizzes: Vec<Quiz> = STORAGE.with(|service| service.borrow().values().cloned().collect());
+// Brings a quiz written under an older schema up to SCHEMA_VERSION, one
+// version at a time. Added fields are Options, so those decode without help;
+// a step here is for changes that need existing data rewritten.
+fn migrate(mut quiz: Quiz) -> Quiz {
+    let mut version = quiz.schema_version.unwrap_or(0);
+    while version < SCHEMA_VERSION {
+        match version {
+            // unversioned records already have the version 1 shape
+            0 => {}
+            // options get ids in their current order
+            1 => quiz.sync_option_details(),
+            // one answer key: `correct_option` joins `correct_options`
+            2 => {
+                if let Some(correct) = quiz.correct_option.take() {
+                    let key = quiz.correct_options.get_or_insert_with(Vec::new);
+                    if !key.contains(&correct) {
+                        key.push(correct);
+                    }
+                }
+            }
+            _ => unreachable!("no migration from schema version {}", version),
+        }
+        version += 1;
+    }
+    quiz.schema_version = Some(SCHEMA_VERSION);
+    quiz
+}
 
-    if !quizzes.is_empty() {
-        Ok(quizzes)
-    } else {
-        Err(Error::NotFound {
-            msg: "There are currently no quizzes".to_string(),
-        })
+// Rewrites every outdated record in place. Indexes and certified results
+// don't depend on the version, so they're left alone.
+fn migrate_all() {
+    let outdated: Vec<u64> = STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, stored)| match stored {
+                StoredQuiz::Quiz(quiz) => quiz.schema_version.unwrap_or(0) < SCHEMA_VERSION,
+                StoredQuiz::Corrupt(_) => false,
+            })
+            .map(|(id, _)| id)
+            .collect()
+    });
+    for id in outdated {
+        if let Some(quiz) = _get_quiz(&id) {
+            STORAGE.with(|storage| {
+                storage
+                    .borrow_mut()
+                    .insert(id, StoredQuiz::Quiz(Box::new(quiz)))
+            });
+        }
     }
+    write_config(|config| config.schema_version = Some(SCHEMA_VERSION));
 }
 
+// the schema version every stored quiz has been migrated to
 #[ic_cdk::query]
-fn get_quiz(id: u64) -> Result<Quiz, Error> {
-    _get_quiz(&id).ok_or_else(|| Error::NotFound {
-        msg: format!("Quiz with id={} not found", id),
-    })
+fn get_schema_version() -> u32 {
+    get_config().schema_version.unwrap_or(0)
 }
 
-fn _get_quiz(id: &u64) -> Option<Quiz> {
-    STORAGE.with(|s| s.borrow().get(id))
+// canister-wide settings managed by the controllers
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Config {
+    // 0 means unlimited
+    max_quizzes: u64,
+    // applied to new quizzes whose payload doesn't set `expires_at`
+    default_expiry_ns: Option<u64>,
+    // canister whose `on_quiz_closed` is notified when a quiz closes
+    close_notification_target: Option<Principal>,
+    // None keeps the original behaviour, `Unicode`
+    normalization_mode: Option<NormalizationMode>,
+    // None falls back to MIN_OPTION_LEN
+    min_option_len: Option<u64>,
+    // None falls back to DEFAULT_RESULT_PRECISION
+    result_precision: Option<u8>,
+    // None falls back to DEFAULT_MAX_QUESTION_LEN
+    max_question_len: Option<u64>,
+    // None falls back to DEFAULT_MAX_OPTION_LEN
+    max_option_len: Option<u64>,
+    // lets anonymous callers create quizzes, and answer the ones that allow
+    // it; None is false
+    allow_anonymous: Option<bool>,
+    // set by `post_upgrade`; None until the first upgrade
+    last_upgraded_at: Option<u64>,
+    // None is no limits at all
+    rate_limits: Option<RateLimits>,
+    // set by `init` and `migrate_all`; None is 0
+    schema_version: Option<u32>,
+    // 32 bytes from `raw_rand`, fetched once; keys every voter's option order
+    shuffle_salt: Option<Vec<u8>>,
+    // the first quiz id this canister hands out; None is 0
+    id_base: Option<u64>,
+    // stable memory use past which a shard is spawned; None never spawns one
+    shard_threshold_bytes: Option<u64>,
+    // None is unlimited
+    max_attempts_per_quiz: Option<u64>,
+    // None falls back to DEFAULT_STORAGE_BUDGET_BYTES
+    storage_budget_bytes: Option<u64>,
+    // ICRC-7 collection that mints completion badges; None mints none
+    badge_collection: Option<Principal>,
+    // a graded quiz's score that earns its badge; None is 1, a correct answer
+    badge_min_score: Option<u64>,
 }
 
-#[ic_cdk::query]
-fn get_quiz_count() -> usize {
-    STORAGE.with(|service| service.borrow().len())
+// Per-principal quotas over a rolling hour; None leaves that action
+// unlimited. Admins aren't limited.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default)]
+struct RateLimits {
+    quizzes_per_hour: Option<u64>,
+    answers_per_hour: Option<u64>,
+    comments_per_hour: Option<u64>,
 }
 
-#[ic_cdk::update]
-fn create_quiz(payload: QuizPayload) -> Option<Quiz> {
-    let id = ID_COUNTER.with(|counter| {
-        let current_value = *counter.borrow().get();
-        counter
-            .borrow_mut()
-            .set(current_value + 1)
-            .unwrap_or_else(|_| panic!("Cannot increment id counter"))
-    });
+#[derive(Clone, Copy)]
+enum RateAction {
+    CreateQuiz,
+    Answer,
+    Comment,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+enum QuizKind {
+    // votes are only counted
+    #[default]
+    Poll,
+    // answers are checked against the quiz's correct options and scored
+    Graded,
+    // voters rank every option; the winner comes from instant runoff
+    Ranked,
+    // no options: voters write a response, which the owner moderates
+    OpenText,
+}
 
-    let mut answers = HashMap::new();
+// How option texts are case-folded before they're compared.
+//
+// `Unicode` uses full Unicode lowercasing, so "ÉTÉ" and "été" match, but it
+// applies the same rules to every language: Turkish "I" becomes "i" rather
+// than dotless "ı". `Ascii` only folds A-Z, which never mis-folds but leaves
+// non-ASCII letters case-sensitive.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+enum NormalizationMode {
+    #[default]
+    Unicode,
+    Ascii,
+}
 
-    for option in &payload.options {
-        answers.insert(String::from(option), 0);
+impl Storable for Config {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
     }
 
-    let quiz = Quiz::new(id, payload.question, payload.options);
-    do_insert(&quiz);
-    Some(quiz)
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-fn do_insert(quiz: &Quiz) {
-    STORAGE.with(|service| service.borrow_mut().insert(quiz.id, quiz.clone()));
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct StorablePrincipal(Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(Principal::from_slice(bytes.as_ref()))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 29,
+        is_fixed_size: false,
+    };
 }
 
-#[ic_cdk::update]
-fn update_quiz(id: u64, payload: QuizPayload) -> Result<Quiz, Error> {
-    let quiz_option: Option<Quiz> = STORAGE.with(|service| service.borrow().get(&id));
+// the empty principal, which sorts before every other one; tuple keys need it
+impl Default for StorablePrincipal {
+    fn default() -> Self {
+        Self(Principal::from_slice(&[]))
+    }
+}
 
-    match quiz_option {
-        Some(mut quiz) => {
-            let mut answers = HashMap::new();
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct UserStats {
+    quizzes_answered: u64,
+    last_answered_at: Option<u64>,
+    // correct answers to graded quizzes; opt so older records still decode
+    score: Option<u64>,
+}
 
-            for option in &payload.options {
-                answers.insert(String::from(option), 0);
-            }
+impl Storable for UserStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-            quiz.question = payload.question;
-            quiz.options = payload.options;
-            quiz.answers = answers;
-            quiz.updated_at = Some(time());
-            do_insert(&quiz);
-            Ok(quiz)
-        }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't update a quiz with id={}. quiz not found", id),
-        }),
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
     }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[ic_cdk::update]
-fn delete_quiz(id: u64) -> Result<Quiz, Error> {
-    match STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(quiz) => Ok(quiz),
-        None => Err(Error::NotFound {
-            msg: format!("couldn't delete a quiz with id={}. quiz not found.", id),
-        }),
+// a single entry of the vote log, keyed by (quiz id, sequence number)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Vote {
+    voter: Principal,
+    option: String,
+    weight: u32,
+    timestamp: u64,
+    // index into the quiz's questions; None is the first, as for every vote
+    // logged before quizzes had more than one
+    question: Option<u32>,
+}
+
+impl Vote {
+    fn question_index(&self) -> u32 {
+        self.question.unwrap_or(0)
     }
 }
 
-#[ic_cdk::update]
-fn answer_quiz(id: u64, option: String) -> Result<Quiz, Error> {
-    let quiz_option: Option<Quiz> = STORAGE.with(|service| service.borrow().get(&id));
+impl Storable for Vote {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 
-    match quiz_option {
-        Some(mut quiz) => {
-            if quiz.options.contains(&option) {
-                if let Some(answer_count) = quiz.answers.get_mut(&option) {
-                    *answer_count += 1;
-                }
-                quiz.updated_at = Some(time());
-                do_insert(&quiz);
-                Ok(quiz)
-            } else {
-                Err(Error::NotFound {
-                    msg: format!("The option '{}' is not found for this quiz.", option),
-                })
-            }
-        }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't cast a quiz with id={}. quiz not found", id),
-        }),
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
     }
+
+    const BOUND: Bound = Bound::Unbounded;
 }
 
-#[derive(candid::CandidType, Deserialize, Serialize)]
-enum Error {
-    NotFound { msg: String },
+// One answer as it was cast, keyed by (quiz id, sequence number). Unlike the
+// vote log, attempts are never replaced or taken back, so a changed vote
+// leaves both answers here.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Attempt {
+    quiz_id: u64,
+    principal: Principal,
+    option: String,
+    timestamp: u64,
+    // None is the first question, as on `Vote`
+    question: Option<u32>,
 }
 
-ic_cdk::export_candid!();
+impl Storable for Attempt {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// one edit in a quiz's change journal, keyed by (quiz id, sequence number)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ChangeEntry {
+    timestamp: u64,
+    caller: Principal,
+    description: String,
+}
+
+impl Storable for ChangeEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// One entry of the audit log, keyed by a global sequence number. Unlike the
+// change journal it's never trimmed, and outlives the quiz it's about.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct QuizEvent {
+    kind: QuizEventKind,
+    quiz_id: u64,
+    // anonymous for nonce ballots, which don't record who cast them
+    actor: Principal,
+    timestamp: u64,
+    details: String,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+enum QuizEventKind {
+    Created,
+    // anything that goes into the change journal
+    Updated,
+    Deleted,
+    Answered,
+    Retracted,
+    // a prize transfer went through, or failed
+    RewardPaid,
+    RewardFailed,
+    BadgeMinted,
+}
+
+impl Storable for QuizEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct TextResponse {
+    text: String,
+    status: ResponseStatus,
+    submitted_at: u64,
+    moderated_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+enum ResponseStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl Storable for TextResponse {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// every option of a ranked quiz, most preferred first
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct RankedBallot {
+    ranking: Vec<String>,
+    timestamp: u64,
+}
+
+impl Storable for RankedBallot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// frozen results of a quiz, keyed by (quiz id, snapshot index)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct QuizSnapshot {
+    quiz_id: u64,
+    question: String,
+    options_with_counts: Vec<(String, u32)>,
+    total_votes: u64,
+    closed_at: u64,
+}
+
+impl Storable for QuizSnapshot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// What a quiz's first question stood at when it closed, keyed by (quiz id,
+// close index). A reopened quiz closing again appends a new one.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ResultSnapshot {
+    quiz_id: u64,
+    round: u32,
+    // (option, count) in option order
+    final_tallies: Vec<(String, u32)>,
+    total_votes: u64,
+    closed_at: u64,
+    // None without votes or when first place is tied
+    winner: Option<String>,
+}
+
+impl Storable for ResultSnapshot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// an ordered group of quizzes answered together, e.g. one per lesson
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct QuizSet {
+    id: u64,
+    name: String,
+    quiz_ids: Vec<u64>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+impl Storable for QuizSet {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// a question skeleton quizzes are created from; `{name}` in the question or
+// an option is a placeholder filled in by `create_quiz_from_template`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct QuizTemplate {
+    id: u64,
+    name: String,
+    question: String,
+    options: Vec<String>,
+    kind: Option<QuizKind>,
+    created_at: u64,
+}
+
+// one post in a quiz's discussion thread
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Comment {
+    id: u64,
+    quiz_id: u64,
+    author: Principal,
+    text: String,
+    created_at: u64,
+}
+
+impl Storable for Comment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for QuizTemplate {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // quiz id -> results hash; heap only, so `post_upgrade` rebuilds it
+    static CERTIFIED_RESULTS: RefCell<RbTree<Vec<u8>, Hash>> = const { RefCell::new(RbTree::new()) };
+
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+
+    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
+            .expect("Cannot create a counter")
+    );
+
+    static STORAGE: RefCell<StableBTreeMap<u64, StoredQuiz, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+    ));
+
+    static VOTE_LOG: RefCell<StableBTreeMap<(u64, u64), Vote, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+    ));
+
+    static SET_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))), 0)
+            .expect("Cannot create a set counter")
+    );
+
+    static SETS: RefCell<StableBTreeMap<u64, QuizSet, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+    ));
+
+    static SNAPSHOTS: RefCell<StableBTreeMap<(u64, u64), QuizSnapshot, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+
+    static USER_STATS: RefCell<StableBTreeMap<StorablePrincipal, UserStats, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+    ));
+
+    static USED_NONCES: RefCell<StableBTreeMap<(u64, Blob<MAX_NONCE_LEN>), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+    ));
+
+    static CONFIG: RefCell<ConfigCell> = RefCell::new(
+        ConfigCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))), Config::default())
+            .expect("Cannot create the config cell")
+    );
+
+    static BANNED: RefCell<StableBTreeMap<StorablePrincipal, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+    ));
+
+    static HISTORY: RefCell<StableBTreeMap<(u64, u64), ChangeEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+    ));
+
+    static ATTEMPTS: RefCell<StableBTreeMap<(u64, u64), Attempt, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+    ));
+
+    // (graded quiz id, principal) -> that principal's score on the quiz
+    static LEADERBOARD: RefCell<StableBTreeMap<(u64, StorablePrincipal), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+    ));
+
+    static ADMINS: RefCell<StableBTreeMap<StorablePrincipal, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+    ));
+
+    // (owner, quiz id), kept in step with STORAGE by `do_insert` and `unindex`
+    static OWNER_INDEX: RefCell<StableBTreeMap<(StorablePrincipal, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+    ));
+
+    // (sha256 of the tag, quiz id); tags are hashed since they have no length cap
+    static TAG_INDEX: RefCell<StableBTreeMap<(Blob<32>, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+    ));
+
+    // (sha256 of a word, quiz id) for every word of the quiz's questions and
+    // options; see `Quiz::words`
+    static WORD_INDEX: RefCell<StableBTreeMap<(Blob<32>, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+    ));
+
+    static EVENTS: RefCell<StableBTreeMap<u64, QuizEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+    ));
+
+    // (quiz id, event sequence number), for `get_events`
+    static EVENTS_BY_QUIZ: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+    ));
+
+    // (caller, idempotency key) -> (quiz id, when the key was first seen);
+    // keys are per caller so nobody can look up someone else's quiz with one
+    static IDEMPOTENCY_KEYS: RefCell<StableBTreeMap<IdempotencyKey, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+    ));
+
+    // (quiz id, voter) -> the voter's current ranking on a ranked quiz
+    static RANKED_BALLOTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), RankedBallot, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+    ));
+
+    // (quiz id, author) -> the author's response to an open-text quiz
+    static TEXT_RESPONSES: RefCell<StableBTreeMap<(u64, StorablePrincipal), TextResponse, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+    ));
+
+    // how many times each caller took a rate-limited action at each instant;
+    // entries older than an hour are pruned
+    static RATE_LOG: RefCell<StableBTreeMap<RateKey, u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+    ));
+
+    // (quiz id, winner) -> their share of the quiz's prize
+    static PAYOUTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), Payout, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+    ));
+
+    // webhook calls still to be made, oldest first
+    static WEBHOOK_QUEUE: RefCell<StableBTreeMap<u64, WebhookDelivery, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+    ));
+
+    // peer quiz canisters holding shards of the votes; see `get_aggregated_results`
+    static SHARDS: RefCell<StableBTreeMap<StorablePrincipal, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+    ));
+
+    // this canister's own wasm, uploaded in chunks, for installing on shards
+    static SHARD_WASM: RefCell<StableBTreeMap<u32, Vec<u8>, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+    ));
+
+    // first quiz id -> the shard this canister spawned for ids from there on
+    static ROUTES: RefCell<StableBTreeMap<u64, StorablePrincipal, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+    ));
+
+    static TEMPLATE_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))), 0)
+            .expect("Cannot create a template counter")
+    );
+
+    static TEMPLATES: RefCell<StableBTreeMap<u64, QuizTemplate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+    ));
+
+    static COMMENT_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))), 0)
+            .expect("Cannot create a comment counter")
+    );
+
+    // (quiz id, comment id) -> comment, so a quiz's thread reads oldest first
+    static COMMENTS: RefCell<StableBTreeMap<(u64, u64), Comment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+    ));
+
+    // (quiz id, participant) -> when they called `start_attempt`
+    static STARTED_ATTEMPTS: RefCell<StableBTreeMap<(u64, StorablePrincipal), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+    ));
+
+    // (quiz id, hour since the epoch) -> answers taken that hour; buckets past
+    // TRENDING_WINDOW_HOURS are dropped by `decay_activity`
+    static ACTIVITY: RefCell<StableBTreeMap<(u64, u64), u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+    ));
+
+    // quiz id -> the sum of its ACTIVITY buckets, for quizzes that have any
+    static RECENT_ANSWERS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+    ));
+
+    // quiz id -> when it was last answered
+    static LAST_ANSWERED: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+    ));
+
+    // (u64::MAX - LAST_ANSWERED time, quiz id), so the newest come first
+    static ANSWERED_ORDER: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)))
+    ));
+
+    // receipt hash -> the ATTEMPTS key of the answer it was issued for
+    static RECEIPTS: RefCell<StableBTreeMap<Blob<32>, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)))
+    ));
+
+    // written once per close and never updated, unlike SNAPSHOTS
+    static FINAL_RESULTS: RefCell<StableBTreeMap<(u64, u64), ResultSnapshot, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)))
+    ));
+
+    // (quiz id, participant) -> the badge minted, or being minted, for it
+    static BADGES: RefCell<StableBTreeMap<(u64, StorablePrincipal), Badge, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39)))
+    ));
+
+    // (quiz id, ballot_digest) for each `answer_quiz_nonce` ballot; the digest
+    // is salted, so the entries can't be matched back to principals
+    static ANONYMOUS_BALLOTS: RefCell<StableBTreeMap<(u64, Blob<32>), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40)))
+    ));
+
+    // keeps a second spawn from starting while one is waiting on the
+    // management canister
+    static SPAWNING_SHARD: RefCell<bool> = const { RefCell::new(false) };
+
+    // stops a second delivery run from starting while one is waiting on an outcall
+    static DELIVERING_WEBHOOKS: RefCell<bool> = const { RefCell::new(false) };
+}
+
+impl Quiz {
+    fn new(id: u64, question: String, options: Vec<String>) -> Self {
+        let answers = options.iter().cloned().map(|option| (option, 0)).collect();
+        let mut quiz = Self {
+            id,
+            question,
+            options,
+            answers,
+            created_at: time(),
+            updated_at: None,
+            lock_on_first_vote: None,
+            locked: None,
+            status: None,
+            expires_at: None,
+            allow_vote_change: None,
+            display_seed: Some(splitmix64(time() ^ id)),
+            multi_select: None,
+            tags: None,
+            max_total_votes: None,
+            allowed_voters: None,
+            correct_option: None,
+            owner: None,
+            metadata: None,
+            round: None,
+            featured: None,
+            archived: None,
+            option_colors: None,
+            prerequisite: None,
+            max_weight_per_vote: Some(1),
+            kind: None,
+            correct_options: None,
+            extra_questions: None,
+            allow_anonymous: None,
+            results_visibility: None,
+            category: None,
+            max_selections: None,
+            visibility: None,
+            invite_code: None,
+            opens_at: None,
+            schema_version: Some(SCHEMA_VERSION),
+            shuffle_per_voter: None,
+            token_gate: None,
+            prize: None,
+            webhook_url: None,
+            option_details: None,
+            next_option_id: None,
+            time_limit_secs: None,
+            default_locale: None,
+            translations: None,
+        };
+        quiz.sync_option_details();
+        quiz
+    }
+
+    // a fresh, vote-less quiz with this one's question and voting settings,
+    // created by the caller
+    fn fork(&self, id: u64, options: Vec<String>) -> Self {
+        let mut forked = Quiz::new(id, self.question.clone(), options);
+        forked.lock_on_first_vote = self.lock_on_first_vote;
+        forked.expires_at = self.expires_at;
+        forked.allow_vote_change = self.allow_vote_change;
+        forked.multi_select = self.multi_select;
+        forked.max_selections = self.max_selections;
+        forked.visibility = self.visibility;
+        forked.shuffle_per_voter = self.shuffle_per_voter;
+        forked.token_gate = self.token_gate.clone();
+        forked.time_limit_secs = self.time_limit_secs;
+        forked.default_locale = self.default_locale.clone();
+        forked.translations = self.translations.clone();
+        forked.tags = self.tags.clone();
+        forked.category = self.category.clone();
+        forked.allowed_voters = self.allowed_voters.clone();
+        forked.allow_anonymous = self.allow_anonymous;
+        forked.results_visibility = self.results_visibility;
+        forked.metadata = self.metadata.clone();
+        forked.owner = Some(caller());
+        forked.option_colors = self.option_colors.clone();
+        forked.retain_option_colors();
+        forked.kind = self.kind;
+        forked.correct_options = self.correct_options.clone();
+        forked.retain_correct_options();
+        forked.option_details = self.option_details.clone();
+        forked.next_option_id = self.next_option_id;
+        forked.sync_option_details();
+        forked
+    }
+
+    // what `search_quizzes` matches on
+    fn words(&self) -> BTreeSet<String> {
+        self.questions()
+            .iter()
+            .flat_map(|question| std::iter::once(&question.text).chain(question.options.iter()))
+            .flat_map(|text| words_of(text))
+            .collect()
+    }
+
+    // every question in order; the first is always there
+    fn questions(&self) -> Vec<Question> {
+        let mut questions = vec![Question {
+            text: self.question.clone(),
+            options: self.options.clone(),
+            answers: self.answers.clone(),
+        }];
+        questions.extend(self.extra_questions.iter().flatten().cloned());
+        questions
+    }
+
+    fn question_options(&self, index: u32) -> Option<&[String]> {
+        match index {
+            0 => Some(&self.options),
+            _ => self
+                .extra_questions
+                .as_ref()?
+                .get(index as usize - 1)
+                .map(|question| question.options.as_slice()),
+        }
+    }
+
+    fn tallies(&self, index: u32) -> Option<&BTreeMap<String, u32>> {
+        match index {
+            0 => Some(&self.answers),
+            _ => self
+                .extra_questions
+                .as_ref()?
+                .get(index as usize - 1)
+                .map(|question| &question.answers),
+        }
+    }
+
+    fn tallies_mut(&mut self, index: u32) -> Option<&mut BTreeMap<String, u32>> {
+        match index {
+            0 => Some(&mut self.answers),
+            _ => self
+                .extra_questions
+                .as_mut()?
+                .get_mut(index as usize - 1)
+                .map(|question| &mut question.answers),
+        }
+    }
+
+    // every question back to a zero count for each of its options
+    fn reset_tallies(&mut self) {
+        self.answers = self
+            .options
+            .iter()
+            .cloned()
+            .map(|option| (option, 0))
+            .collect();
+        for question in self.extra_questions.iter_mut().flatten() {
+            *question = Question::new(question.text.clone(), question.options.clone());
+        }
+    }
+
+    // drops colors of options the quiz no longer has
+    fn retain_option_colors(&mut self) {
+        if let Some(colors) = self.option_colors.as_mut() {
+            colors.retain(|option, _| self.options.contains(option));
+        }
+    }
+
+    // Keeps `option_details` in step with `options`: details follow their
+    // label, and a label the quiz didn't have before gets the next unused id.
+    fn sync_option_details(&mut self) {
+        let mut next_id = self.next_option_id.unwrap_or(0);
+        let mut previous = self.option_details.take().unwrap_or_default();
+        let details = self
+            .options
+            .iter()
+            .map(
+                |label| match previous.iter().position(|detail| detail.label == *label) {
+                    Some(i) => previous.swap_remove(i),
+                    None => {
+                        next_id += 1;
+                        QuizOption {
+                            id: next_id - 1,
+                            label: label.clone(),
+                            ..Default::default()
+                        }
+                    }
+                },
+            )
+            .collect();
+        self.option_details = Some(details);
+        self.next_option_id = Some(next_id);
+        let ids: Vec<u32> = self
+            .option_details
+            .iter()
+            .flatten()
+            .map(|detail| detail.id)
+            .collect();
+        for content in self.translations.iter_mut().flat_map(|t| t.values_mut()) {
+            content.options.retain(|id, _| ids.contains(id));
+        }
+    }
+
+    // a translation into the new default locale would only shadow the quiz's
+    // own text, so it's dropped
+    fn set_default_locale(&mut self, locale: String) {
+        if let Some(translations) = self.translations.as_mut() {
+            translations.remove(&locale);
+        }
+        self.default_locale = Some(locale);
+    }
+
+    fn locale(&self) -> String {
+        self.default_locale
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+    }
+
+    // `locale` itself, then its bare language ("pt" for "pt-br"), then any
+    // regional variant of that language; None falls back to the default
+    fn best_translation(&self, locale: &str) -> Option<(&String, &LocalizedContent)> {
+        let translations = self.translations.as_ref()?;
+        let language = locale.split('-').next().unwrap_or(locale);
+        translations
+            .get_key_value(locale)
+            .or_else(|| translations.get_key_value(language))
+            .or_else(|| {
+                translations
+                    .iter()
+                    .find(|(key, _)| key.split('-').next() == Some(language))
+            })
+    }
+
+    // see `VoteReceipt::option_id`
+    fn option_id_of(&self, question: u32, option: &str) -> Option<u32> {
+        if question == 0 {
+            return self
+                .option_details
+                .iter()
+                .flatten()
+                .find(|detail| detail.label == option)
+                .map(|detail| detail.id);
+        }
+        self.question_options(question)?
+            .iter()
+            .position(|candidate| candidate == option)
+            .map(|i| i as u32)
+    }
+
+    fn option_label(&self, option_id: u32) -> Option<String> {
+        self.option_details
+            .iter()
+            .flatten()
+            .find(|detail| detail.id == option_id)
+            .map(|detail| detail.label.clone())
+    }
+
+    // drops correct answers the quiz no longer has as options
+    fn retain_correct_options(&mut self) {
+        if let Some(correct) = self.correct_options.as_mut() {
+            correct.retain(|option| self.options.contains(option));
+        }
+    }
+
+    fn is_open_text(&self) -> bool {
+        self.kind == Some(QuizKind::OpenText)
+    }
+
+    fn is_ranked(&self) -> bool {
+        self.kind == Some(QuizKind::Ranked)
+    }
+
+    fn is_graded(&self) -> bool {
+        self.kind == Some(QuizKind::Graded)
+    }
+
+    // what a caller may see: a graded quiz's answer key stays with its
+    // managers until the quiz closes, and so do the tallies if
+    // `results_visibility` says so
+    fn redacted(mut self) -> Self {
+        if ensure_can_manage(&self).is_ok() {
+            return self;
+        }
+        self.invite_code = None;
+        self.webhook_url = None;
+        if self.status() == QuizStatus::Closed {
+            return self;
+        }
+        self.correct_options = None;
+        if !self.results_visible_to(&caller()) {
+            self.answers.clear();
+            for question in self.extra_questions.iter_mut().flatten() {
+                question.answers.clear();
+            }
+        }
+        self
+    }
+
+    fn results_visible_to(&self, principal: &Principal) -> bool {
+        match self.results_visibility.unwrap_or_default() {
+            ResultsVisibility::Always => true,
+            ResultsVisibility::AfterVoting => {
+                *principal != Principal::anonymous() && has_voted(self.id, principal)
+            }
+            ResultsVisibility::AfterClose => self.status() == QuizStatus::Closed,
+        }
+    }
+
+    // options in the per-quiz shuffled order every viewer sees
+    fn display_options(&self) -> Vec<String> {
+        shuffled(&self.options, self.display_seed())
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn is_allowed_voter(&self, principal: &Principal) -> bool {
+        self.allowed_voters
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(principal))
+    }
+
+    fn accepts_votes(&self, now: u64) -> bool {
+        self.status() == QuizStatus::Open && !self.is_archived() && !self.is_expired(now)
+    }
+
+    fn is_listed(&self) -> bool {
+        self.visibility.unwrap_or_default() == QuizVisibility::Public
+            && !self.is_scheduled()
+            && !self.is_archived()
+    }
+
+    fn is_private(&self) -> bool {
+        self.visibility == Some(QuizVisibility::Private)
+    }
+
+    // a draft waiting on its `opens_at` timer
+    fn is_scheduled(&self) -> bool {
+        self.status() == QuizStatus::Draft && self.opens_at.is_some()
+    }
+
+    fn set_max_selections(&mut self, max_selections: u32) {
+        self.max_selections = Some(max_selections);
+        self.multi_select = Some(max_selections > 1);
+    }
+
+    fn round(&self) -> u32 {
+        self.round.unwrap_or(0)
+    }
+
+    fn status(&self) -> QuizStatus {
+        self.status.unwrap_or_default()
+    }
+
+    fn allows_vote_change(&self) -> bool {
+        self.allow_vote_change != Some(false)
+    }
+
+    fn display_seed(&self) -> u64 {
+        self.display_seed.unwrap_or_else(|| splitmix64(self.id))
+    }
+
+    fn is_multi_select(&self) -> bool {
+        self.multi_select == Some(true)
+    }
+
+    fn tags(&self) -> &[String] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    fn is_archived(&self) -> bool {
+        self.archived == Some(true)
+    }
+
+    fn admits_anonymous(&self) -> bool {
+        self.allow_anonymous == Some(true) && anonymous_allowed()
+    }
+
+    // only drafts get an `opens_at`; anything else is opened by hand
+    fn ensure_schedulable(&self) -> Result<(), Error> {
+        if self.status() != QuizStatus::Draft {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "quiz with id={} is already open; only drafts can be scheduled",
+                    self.id
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    fn ensure_editable(&self) -> Result<(), Error> {
+        if self.locked == Some(true) {
+            return Err(Error::Locked {
+                msg: format!("quiz with id={} is locked after its first vote", self.id),
+            });
+        }
+        Ok(())
+    }
+
+    // answers paired up in the order of `options`, which is the display order
+    fn ordered_answers(&self) -> Vec<(String, u32)> {
+        self.options
+            .iter()
+            .map(|option| (option.clone(), *self.answers.get(option).unwrap_or(&0)))
+            .collect()
+    }
+
+    fn resolve_option(&self, selection: &str) -> Option<String> {
+        resolve_in(&self.options, selection)
+    }
+
+    fn total_votes(&self) -> u64 {
+        self.answers.values().map(|count| *count as u64).sum()
+    }
+
+    // share of votes on correct options; None without an answer key or
+    // without votes
+    fn accuracy(&self) -> Option<f64> {
+        let correct = self
+            .correct_options
+            .as_ref()
+            .filter(|key| !key.is_empty())?;
+        let total = self.total_votes();
+        if total == 0 {
+            return None;
+        }
+        let right: u64 = correct
+            .iter()
+            .map(|option| *self.answers.get(option).unwrap_or(&0) as u64)
+            .sum();
+        Some(right as f64 / total as f64)
+    }
+
+    // 1.0 when the top two options are tied, 0.0 when one option has every
+    // vote; None until somebody votes
+    fn closeness(&self) -> Option<f64> {
+        let total = self.total_votes();
+        if total == 0 {
+            return None;
+        }
+        let mut counts: Vec<u32> = self.answers.values().copied().collect();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let top = counts.first().copied().unwrap_or(0);
+        let runner_up = counts.get(1).copied().unwrap_or(0);
+        Some(1.0 - (top - runner_up) as f64 / total as f64)
+    }
+}
+
+// the stored option a voter's text refers to, exact matches first
+fn resolve_in(options: &[String], selection: &str) -> Option<String> {
+    if options.iter().any(|option| option == selection) {
+        return Some(selection.to_string());
+    }
+    let normalized = normalize_option(selection);
+    options
+        .iter()
+        .find(|option| normalize_option(option) == normalized)
+        .cloned()
+}
+
+// Fisher-Yates driven by splitmix64, so the same seed gives the same order
+fn shuffled(options: &[String], seed: u64) -> Vec<String> {
+    let mut options = options.to_vec();
+    let mut state = seed;
+    for i in (1..options.len()).rev() {
+        state = splitmix64(state);
+        options.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    options
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct QuizPayload {
+    question: String,
+    options: Vec<String>,
+    lock_on_first_vote: Option<bool>,
+    expires_at: Option<u64>,
+    allow_vote_change: Option<bool>,
+    max_total_votes: Option<u64>,
+    allowed_voters: Option<Vec<Principal>>,
+    metadata: Option<String>,
+    kind: Option<QuizKind>,
+    // indices into `options`; required for graded quizzes
+    correct_options: Option<Vec<u32>>,
+    // questions after the first; None on update keeps the current ones
+    questions: Option<Vec<QuestionPayload>>,
+    // creates the quiz as a draft; ignored on update
+    draft: Option<bool>,
+    allow_anonymous: Option<bool>,
+    results_visibility: Option<ResultsVisibility>,
+    // lowercased, trimmed and deduplicated; None on update keeps the current ones
+    tags: Option<Vec<String>>,
+    category: Option<String>,
+    // more than 1 makes the quiz multi-select, 1 makes it single-select
+    max_selections: Option<u32>,
+    visibility: Option<QuizVisibility>,
+    // creates the quiz as a draft that opens itself at this time
+    opens_at: Option<u64>,
+    shuffle_per_voter: Option<bool>,
+    token_gate: Option<TokenGate>,
+    // graded quizzes only
+    time_limit_secs: Option<u64>,
+    default_locale: Option<String>,
+    // a retried `create_quiz` with the same key returns the quiz the first
+    // call created; ignored on update
+    idempotency_key: Option<String>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct QuestionPayload {
+    question: String,
+    options: Vec<String>,
+}
+
+// for `patch_quiz`; every field left None stays as it is
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct UpdateQuizPayload {
+    question: Option<String>,
+    options: Option<Vec<String>>,
+    lock_on_first_vote: Option<bool>,
+    expires_at: Option<u64>,
+    allow_vote_change: Option<bool>,
+    max_total_votes: Option<u64>,
+    metadata: Option<String>,
+    // indices into the options the quiz ends up with
+    correct_options: Option<Vec<u32>>,
+    allow_anonymous: Option<bool>,
+    results_visibility: Option<ResultsVisibility>,
+    // lowercased, trimmed and deduplicated; None on update keeps the current ones
+    tags: Option<Vec<String>>,
+    category: Option<String>,
+    max_selections: Option<u32>,
+    visibility: Option<QuizVisibility>,
+    // only for drafts
+    opens_at: Option<u64>,
+    shuffle_per_voter: Option<bool>,
+    token_gate: Option<TokenGate>,
+    time_limit_secs: Option<u64>,
+    default_locale: Option<String>,
+}
+
+// passed to `init` and `post_upgrade`
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct InitArgs {
+    // added to the current admins; upgrades never remove any
+    admins: Vec<Principal>,
+    // set by the parent when it spawns this canister as a shard; ignored on
+    // upgrade
+    id_base: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PatchResult {
+    quiz: Quiz,
+    // counted on the options that were removed, along with the votes behind them
+    discarded_votes: u64,
+}
+
+// for `edit_option`; every field left None stays as it is
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct OptionPatch {
+    label: Option<String>,
+    description: Option<String>,
+    image_url: Option<String>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct TemplatePayload {
+    name: String,
+    question: String,
+    options: Vec<String>,
+    kind: Option<QuizKind>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct QuizSetPayload {
+    name: String,
+    quiz_ids: Vec<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct QuizSummary {
+    id: u64,
+    question: String,
+    status: QuizStatus,
+    // 0 while the caller can't see the results
+    total_votes: u64,
+    option_count: u64,
+    created_at: u64,
+}
+
+// `Page`, for list views that only need `QuizSummary`s
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct SummaryPage {
+    items: Vec<QuizSummary>,
+    total: u64,
+    next_offset: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum SortOrder {
+    CreatedAtAsc,
+    CreatedAtDesc,
+    // ties keep id order
+    TotalVotesDesc,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct Page {
+    items: Vec<Quiz>,
+    // readable quizzes overall, not just on this page
+    total: u64,
+    // None on the last page
+    next_offset: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AnswerResult {
+    correct: bool,
+    // the caller's correct answers across all graded quizzes
+    score: u64,
+    // one per option picked
+    receipts: Vec<VoteReceipt>,
+}
+
+// Proof of one recorded answer: `receipt_hash` commits to the quiz, the
+// voter, the question, the option and the time, so it can't be made up for an
+// answer that was never cast. `option_id` is the option's stable id on the
+// first question and its position on follow-up questions, which have no ids.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct VoteReceipt {
+    quiz_id: u64,
+    question: u32,
+    option_id: u32,
+    timestamp: u64,
+    receipt_hash: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ReceiptStatus {
+    // the answer was cast as the receipt says
+    recorded: bool,
+    // and still counts, i.e. hasn't been changed or retracted since
+    counted: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct LeaderboardEntry {
+    principal: Principal,
+    score: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct QuizWithContext {
+    quiz: Quiz,
+    has_voted: bool,
+    my_option: Option<String>,
+    votable: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct OptionShare {
+    option: String,
+    votes: u32,
+    share: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct WinnerMargin {
+    // more than one entry means a tie for first; empty when nobody voted
+    winners: Vec<String>,
+    winner_votes: u32,
+    // fraction of all votes, in [0, 1]
+    winner_share: f64,
+    runner_up_votes: u32,
+    margin_votes: u32,
+    // margin over the runner-up in percentage points of all votes
+    margin_percent: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct EngagementComparison {
+    total_votes: u64,
+    mean_total_votes: f64,
+    // None when no quiz has any votes yet
+    ratio_to_mean: Option<f64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct OptionCountStats {
+    min: u64,
+    max: u64,
+    mean: f64,
+    median: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum Movement {
+    Up,
+    Down,
+    Same,
+    // no snapshot yet, or the option wasn't in it
+    New,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct RankMovement {
+    option: String,
+    // 1-based; tied options share a rank
+    current_rank: u64,
+    previous_rank: Option<u64>,
+    movement: Movement,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum IneligibleReason {
+    Banned,
+    NotAllowed,
+    // anonymous callers aren't admitted to the quiz
+    Anonymous,
+    AlreadyVoted,
+    Closed,
+    Expired,
+    // still a draft
+    NotStarted,
+    Full,
+}
+
+// a participant's clock on a timed quiz, as of the call
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct AttemptStatus {
+    started_at: u64,
+    // answers arriving after this are rejected
+    deadline: u64,
+    remaining_ns: u64,
+    expired: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VoteEligibility {
+    eligible: bool,
+    // None when eligible
+    reason: Option<IneligibleReason>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct Funnel {
+    total_quizzes: u64,
+    with_any_votes: u64,
+    with_ten_votes: u64,
+    // closed or past `expires_at`
+    closed_or_expired: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CohortStat {
+    // first byte of the voters' principals
+    prefix: u8,
+    voters: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct Turnout {
+    // distinct principals in the vote log
+    voters: u64,
+    // size of the allowlist; None for open quizzes
+    allowed: Option<u64>,
+    // share of allowlisted principals who voted; None for open quizzes
+    turnout: Option<f64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct OptionResult {
+    option: String,
+    votes: u32,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct PeakWindow {
+    // inclusive start, exclusive end
+    start: u64,
+    end: u64,
+    vote_count: u64,
+}
+
+// a quiz with its vote log, as `export_quizzes` hands it out
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct QuizExport {
+    quiz: Quiz,
+    votes: Vec<Vote>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CertifiedQuiz {
+    quiz: Quiz,
+    // the first question's (option, count) pairs, sorted by option
+    results: Vec<(String, u32)>,
+    certificate: Vec<u8>,
+    // CBOR-encoded hash tree
+    witness: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CertifiedResult {
+    snapshot: ResultSnapshot,
+    // None in update calls, which get no certificate
+    certificate: Option<Vec<u8>>,
+    // CBOR-encoded hash tree
+    witness: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PendingResponse {
+    principal: Principal,
+    text: String,
+    submitted_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct RankedResult {
+    // None while nobody has voted, or when the last options standing tie
+    winner: Option<String>,
+    rounds: Vec<RankedRound>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct RankedRound {
+    // first preferences among the options still standing, in quiz order
+    counts: Vec<(String, u32)>,
+    // knocked out at the end of this round; empty in the deciding round
+    eliminated: Vec<String>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ExportChunk {
+    // a candid-encoded `Vec<QuizExport>`, ready for `import_quizzes`
+    data: Vec<u8>,
+    // pass back to `export_chunk` for the next chunk; None once done
+    next_cursor: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct ImportValidation {
+    // set when the input isn't a JSON array at all
+    parse_error: Option<String>,
+    valid_count: u64,
+    // (index in the array, why it would be rejected)
+    failures: Vec<(u64, Error)>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct SnapshotDiff {
+    quiz_id: u64,
+    from_index: u64,
+    to_index: u64,
+    // options of the later snapshot first, then any that only the earlier one had
+    deltas: Vec<(String, i64)>,
+    total_delta: i64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct OptionSetStat {
+    // normalized option texts, in lexicographic order
+    options: Vec<String>,
+    quiz_count: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct TimeBucket {
+    bucket_start: u64,
+    count: u64,
+}
+
+// Answers to the first question as they were cast, so a changed or retracted
+// answer still counts here even though it's gone from the tallies.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct QuizStats {
+    total_votes: u64,
+    // every option, in the quiz's order; shares are fractions in [0, 1]
+    options: Vec<OptionShare>,
+    // more than one entry means a tie; empty when nobody answered
+    winners: Vec<String>,
+    hourly: Vec<TimeBucket>,
+    daily: Vec<TimeBucket>,
+    // distinct principals, anonymous ballots not counted
+    unique_voters: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct StorageInfo {
+    quiz_count: u64,
+    total_bytes: u64,
+    largest_quiz_bytes: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct StorageUsage {
+    used_bytes: u64,
+    budget_bytes: u64,
+    // one per MemoryId, in id order
+    regions: Vec<RegionUsage>,
+    quiz_count: u64,
+    // 0 means unlimited, as in `Config`
+    max_quizzes: u64,
+    max_attempts_per_quiz: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct RegionUsage {
+    memory_id: u8,
+    name: String,
+    bytes: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CanisterMetrics {
+    quiz_count: u64,
+    // entries in the vote log, i.e. votes currently counted
+    total_votes: u64,
+    // (memory id, 64 KiB pages) for every id below MEMORY_ID_COUNT
+    stable_memory_pages: Vec<(u8, u64)>,
+    heap_bytes: u64,
+    cycle_balance: u128,
+    last_upgraded_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct IntegrityReport {
+    // (set id, quiz id) pairs whose quiz no longer exists
+    orphaned_set_refs: Vec<(u64, u64)>,
+    // vote log keys whose quiz no longer exists
+    orphaned_vote_keys: Vec<(u64, u64)>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PurgeSummary {
+    set_refs_removed: u64,
+    vote_records_removed: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ParticipationReport {
+    set_id: u64,
+    answered: u64,
+    total: u64,
+    unanswered: Vec<u64>,
+}
+
+#[ic_cdk::query]
+fn get_all_quiz() -> Result<Vec<Quiz>, Error> {
+    let quizzes = page_of(0, u64::MAX, SortOrder::CreatedAtAsc, None).items;
+
+    if !quizzes.is_empty() {
+        Ok(quizzes)
+    } else {
+        Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: "There are currently no quizzes".to_string(),
+        })
+    }
+}
+
+// None lists quizzes in every status
+#[ic_cdk::query]
+fn list_quizzes(offset: u64, limit: u64, sort: SortOrder, status: Option<QuizStatus>) -> Page {
+    page_of(offset, limit.min(MAX_PAGE_SIZE), sort, status)
+}
+
+// sorts on keys only, so just the quizzes on the page are cloned out
+fn page_of(offset: u64, limit: u64, sort: SortOrder, status: Option<QuizStatus>) -> Page {
+    let mut keys: Vec<(u64, u64)> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| status.is_none_or(|status| quiz.status() == status))
+            .map(|quiz| match sort {
+                SortOrder::CreatedAtAsc | SortOrder::CreatedAtDesc => (quiz.created_at, quiz.id),
+                SortOrder::TotalVotesDesc => (quiz.total_votes(), quiz.id),
+            })
+            .collect()
+    });
+    match sort {
+        SortOrder::CreatedAtAsc => keys.sort_unstable(),
+        SortOrder::CreatedAtDesc => keys.sort_unstable_by(|a, b| b.cmp(a)),
+        SortOrder::TotalVotesDesc => {
+            keys.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)))
+        }
+    }
+
+    let total = keys.len() as u64;
+    let items: Vec<Quiz> = keys
+        .iter()
+        .skip(offset.min(total) as usize)
+        .take(limit.min(total) as usize)
+        .filter_map(|(_, id)| _get_quiz(id))
+        .map(Quiz::redacted)
+        .collect();
+    let end = offset.saturating_add(limit);
+    Page {
+        items,
+        total,
+        next_offset: (end < total).then_some(end),
+    }
+}
+
+// in id order
+#[ic_cdk::query]
+fn list_quiz_summaries(offset: u64, limit: u64) -> SummaryPage {
+    let limit = limit.min(MAX_PAGE_SIZE);
+    let mut total = 0;
+    let mut items = Vec::new();
+    with_listed_quizzes(|quizzes| {
+        for quiz in quizzes {
+            if total >= offset && (items.len() as u64) < limit {
+                items.push(summary_of(quiz.redacted()));
+            }
+            total += 1;
+        }
+    });
+    let end = offset.saturating_add(limit);
+    SummaryPage {
+        items,
+        total,
+        next_offset: (end < total).then_some(end),
+    }
+}
+
+// Most answers over the last TRENDING_WINDOW_HOURS first, then most answers
+// overall. Reads the running totals `note_activity` keeps, so only recently
+// answered quizzes are looked at.
+#[ic_cdk::query]
+fn get_trending_quizzes(limit: u64) -> Vec<Quiz> {
+    let mut ranked: Vec<(u64, Quiz)> = RECENT_ANSWERS.with(|recent| {
+        recent
+            .borrow()
+            .iter()
+            .filter_map(|(id, count)| {
+                _get_quiz(&id)
+                    .filter(Quiz::is_listed)
+                    .map(|quiz| (count, quiz))
+            })
+            .collect()
+    });
+    ranked.sort_by(|(a_recent, a), (b_recent, b)| {
+        b_recent
+            .cmp(a_recent)
+            .then_with(|| b.total_votes().cmp(&a.total_votes()))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    ranked
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(_, quiz)| quiz.redacted())
+        .collect()
+}
+
+// most recently answered first
+#[ic_cdk::query]
+fn get_recently_answered(limit: u64) -> Vec<Quiz> {
+    ANSWERED_ORDER.with(|order| {
+        order
+            .borrow()
+            .iter()
+            .filter_map(|((_, id), _)| _get_quiz(&id))
+            .filter(Quiz::is_listed)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(Quiz::redacted)
+            .collect()
+    })
+}
+
+fn note_activity(quiz_id: u64) {
+    let now = time();
+    let key = (quiz_id, now / NANOS_PER_HOUR);
+    ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        let count = activity.get(&key).unwrap_or(0);
+        activity.insert(key, count.saturating_add(1));
+    });
+    RECENT_ANSWERS.with(|recent| {
+        let mut recent = recent.borrow_mut();
+        let count = recent.get(&quiz_id).unwrap_or(0);
+        recent.insert(quiz_id, count + 1);
+    });
+    let previous = LAST_ANSWERED.with(|last| last.borrow_mut().insert(quiz_id, now));
+    ANSWERED_ORDER.with(|order| {
+        let mut order = order.borrow_mut();
+        if let Some(previous) = previous {
+            order.remove(&(u64::MAX - previous, quiz_id));
+        }
+        order.insert((u64::MAX - now, quiz_id), ());
+    });
+}
+
+// Fills the feeds from the vote log, for canisters upgraded from before they
+// existed. Removed and replaced votes are gone from the log, so the counts can
+// come out lower than `note_activity` would have kept them.
+fn rebuild_activity() {
+    let oldest_hour = (time() / NANOS_PER_HOUR).saturating_sub(TRENDING_WINDOW_HOURS - 1);
+    let mut buckets: BTreeMap<(u64, u64), u32> = BTreeMap::new();
+    let mut last: BTreeMap<u64, u64> = BTreeMap::new();
+    VOTE_LOG.with(|log| {
+        for ((quiz_id, _), vote) in log.borrow().iter() {
+            let hour = vote.timestamp / NANOS_PER_HOUR;
+            if hour >= oldest_hour {
+                *buckets.entry((quiz_id, hour)).or_insert(0) += 1;
+            }
+            let latest = last.entry(quiz_id).or_insert(0);
+            *latest = (*latest).max(vote.timestamp);
+        }
+    });
+    for ((quiz_id, hour), count) in buckets {
+        ACTIVITY.with(|activity| activity.borrow_mut().insert((quiz_id, hour), count));
+        RECENT_ANSWERS.with(|recent| {
+            let mut recent = recent.borrow_mut();
+            let total = recent.get(&quiz_id).unwrap_or(0);
+            recent.insert(quiz_id, total + count as u64);
+        });
+    }
+    for (quiz_id, at) in last {
+        LAST_ANSWERED.with(|last| last.borrow_mut().insert(quiz_id, at));
+        ANSWERED_ORDER.with(|order| order.borrow_mut().insert((u64::MAX - at, quiz_id), ()));
+    }
+}
+
+// Runs hourly from the pruning timer: buckets that slid out of the trending
+// window come off their quiz's running total.
+fn decay_activity() {
+    let oldest_hour = (time() / NANOS_PER_HOUR).saturating_sub(TRENDING_WINDOW_HOURS - 1);
+    let stale: Vec<((u64, u64), u32)> = ACTIVITY.with(|activity| {
+        activity
+            .borrow()
+            .iter()
+            .filter(|((_, hour), _)| *hour < oldest_hour)
+            .collect()
+    });
+    for (key, count) in stale {
+        ACTIVITY.with(|activity| activity.borrow_mut().remove(&key));
+        RECENT_ANSWERS.with(|recent| {
+            let mut recent = recent.borrow_mut();
+            let remaining = recent.get(&key.0).unwrap_or(0).saturating_sub(count as u64);
+            if remaining == 0 {
+                recent.remove(&key.0);
+            } else {
+                recent.insert(key.0, remaining);
+            }
+        });
+    }
+}
+
+fn clear_activity(quiz_id: u64) {
+    ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        let keys: Vec<(u64, u64)> = activity
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            activity.remove(&key);
+        }
+    });
+    RECENT_ANSWERS.with(|recent| recent.borrow_mut().remove(&quiz_id));
+    if let Some(last) = LAST_ANSWERED.with(|last| last.borrow_mut().remove(&quiz_id)) {
+        ANSWERED_ORDER.with(|order| order.borrow_mut().remove(&(u64::MAX - last, quiz_id)));
+    }
+}
+
+fn summary_of(quiz: Quiz) -> QuizSummary {
+    QuizSummary {
+        id: quiz.id,
+        status: quiz.status(),
+        total_votes: quiz.total_votes(),
+        option_count: quiz.options.len() as u64,
+        created_at: quiz.created_at,
+        question: quiz.question,
+    }
+}
+
+// `invite_code` is only looked at for private quizzes. Quizzes on a shard are
+// fetched from it; this canister controls the shard, so it gets them whole and
+// redacts them here. Votes stay on the shard, so to this canister nobody has
+// voted on them.
+#[ic_cdk::query(name = "get_quiz", composite = true)]
+async fn get_quiz_redacted(id: u64, invite_code: Option<String>) -> Result<Quiz, Error> {
+    let quiz = match shard_for(id) {
+        Some(shard) => {
+            let result: Result<(QuizResult,), _> =
+                ic_cdk::call(shard, "get_quiz", (id, None::<String>)).await;
+            result
+                .map_err(|(_, msg)| Error::CallFailed {
+                    canister: shard,
+                    msg: format!("couldn't reach shard {}: {}", shard, msg),
+                })?
+                .0?
+        }
+        None => get_quiz(id)?,
+    };
+    ensure_access(&quiz, &caller(), invite_code.as_deref())?;
+    Ok(quiz.redacted())
+}
+
+// How listings show a quiz to the caller: redacted, or None if they couldn't
+// open it with `get_quiz`.
+fn visible(quiz: Quiz) -> Option<Quiz> {
+    ensure_access(&quiz, &caller(), None).ok()?;
+    Some(quiz.redacted())
+}
+
+fn get_quiz(id: u64) -> Result<Quiz, Error> {
+    _get_quiz(&id).ok_or_else(|| Error::NotFound {
+        resource: Resource::Quiz,
+        msg: format!("Quiz with id={} not found", id),
+    })
+}
+
+// positions count readable quizzes in id order, so corrupt records are skipped
+#[ic_cdk::query]
+fn get_quiz_at_position(position: u64) -> Result<Quiz, Error> {
+    with_listed_quizzes(|quizzes| quizzes.nth(position as usize).map(Quiz::redacted)).ok_or_else(
+        || Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!("no quiz at position {}", position),
+        },
+    )
+}
+
+fn _get_quiz(id: &u64) -> Option<Quiz> {
+    STORAGE
+        .with(|s| s.borrow().get(id))
+        .and_then(|stored| stored.into_quiz(*id))
+}
+
+// what public listings go through: unlisted and private quizzes are left out
+fn with_listed_quizzes<R>(f: impl FnOnce(&mut dyn Iterator<Item = Quiz>) -> R) -> R {
+    with_quizzes(|quizzes| f(&mut quizzes.filter(Quiz::is_listed)))
+}
+
+// runs `f` over every readable quiz, skipping (and logging) corrupt records
+fn with_quizzes<R>(f: impl FnOnce(&mut dyn Iterator<Item = Quiz>) -> R) -> R {
+    STORAGE.with(|service| {
+        let service = service.borrow();
+        let mut quizzes = service
+            .iter()
+            .filter_map(|(id, stored)| stored.into_quiz(id));
+        f(&mut quizzes)
+    })
+}
+
+#[ic_cdk::query]
+fn count_corrupt_entries() -> u64 {
+    STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, stored)| matches!(stored, StoredQuiz::Corrupt(_)))
+            .count() as u64
+    })
+}
+
+#[ic_cdk::query]
+fn get_quiz_display(id: u64, invite_code: Option<String>) -> Result<Quiz, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_access(&quiz, &caller(), invite_code.as_deref())?;
+    let mut quiz = quiz.redacted();
+    quiz.options = quiz.display_options();
+    Ok(quiz)
+}
+
+// The quiz with its options in the order the caller should see them: their
+// own order if the quiz shuffles per voter, the shared display order if not.
+// Votes still name options by text, so tallies are unaffected.
+#[ic_cdk::query]
+fn get_quiz_for_voting(id: u64, invite_code: Option<String>) -> Result<Quiz, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_access(&quiz, &caller(), invite_code.as_deref())?;
+    let mut quiz = quiz.redacted();
+    if quiz.shuffle_per_voter != Some(true) {
+        quiz.options = quiz.display_options();
+        return Ok(quiz);
+    }
+    let seed = voter_seed(id, &caller(), quiz.display_seed());
+    quiz.options = shuffled(&quiz.options, seed);
+    if let Some(questions) = quiz.extra_questions.as_mut() {
+        for (i, question) in questions.iter_mut().enumerate() {
+            question.options = shuffled(&question.options, splitmix64(seed ^ (i as u64 + 1)));
+        }
+    }
+    Ok(quiz)
+}
+
+// Stable per (quiz, voter), and unguessable once the salt has been fetched.
+// Until then the quiz's own display seed stands in for it.
+fn voter_seed(id: u64, voter: &Principal, display_seed: u64) -> u64 {
+    let salt = get_config()
+        .shuffle_salt
+        .unwrap_or_else(|| display_seed.to_be_bytes().to_vec());
+    let mut hasher = Sha256::new();
+    hasher.update(&salt);
+    hasher.update(id.to_be_bytes());
+    hasher.update(voter.as_slice());
+    let digest = hasher.finalize();
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+// `raw_rand` can't be called from `init` or `post_upgrade` directly, so it
+// runs from a timer right after
+fn schedule_salt_fetch() {
+    if get_config().shuffle_salt.is_none() {
+        set_timer(Duration::ZERO, || ic_cdk::spawn(fetch_shuffle_salt()));
+    }
+}
+
+async fn fetch_shuffle_salt() {
+    match raw_rand().await {
+        Ok((bytes,)) => {
+            write_config(|config| config.shuffle_salt = Some(bytes));
+        }
+        Err((_, msg)) => ic_cdk::println!("couldn't fetch the shuffle salt: {}", msg),
+    }
+}
+
+#[ic_cdk::query]
+fn get_quiz_with_context(id: u64, invite_code: Option<String>) -> Result<QuizWithContext, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_access(&quiz, &caller(), invite_code.as_deref())?;
+    let quiz = quiz.redacted();
+    let my_vote = last_vote_of(id, &caller());
+    let has_voted = my_vote.is_some();
+    let votable = quiz.accepts_votes(time()) && (quiz.allows_vote_change() || !has_voted);
+
+    Ok(QuizWithContext {
+        quiz,
+        has_voted,
+        my_option: my_vote.map(|vote| vote.option),
+        votable,
+    })
+}
+
+#[ic_cdk::query]
+fn get_answers_ordered(id: u64) -> Result<Vec<(String, u32)>, Error> {
+    Ok(get_quiz_for_results(id)?.ordered_answers())
+}
+
+// every question of the quiz with its tallies, the first one included
+#[ic_cdk::query]
+fn get_questions(id: u64) -> Result<Vec<Question>, Error> {
+    Ok(get_quiz_for_results(id)?.questions())
+}
+
+// like `get_answers_ordered`, for any question of the quiz
+#[ic_cdk::query]
+fn get_question_results(id: u64, question_index: u32) -> Result<Vec<(String, u32)>, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let question = quiz
+        .questions()
+        .into_iter()
+        .nth(question_index as usize)
+        .ok_or_else(|| Error::NotFound {
+            resource: Resource::Question,
+            msg: format!("quiz with id={} has no question {}", id, question_index),
+        })?;
+    Ok(question
+        .options
+        .iter()
+        .map(|option| (option.clone(), *question.answers.get(option).unwrap_or(&0)))
+        .collect())
+}
+
+#[ic_cdk::query]
+fn get_vote_shares(id: u64) -> Result<Vec<OptionShare>, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let total = quiz.total_votes();
+
+    Ok(quiz
+        .ordered_answers()
+        .into_iter()
+        .map(|(option, votes)| OptionShare {
+            option,
+            votes,
+            share: if total == 0 {
+                0.0
+            } else {
+                round_result(votes as f64 / total as f64)
+            },
+        })
+        .collect())
+}
+
+fn round_result(value: f64) -> f64 {
+    let precision = get_config()
+        .result_precision
+        .unwrap_or(DEFAULT_RESULT_PRECISION)
+        .min(MAX_RESULT_PRECISION);
+    let scale = 10f64.powi(precision as i32);
+    (value * scale).round() / scale
+}
+
+#[ic_cdk::query]
+fn get_rank_movement(id: u64) -> Result<Vec<RankMovement>, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let previous = latest_snapshot(id)
+        .map(|snapshot| ranks(&snapshot.options_with_counts))
+        .unwrap_or_default();
+
+    Ok(ranks(&quiz.ordered_answers())
+        .into_iter()
+        .map(|(option, current_rank)| {
+            let previous_rank = previous
+                .iter()
+                .find(|(previous_option, _)| *previous_option == option)
+                .map(|(_, rank)| *rank);
+            let movement = match previous_rank {
+                None => Movement::New,
+                Some(rank) if current_rank < rank => Movement::Up,
+                Some(rank) if current_rank > rank => Movement::Down,
+                Some(_) => Movement::Same,
+            };
+            RankMovement {
+                option,
+                current_rank,
+                previous_rank,
+                movement,
+            }
+        })
+        .collect())
+}
+
+// rank = 1 + the number of options with strictly more votes
+fn ranks(counts: &[(String, u32)]) -> Vec<(String, u64)> {
+    counts
+        .iter()
+        .map(|(option, count)| {
+            let ahead = counts.iter().filter(|(_, other)| other > count).count();
+            (option.clone(), ahead as u64 + 1)
+        })
+        .collect()
+}
+
+// The returned window starts at a vote; the earliest one wins ties.
+#[ic_cdk::query]
+fn get_peak_voting_window(id: u64, window_ns: u64) -> Result<PeakWindow, Error> {
+    if window_ns == 0 {
+        return Err(Error::InvalidInput {
+            msg: "window_ns must be greater than zero".to_string(),
+        });
+    }
+    get_readable_quiz(id)?;
+
+    let mut timestamps: Vec<u64> = VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(_, vote)| vote.timestamp)
+            .collect()
+    });
+    timestamps.sort_unstable();
+
+    let mut peak = PeakWindow::default();
+    let mut end = 0;
+    for (start, &start_time) in timestamps.iter().enumerate() {
+        let window_end = start_time.saturating_add(window_ns);
+        end = end.max(start);
+        while end < timestamps.len() && timestamps[end] < window_end {
+            end += 1;
+        }
+        let vote_count = (end - start) as u64;
+        if vote_count > peak.vote_count {
+            peak = PeakWindow {
+                start: start_time,
+                end: window_end,
+                vote_count,
+            };
+        }
+    }
+    Ok(peak)
+}
+
+// Shannon entropy in bits: 0 when unanimous, log2(option count) when even
+#[ic_cdk::query]
+fn get_vote_entropy(id: u64) -> Result<f64, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let total = quiz.total_votes();
+    if total == 0 {
+        return Ok(0.0);
+    }
+    Ok(quiz
+        .answers
+        .values()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let share = *count as f64 / total as f64;
+            -share * share.log2()
+        })
+        .sum())
+}
+
+// SHA-256 hex over (option, count) pairs sorted by option. Each option is
+// length-prefixed so no two result sets can produce the same byte stream.
+#[ic_cdk::query]
+fn get_results_hash(id: u64) -> Result<String, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    Ok(results_digest(&sorted_results(&quiz))
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn sorted_results(quiz: &Quiz) -> Vec<(String, u32)> {
+    let mut results = quiz.ordered_answers();
+    results.sort();
+    results
+}
+
+fn results_digest(results: &[(String, u32)]) -> Hash {
+    let mut hasher = Sha256::new();
+    for (option, count) in results {
+        hasher.update((option.len() as u64).to_le_bytes());
+        hasher.update(option.as_bytes());
+        hasher.update(count.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+// To verify: check `certificate` against the IC root key, hash `results` as
+// `get_results_hash` does, and look it up in `witness` under
+// "results" / <id as 8 big-endian bytes>. The witness' root must match the
+// certified data in the certificate.
+#[ic_cdk::query]
+fn get_quiz_certified(id: u64) -> Result<CertifiedQuiz, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_results_visible(&quiz)?;
+    let certificate = data_certificate().ok_or_else(|| Error::InvalidInput {
+        msg: "certificates are only available in query calls".to_string(),
+    })?;
+    Ok(CertifiedQuiz {
+        results: sorted_results(&quiz),
+        quiz: quiz.redacted(),
+        certificate,
+        witness: results_witness(&id.to_be_bytes()),
+    })
+}
+
+fn results_witness(key: &[u8]) -> Vec<u8> {
+    CERTIFIED_RESULTS.with(|tree| {
+        let tree = tree.borrow();
+        let witness = labeled(CERTIFIED_RESULTS_LABEL, tree.witness(key));
+        let mut serializer = serde_cbor::Serializer::new(Vec::new());
+        serializer.self_describe().expect("Cannot encode witness");
+        serde::Serialize::serialize(&witness, &mut serializer).expect("Cannot encode witness");
+        serializer.into_inner()
+    })
+}
+
+// Final results sit in the same certified tree as the live ones, under
+// "results" / <id as 8 big-endian bytes> <close index as 8 big-endian bytes>.
+// The hash there is `final_result_digest` of the snapshot.
+#[ic_cdk::query]
+fn get_final_result(quiz_id: u64) -> Result<CertifiedResult, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    // the results were out once the quiz closed, whatever happened after
+    if quiz.results_visibility != Some(ResultsVisibility::AfterClose) {
+        ensure_results_visible(&quiz)?;
+    }
+    let (index, snapshot) = latest_final_result(quiz_id).ok_or_else(|| Error::NotFound {
+        resource: Resource::Snapshot,
+        msg: format!("quiz with id={} has never closed", quiz_id),
+    })?;
+    Ok(CertifiedResult {
+        snapshot,
+        certificate: data_certificate(),
+        witness: results_witness(&final_result_key(quiz_id, index)),
+    })
+}
+
+fn latest_final_result(quiz_id: u64) -> Option<(u64, ResultSnapshot)> {
+    FINAL_RESULTS.with(|results| {
+        match results
+            .borrow()
+            .iter_upper_bound(&(quiz_id, u64::MAX))
+            .next()
+        {
+            Some(((id, index), snapshot)) if id == quiz_id => Some((index, snapshot)),
+            _ => None,
+        }
+    })
+}
+
+fn final_result_key(quiz_id: u64, index: u64) -> Vec<u8> {
+    [quiz_id.to_be_bytes(), index.to_be_bytes()].concat()
+}
+
+// `results_digest` of the sorted tallies, bound to the round and close time
+fn final_result_digest(snapshot: &ResultSnapshot) -> Hash {
+    let mut results = snapshot.final_tallies.clone();
+    results.sort();
+    let mut hasher = Sha256::new();
+    hasher.update(b"final-result");
+    hasher.update(snapshot.round.to_le_bytes());
+    hasher.update(snapshot.closed_at.to_le_bytes());
+    hasher.update(snapshot.total_votes.to_le_bytes());
+    if let Some(winner) = &snapshot.winner {
+        hasher.update((winner.len() as u64).to_le_bytes());
+        hasher.update(winner.as_bytes());
+    }
+    hasher.update(results_digest(&results));
+    hasher.finalize().into()
+}
+
+fn freeze_result(quiz: &Quiz, closed_at: u64) {
+    let final_tallies = quiz.ordered_answers();
+    let top = final_tallies
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0);
+    let leaders: Vec<&String> = final_tallies
+        .iter()
+        .filter(|(_, count)| top > 0 && *count == top)
+        .map(|(option, _)| option)
+        .collect();
+    let winner = match leaders.as_slice() {
+        [leader] => Some((*leader).clone()),
+        _ => None,
+    };
+    let snapshot = ResultSnapshot {
+        quiz_id: quiz.id,
+        round: quiz.round(),
+        final_tallies,
+        total_votes: quiz.total_votes(),
+        closed_at,
+        winner,
+    };
+    let index = match latest_final_result(quiz.id) {
+        Some((index, _)) => index + 1,
+        None => 0,
+    };
+    FINAL_RESULTS.with(|results| {
+        results
+            .borrow_mut()
+            .insert((quiz.id, index), snapshot.clone())
+    });
+    certify_final_result(index, &snapshot);
+}
+
+fn certify_final_result(index: u64, snapshot: &ResultSnapshot) {
+    CERTIFIED_RESULTS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert(
+            final_result_key(snapshot.quiz_id, index),
+            final_result_digest(snapshot),
+        );
+        set_certified_data(&labeled_hash(CERTIFIED_RESULTS_LABEL, &tree.root_hash()));
+    });
+}
+
+fn clear_final_results(quiz_id: u64) {
+    let stale: Vec<(u64, u64)> = FINAL_RESULTS.with(|results| {
+        results
+            .borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect()
+    });
+    CERTIFIED_RESULTS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        for (id, index) in &stale {
+            tree.delete(&final_result_key(*id, *index));
+        }
+        set_certified_data(&labeled_hash(CERTIFIED_RESULTS_LABEL, &tree.root_hash()));
+    });
+    FINAL_RESULTS.with(|results| {
+        let mut results = results.borrow_mut();
+        for key in stale {
+            results.remove(&key);
+        }
+    });
+}
+
+// None when fewer than two options have votes or first place is tied; a tie
+// for second goes to the option listed first.
+#[ic_cdk::query]
+fn get_runner_up(id: u64) -> Result<Option<OptionResult>, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let mut voted: Vec<(String, u32)> = quiz
+        .ordered_answers()
+        .into_iter()
+        .filter(|(_, votes)| *votes > 0)
+        .collect();
+    // stable, so equal counts keep their option order
+    voted.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    match voted.as_slice() {
+        [(_, first), (option, second), ..] if first > second => Ok(Some(OptionResult {
+            option: option.clone(),
+            votes: *second,
+        })),
+        _ => Ok(None),
+    }
+}
+
+// Gini coefficient over option counts: 0 when even, (n - 1) / n when one of
+// n options has every vote
+#[ic_cdk::query]
+fn get_vote_gini(id: u64) -> Result<f64, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let total = quiz.total_votes();
+    if total == 0 {
+        return Ok(0.0);
+    }
+    let counts: Vec<f64> = quiz.answers.values().map(|count| *count as f64).collect();
+    let pairwise: f64 = counts
+        .iter()
+        .flat_map(|a| counts.iter().map(move |b| (a - b).abs()))
+        .sum();
+    Ok(pairwise / (2.0 * counts.len() as f64 * total as f64))
+}
+
+// anonymous ballots report the anonymous principal
+#[ic_cdk::query]
+fn get_last_voter(id: u64) -> Result<Option<Principal>, Error> {
+    get_readable_quiz(id)?;
+    Ok(VOTE_LOG.with(
+        |log| match log.borrow().iter_upper_bound(&(id, u64::MAX)).next() {
+            Some(((quiz_id, _), vote)) if quiz_id == id => Some(vote.voter),
+            _ => None,
+        },
+    ))
+}
+
+#[ic_cdk::query]
+fn get_time_to_first_vote(id: u64) -> Result<Option<u64>, Error> {
+    let quiz = get_readable_quiz(id)?;
+    let first_vote = VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(_, vote)| vote.timestamp)
+            .min()
+    });
+    Ok(first_vote.map(|timestamp| timestamp.saturating_sub(quiz.created_at)))
+}
+
+// distinct voters per principal prefix, in prefix order
+#[ic_cdk::query]
+fn get_voter_cohorts(id: u64) -> Result<Vec<CohortStat>, Error> {
+    get_readable_quiz(id)?;
+    let voters = distinct_voters(id);
+
+    let mut cohorts: BTreeMap<u8, u64> = BTreeMap::new();
+    for voter in &voters {
+        let prefix = voter.as_slice().first().copied().unwrap_or(0);
+        *cohorts.entry(prefix).or_insert(0) += 1;
+    }
+    Ok(cohorts
+        .into_iter()
+        .map(|(prefix, voters)| CohortStat { prefix, voters })
+        .collect())
+}
+
+#[ic_cdk::query]
+fn get_turnout(id: u64) -> Result<Turnout, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let voters = distinct_voters(id);
+
+    let allowed = quiz
+        .allowed_voters
+        .as_ref()
+        .map(|allowed| allowed.len() as u64);
+    let turnout = quiz.allowed_voters.as_ref().and_then(|allowed| {
+        let voted = allowed.iter().filter(|p| voters.contains(p)).count();
+        (!allowed.is_empty()).then(|| round_result(voted as f64 / allowed.len() as f64))
+    });
+    Ok(Turnout {
+        voters: voters.len() as u64,
+        allowed,
+        turnout,
+    })
+}
+
+#[ic_cdk::query]
+fn get_winner_margin(id: u64) -> Result<WinnerMargin, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let total = quiz.total_votes();
+    if total == 0 {
+        return Ok(WinnerMargin::default());
+    }
+
+    let answers = quiz.ordered_answers();
+    let winner_votes = answers.iter().map(|(_, votes)| *votes).max().unwrap_or(0);
+    let winners: Vec<String> = answers
+        .iter()
+        .filter(|(_, votes)| *votes == winner_votes)
+        .map(|(option, _)| option.clone())
+        .collect();
+    let runner_up_votes = if winners.len() > 1 {
+        winner_votes
+    } else {
+        answers
+            .iter()
+            .map(|(_, votes)| *votes)
+            .filter(|votes| *votes < winner_votes)
+            .max()
+            .unwrap_or(0)
+    };
+    let margin_votes = winner_votes - runner_up_votes;
+
+    Ok(WinnerMargin {
+        winners,
+        winner_votes,
+        winner_share: round_result(winner_votes as f64 / total as f64),
+        runner_up_votes,
+        margin_votes,
+        margin_percent: round_result(margin_votes as f64 / total as f64 * 100.0),
+    })
+}
+
+#[ic_cdk::query]
+fn get_engagement_comparison(id: u64) -> Result<EngagementComparison, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let (quiz_count, all_votes) = with_quizzes(|quizzes| {
+        quizzes.fold((0u64, 0u64), |(count, votes), quiz| {
+            (count + 1, votes + quiz.total_votes())
+        })
+    });
+
+    let total_votes = quiz.total_votes();
+    let mean_total_votes = all_votes as f64 / quiz_count as f64;
+    Ok(EngagementComparison {
+        total_votes,
+        mean_total_votes,
+        ratio_to_mean: (all_votes > 0).then(|| total_votes as f64 / mean_total_votes),
+    })
+}
+
+#[ic_cdk::query]
+fn get_quiz_stats(id: u64) -> Result<QuizStats, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let attempts: Vec<Attempt> = ATTEMPTS.with(|attempts| {
+        attempts
+            .borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(_, attempt)| attempt)
+            .filter(|attempt| attempt.question.unwrap_or(0) == 0)
+            .collect()
+    });
+
+    let total_votes = attempts.len() as u64;
+    let mut counts: HashMap<&str, u32> = HashMap::new();
+    let mut hourly: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut daily: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut voters: BTreeSet<Principal> = BTreeSet::new();
+    for attempt in &attempts {
+        *counts.entry(&attempt.option).or_insert(0) += 1;
+        *hourly
+            .entry(attempt.timestamp - attempt.timestamp % NANOS_PER_HOUR)
+            .or_insert(0) += 1;
+        *daily
+            .entry(attempt.timestamp - attempt.timestamp % NANOS_PER_DAY)
+            .or_insert(0) += 1;
+        if attempt.principal != Principal::anonymous() {
+            voters.insert(attempt.principal);
+        }
+    }
+
+    let options: Vec<OptionShare> = quiz
+        .options
+        .iter()
+        .map(|option| {
+            let votes = counts.get(option.as_str()).copied().unwrap_or(0);
+            OptionShare {
+                option: option.clone(),
+                votes,
+                share: if total_votes == 0 {
+                    0.0
+                } else {
+                    round_result(votes as f64 / total_votes as f64)
+                },
+            }
+        })
+        .collect();
+    let winner_votes = options.iter().map(|share| share.votes).max().unwrap_or(0);
+    let winners = options
+        .iter()
+        .filter(|share| winner_votes > 0 && share.votes == winner_votes)
+        .map(|share| share.option.clone())
+        .collect();
+    let buckets = |buckets: BTreeMap<u64, u64>| {
+        buckets
+            .into_iter()
+            .map(|(bucket_start, count)| TimeBucket {
+                bucket_start,
+                count,
+            })
+            .collect()
+    };
+
+    Ok(QuizStats {
+        total_votes,
+        options,
+        winners,
+        hourly: buckets(hourly),
+        daily: buckets(daily),
+        unique_voters: voters.len() as u64,
+    })
+}
+
+// for queries that read a quiz's tallies
+fn get_quiz_for_results(id: u64) -> Result<Quiz, Error> {
+    let quiz = get_readable_quiz(id)?;
+    ensure_results_visible(&quiz)?;
+    Ok(quiz)
+}
+
+// for queries about who voted and when, or how the quiz was edited
+fn get_readable_quiz(id: u64) -> Result<Quiz, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_access(&quiz, &caller(), None)?;
+    Ok(quiz)
+}
+
+// `results_visibility` applied to callers who aren't managing the quiz
+fn ensure_results_visible(quiz: &Quiz) -> Result<(), Error> {
+    if quiz.status() == QuizStatus::Closed
+        || ensure_can_manage(quiz).is_ok()
+        || quiz.results_visible_to(&caller())
+    {
+        return Ok(());
+    }
+    Err(Error::Unauthorized {
+        msg: format!("results of quiz with id={} aren't visible yet", quiz.id),
+    })
+}
+
+#[ic_cdk::query]
+fn get_creation_histogram(bucket_ns: u64) -> Result<Vec<TimeBucket>, Error> {
+    if bucket_ns == 0 {
+        return Err(Error::InvalidInput {
+            msg: "bucket_ns must be greater than zero".to_string(),
+        });
+    }
+
+    let mut buckets: BTreeMap<u64, u64> = BTreeMap::new();
+    with_quizzes(|quizzes| {
+        for quiz in quizzes {
+            let bucket_start = quiz.created_at - quiz.created_at % bucket_ns;
+            *buckets.entry(bucket_start).or_insert(0) += 1;
+        }
+    });
+
+    Ok(buckets
+        .into_iter()
+        .map(|(bucket_start, count)| TimeBucket {
+            bucket_start,
+            count,
+        })
+        .collect())
+}
+
+// Whole-word and case-insensitive: a quiz matches if its questions and
+// options contain every word of `term`. Lowest ids first.
+#[ic_cdk::query]
+fn search_quizzes(term: String, limit: u64) -> Vec<Quiz> {
+    let mut matches: Option<BTreeSet<u64>> = None;
+    for word in words_of(&term) {
+        let key = hashed_key(&word);
+        let ids: BTreeSet<u64> = WORD_INDEX.with(|index| {
+            index
+                .borrow()
+                .range((key, 0)..=(key, u64::MAX))
+                .map(|((_, id), _)| id)
+                .collect()
+        });
+        matches = Some(match matches {
+            Some(matches) => matches.intersection(&ids).copied().collect(),
+            None => ids,
+        });
+    }
+    matches
+        .unwrap_or_default()
+        .iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .filter_map(_get_quiz)
+        .map(Quiz::redacted)
+        .collect()
+}
+
+#[ic_cdk::query]
+fn search_quizzes_fuzzy(query: String, max_distance: u32) -> Vec<Quiz> {
+    let query: Vec<char> = query.trim().to_lowercase().chars().collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    // any text is within query.len() edits of the query, so cap below that
+    let max_distance = (max_distance.min(MAX_FUZZY_DISTANCE) as usize).min(query.len() - 1);
+
+    let mut matches: Vec<(usize, Quiz)> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter_map(|quiz| {
+                let distance = std::iter::once(&quiz.question)
+                    .chain(quiz.options.iter())
+                    .map(|text| fuzzy_distance(&query, text))
+                    .min()
+                    .unwrap_or(usize::MAX);
+                (distance <= max_distance).then_some((distance, quiz))
+            })
+            .collect()
+    });
+    matches.sort_by(|(a_distance, a), (b_distance, b)| {
+        a_distance.cmp(b_distance).then_with(|| a.id.cmp(&b.id))
+    });
+    matches
+        .into_iter()
+        .filter_map(|(_, quiz)| visible(quiz))
+        .collect()
+}
+
+// Levenshtein distance between `query` and its best-matching substring of
+// `text` (case-insensitive), so a typo inside a longer question still matches.
+fn fuzzy_distance(query: &[char], text: &str) -> usize {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    // previous[j] is the cost of matching the query prefix so far against a
+    // substring of `text` ending at j; starting anywhere in `text` is free.
+    let mut previous = vec![0; text.len() + 1];
+    for (i, query_char) in query.iter().enumerate() {
+        let mut current = vec![i + 1; text.len() + 1];
+        for (j, text_char) in text.iter().enumerate() {
+            let substitution = previous[j] + usize::from(query_char != text_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous.into_iter().min().unwrap_or(query.len())
+}
+
+#[ic_cdk::query]
+fn get_expiring_soon(within_ns: u64) -> Vec<Quiz> {
+    let now = time();
+    let until = now.saturating_add(within_ns);
+
+    let mut expiring: Vec<Quiz> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.status() == QuizStatus::Open)
+            .filter(|quiz| {
+                quiz.expires_at
+                    .is_some_and(|expires_at| expires_at > now && expires_at <= until)
+            })
+            .collect()
+    });
+    expiring.sort_by_key(|quiz| (quiz.expires_at, quiz.id));
+    expiring.into_iter().filter_map(visible).collect()
+}
+
+// Quizzes whose tallies the caller can't see are left out.
+#[ic_cdk::query]
+fn get_controversial_in_tag(tag: String, limit: u64) -> Vec<Quiz> {
+    let tag = normalize_tag(&tag);
+    let mut ranked: Vec<(f64, Quiz)> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.tags().contains(&tag))
+            .filter_map(visible)
+            .filter(|quiz| ensure_results_visible(quiz).is_ok())
+            .filter_map(|quiz| quiz.closeness().map(|closeness| (closeness, quiz)))
+            .collect()
+    });
+    ranked.sort_by(|(a, quiz_a), (b, quiz_b)| {
+        b.total_cmp(a)
+            .then_with(|| quiz_b.total_votes().cmp(&quiz_a.total_votes()))
+            .then_with(|| quiz_a.id.cmp(&quiz_b.id))
+    });
+    ranked
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(_, quiz)| quiz)
+        .collect()
+}
+
+// An empty option next to a non-empty one always counts as lopsided.
+#[ic_cdk::query]
+fn get_lopsided_quizzes(max_len_ratio: f64) -> Result<Vec<Quiz>, Error> {
+    if max_len_ratio.is_nan() || max_len_ratio < 1.0 {
+        return Err(Error::InvalidInput {
+            msg: "max_len_ratio must be at least 1.0".to_string(),
+        });
+    }
+
+    Ok(with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| {
+                let lengths = quiz.options.iter().map(|option| option.chars().count());
+                let shortest = lengths.clone().min().unwrap_or(0);
+                let longest = lengths.max().unwrap_or(0);
+                longest as f64 > shortest as f64 * max_len_ratio
+            })
+            .filter_map(visible)
+            .collect()
+    }))
+}
+
+#[ic_cdk::query]
+fn get_low_accuracy_quizzes(max_accuracy: f64, min_votes: u64) -> Result<Vec<Quiz>, Error> {
+    if !(0.0..=1.0).contains(&max_accuracy) {
+        return Err(Error::InvalidInput {
+            msg: "max_accuracy must be between 0 and 1".to_string(),
+        });
+    }
+
+    let mut low: Vec<(f64, Quiz)> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.total_votes() >= min_votes)
+            .filter_map(|quiz| quiz.accuracy().map(|accuracy| (accuracy, quiz)))
+            .filter(|(accuracy, _)| *accuracy <= max_accuracy)
+            .collect()
+    });
+    low.sort_by(|(a, quiz_a), (b, quiz_b)| a.total_cmp(b).then_with(|| quiz_a.id.cmp(&quiz_b.id)));
+    Ok(low
+        .into_iter()
+        .filter_map(|(_, quiz)| visible(quiz))
+        .collect())
+}
+
+#[ic_cdk::query]
+fn get_participation_funnel() -> Funnel {
+    let now = time();
+    with_quizzes(|quizzes| {
+        quizzes.fold(Funnel::default(), |mut funnel, quiz| {
+            let votes = quiz.total_votes();
+            funnel.total_quizzes += 1;
+            if votes > 0 {
+                funnel.with_any_votes += 1;
+            }
+            if votes >= 10 {
+                funnel.with_ten_votes += 1;
+            }
+            if !quiz.accepts_votes(now) {
+                funnel.closed_or_expired += 1;
+            }
+            funnel
+        })
+    })
+}
+
+// ranked by how many normalized options they share with quiz `id`
+#[ic_cdk::query]
+fn get_similar_quizzes(id: u64, limit: u64) -> Result<Vec<Quiz>, Error> {
+    let target: Vec<String> = get_readable_quiz(id)?
+        .options
+        .iter()
+        .map(|option| normalize_option(option))
+        .collect();
+
+    let mut similar: Vec<(usize, Quiz)> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.id != id)
+            .filter_map(visible)
+            .map(|quiz| {
+                let mut shared: Vec<String> = quiz
+                    .options
+                    .iter()
+                    .map(|option| normalize_option(option))
+                    .filter(|option| target.contains(option))
+                    .collect();
+                shared.sort();
+                shared.dedup();
+                (shared.len(), quiz)
+            })
+            .filter(|(overlap, _)| *overlap > 0)
+            .collect()
+    });
+    similar.sort_by(|(a, quiz_a), (b, quiz_b)| b.cmp(a).then_with(|| quiz_a.id.cmp(&quiz_b.id)));
+    Ok(similar
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(_, quiz)| quiz)
+        .collect())
+}
+
+// newest first
+#[ic_cdk::query]
+fn get_created_within(window_ns: u64) -> Result<Vec<Quiz>, Error> {
+    if window_ns == 0 {
+        return Err(Error::InvalidInput {
+            msg: "window_ns must be greater than zero".to_string(),
+        });
+    }
+    let since = time().saturating_sub(window_ns);
+    let mut recent: Vec<Quiz> =
+        with_listed_quizzes(|quizzes| quizzes.filter(|quiz| quiz.created_at >= since).collect());
+    recent.sort_by_key(|quiz| std::cmp::Reverse((quiz.created_at, quiz.id)));
+    Ok(recent.into_iter().filter_map(visible).collect())
+}
+
+#[ic_cdk::query]
+fn get_option_count_stats() -> OptionCountStats {
+    let mut counts: Vec<u64> =
+        with_quizzes(|quizzes| quizzes.map(|quiz| quiz.options.len() as u64).collect());
+    if counts.is_empty() {
+        return OptionCountStats::default();
+    }
+    counts.sort_unstable();
+
+    let len = counts.len();
+    let median = if len.is_multiple_of(2) {
+        (counts[len / 2 - 1] + counts[len / 2]) as f64 / 2.0
+    } else {
+        counts[len / 2] as f64
+    };
+    OptionCountStats {
+        min: counts[0],
+        max: counts[len - 1],
+        mean: counts.iter().sum::<u64>() as f64 / len as f64,
+        median,
+    }
+}
+
+#[ic_cdk::query]
+fn get_answers_json(id: u64) -> Result<String, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    Ok(serde_json::to_string(&quiz.answers).expect("Cannot serialize answers"))
+}
+
+// score = (total_votes + 1) / (age_in_hours + 2) ^ FEED_GRAVITY, with age taken
+// from `created_at`. The +1 lets fresh quizzes without votes still rank, the +2
+// keeps brand new quizzes from dividing by (nearly) zero. A quiz whose
+// tallies the caller can't see scores as if it had no votes.
+fn feed_score(quiz: &Quiz, now: u64) -> f64 {
+    let age_hours = now.saturating_sub(quiz.created_at) as f64 / NANOS_PER_HOUR as f64;
+    let votes = if ensure_results_visible(quiz).is_ok() {
+        quiz.total_votes()
+    } else {
+        0
+    };
+    (votes as f64 + 1.0) / (age_hours + 2.0).powf(FEED_GRAVITY)
+}
+
+#[ic_cdk::query]
+fn get_ranked_feed(limit: u64) -> Vec<Quiz> {
+    let now = time();
+    let mut scored: Vec<(f64, Quiz)> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter_map(visible)
+            .map(|quiz| (feed_score(&quiz, now), quiz))
+            .collect()
+    });
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.total_cmp(a_score).then_with(|| a.id.cmp(&b.id))
+    });
+    scored
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(_, quiz)| quiz)
+        .collect()
+}
+
+#[ic_cdk::query]
+fn render_quiz_markdown(id: u64) -> Result<String, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let total = quiz.total_votes();
+
+    let mut markdown = format!("## {}\n\n", escape_markdown(&quiz.question));
+    markdown.push_str("| Option | Votes | Percent |\n");
+    markdown.push_str("| --- | ---: | ---: |\n");
+    for (option, votes) in quiz.ordered_answers() {
+        let percent = if total == 0 {
+            0.0
+        } else {
+            votes as f64 / total as f64 * 100.0
+        };
+        markdown.push_str(&format!(
+            "| {} | {} | {:.1}% |\n",
+            escape_markdown(&option),
+            votes,
+            percent
+        ));
+    }
+    Ok(markdown)
+}
+
+// One line per option, e.g. "Yes | ######     | 6". Bars are scaled to the
+// option's share of all votes, so they're all empty until somebody votes.
+#[ic_cdk::query]
+fn render_quiz_ascii(id: u64, width: u32) -> Result<String, Error> {
+    let quiz = get_quiz_for_results(id)?;
+    let total = quiz.total_votes();
+    let width = width.clamp(MIN_ASCII_CHART_WIDTH, MAX_ASCII_CHART_WIDTH) as usize;
+    let answers = quiz.ordered_answers();
+    let label_width = answers
+        .iter()
+        .map(|(option, _)| option.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut chart = format!("{}\n", quiz.question);
+    for (option, votes) in answers {
+        let filled = if total == 0 {
+            0
+        } else {
+            (votes as f64 / total as f64 * width as f64).round() as usize
+        };
+        chart.push_str(&format!(
+            "{:<label_width$} |{}{}| {}\n",
+            option,
+            "#".repeat(filled),
+            " ".repeat(width - filled),
+            votes,
+        ));
+    }
+    Ok(chart)
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '(' | ')' | '#' | '+' | '-' | '.'
+            | '!' | '|' | '<' | '>' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            // a newline would break the heading or the table row
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[ic_cdk::query]
+fn get_common_option_sets(limit: u64) -> Vec<OptionSetStat> {
+    // pairwise only: counting larger combinations blows up combinatorially
+    let mut pairs: HashMap<(String, String), u64> = HashMap::new();
+    with_quizzes(|quizzes| {
+        for quiz in quizzes {
+            let mut options: Vec<String> = quiz
+                .options
+                .iter()
+                .map(|option| normalize_option(option))
+                .collect();
+            options.sort();
+            options.dedup();
+            for (i, first) in options.iter().enumerate() {
+                for second in &options[i + 1..] {
+                    *pairs.entry((first.clone(), second.clone())).or_insert(0) += 1;
+                }
+            }
+        }
+    });
+
+    let mut stats: Vec<OptionSetStat> = pairs
+        .into_iter()
+        .map(|((first, second), quiz_count)| OptionSetStat {
+            options: vec![first, second],
+            quiz_count,
+        })
+        .collect();
+    stats.sort_by(|a, b| {
+        b.quiz_count
+            .cmp(&a.quiz_count)
+            .then_with(|| a.options.cmp(&b.options))
+    });
+    stats.truncate(limit as usize);
+    stats
+}
+
+fn normalize_option(option: &str) -> String {
+    match get_config().normalization_mode.unwrap_or_default() {
+        NormalizationMode::Unicode => option.trim().to_lowercase(),
+        NormalizationMode::Ascii => option.trim().to_ascii_lowercase(),
+    }
+}
+
+#[ic_cdk::query]
+fn get_quiz_count() -> u64 {
+    STORAGE.with(|service| service.borrow().len())
+}
+
+// the id the next `create_quiz` will assign
+#[ic_cdk::query]
+fn get_next_id() -> u64 {
+    ID_COUNTER.with(|counter| *counter.borrow().get())
+}
+
+#[ic_cdk::query]
+fn get_storage_info() -> StorageInfo {
+    STORAGE.with(|service| {
+        let service = service.borrow();
+        let sizes: Vec<u64> = service
+            .iter()
+            .map(|(_, stored)| stored.to_bytes().len() as u64)
+            .collect();
+        StorageInfo {
+            quiz_count: service.len(),
+            total_bytes: sizes.iter().sum(),
+            largest_quiz_bytes: sizes.iter().copied().max().unwrap_or(0),
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn get_canister_metrics() -> CanisterMetrics {
+    let stable_memory_pages = (0..MEMORY_ID_COUNT)
+        .map(|id| {
+            let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(id)));
+            (id, memory.size())
+        })
+        .collect();
+    CanisterMetrics {
+        quiz_count: STORAGE.with(|service| service.borrow().len()),
+        total_votes: VOTE_LOG.with(|log| log.borrow().len()),
+        stable_memory_pages,
+        heap_bytes: heap_bytes(),
+        cycle_balance: canister_balance128(),
+        last_upgraded_at: get_config().last_upgraded_at,
+    }
+}
+
+#[ic_cdk::query]
+fn get_storage_usage() -> StorageUsage {
+    let config = get_config();
+    let regions = MEMORY_REGIONS
+        .iter()
+        .enumerate()
+        .map(|(id, name)| {
+            let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(id as u8)));
+            RegionUsage {
+                memory_id: id as u8,
+                name: name.to_string(),
+                bytes: memory.size() * WASM_PAGE_BYTES,
+            }
+        })
+        .collect();
+    StorageUsage {
+        used_bytes: stable_bytes_used(),
+        budget_bytes: storage_budget(),
+        regions,
+        quiz_count: get_quiz_count(),
+        max_quizzes: config.max_quizzes,
+        max_attempts_per_quiz: config.max_attempts_per_quiz,
+    }
+}
+
+// every region plus the memory manager's own header
+fn stable_bytes_used() -> u64 {
+    DefaultMemoryImpl::default().size() * WASM_PAGE_BYTES
+}
+
+fn storage_budget() -> u64 {
+    get_config()
+        .storage_budget_bytes
+        .unwrap_or(DEFAULT_STORAGE_BUDGET_BYTES)
+}
+
+// Every update that adds to stable memory calls this first, votes through
+// `cast_ballot` and new quizzes through `ensure_capacity`. Removals, purges and
+// the controllers' settings skip it, so a full canister can still be cleared.
+fn ensure_storage_available() -> Result<(), Error> {
+    let used = stable_bytes_used();
+    let budget = storage_budget();
+    if used.saturating_add(STORAGE_HEADROOM_BYTES) > budget {
+        return Err(Error::StorageFull {
+            used,
+            limit: budget,
+            msg: format!("stable memory is at {} of its {} byte budget", used, budget),
+        });
+    }
+    Ok(())
+}
+
+// counts the quiz's attempt log, so only when a cap is set
+fn ensure_attempt_capacity(quiz_id: u64) -> Result<(), Error> {
+    let Some(max_attempts) = get_config().max_attempts_per_quiz else {
+        return Ok(());
+    };
+    let attempts = ATTEMPTS.with(|attempts| {
+        attempts
+            .borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .count() as u64
+    });
+    if attempts >= max_attempts {
+        return Err(Error::StorageFull {
+            used: attempts,
+            limit: max_attempts,
+            msg: format!(
+                "quiz with id={} already holds the maximum of {} attempts",
+                quiz_id, max_attempts
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_bytes() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * 65536
+}
+
+// only wasm has a heap to measure
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_bytes() -> u64 {
+    0
+}
+
+// Once this canister has a shard, new quizzes are created there instead,
+// still owned by the caller.
+#[ic_cdk::update]
+async fn create_quiz(payload: QuizPayload) -> Result<Quiz, Error> {
+    create_routed(caller(), payload).await
+}
+
+// how a parent canister creates quizzes for its callers on its shard
+#[ic_cdk::update]
+async fn create_quiz_for(owner: Principal, payload: QuizPayload) -> Result<Quiz, Error> {
+    ensure_controller()?;
+    create_routed(owner, payload).await
+}
+
+async fn create_routed(owner: Principal, payload: QuizPayload) -> Result<Quiz, Error> {
+    let Some(shard) = active_shard() else {
+        return create_quiz_as(owner, payload);
+    };
+    let result: Result<(QuizResult,), _> =
+        ic_cdk::call(shard, "create_quiz_for", (owner, payload)).await;
+    result
+        .map_err(|(_, msg)| Error::CallFailed {
+            canister: shard,
+            msg: format!("couldn't reach shard {}: {}", shard, msg),
+        })?
+        .0
+}
+
+fn create_quiz_as(owner: Principal, payload: QuizPayload) -> Result<Quiz, Error> {
+    if owner == Principal::anonymous() && !anonymous_allowed() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't create quizzes".to_string(),
+        });
+    }
+    let idempotency_key = match &payload.idempotency_key {
+        Some(key) => Some(idempotency_key(owner, key)?),
+        None => None,
+    };
+    if let Some(key) = &idempotency_key {
+        let seen = IDEMPOTENCY_KEYS.with(|keys| keys.borrow().get(key));
+        if let Some((id, seen_at)) = seen {
+            if time().saturating_sub(seen_at) < IDEMPOTENCY_TTL_NS {
+                return get_quiz(id);
+            }
+        }
+    }
+    validate_payload(&payload)?;
+    ensure_capacity()?;
+    take_rate_slot(RateAction::CreateQuiz, owner)?;
+
+    let id = next_quiz_id();
+    if let Some(key) = idempotency_key {
+        IDEMPOTENCY_KEYS.with(|keys| keys.borrow_mut().insert(key, (id, time())));
+    }
+
+    let mut quiz = Quiz::new(id, payload.question, payload.options);
+    quiz.lock_on_first_vote = payload.lock_on_first_vote;
+    quiz.expires_at = payload.expires_at.or_else(|| {
+        get_config()
+            .default_expiry_ns
+            .map(|expiry_ns| quiz.created_at.saturating_add(expiry_ns))
+    });
+    quiz.allow_vote_change = payload.allow_vote_change;
+    quiz.max_total_votes = payload.max_total_votes;
+    quiz.allowed_voters = payload.allowed_voters;
+    quiz.owner = Some(owner);
+    quiz.metadata = payload.metadata;
+    quiz.kind = payload.kind;
+    quiz.allow_anonymous = payload.allow_anonymous;
+    quiz.results_visibility = payload.results_visibility;
+    quiz.tags = payload.tags.map(normalize_tags);
+    quiz.category = payload.category.as_deref().map(normalize_tag);
+    if let Some(max_selections) = payload.max_selections {
+        quiz.set_max_selections(max_selections);
+    }
+    quiz.visibility = payload.visibility;
+    quiz.opens_at = payload.opens_at;
+    quiz.shuffle_per_voter = payload.shuffle_per_voter;
+    quiz.token_gate = payload.token_gate;
+    quiz.time_limit_secs = payload.time_limit_secs;
+    quiz.default_locale = payload.default_locale.as_deref().map(normalize_locale);
+    if payload.draft == Some(true) || payload.opens_at.is_some() {
+        quiz.status = Some(QuizStatus::Draft);
+    }
+    if let Some(indices) = &payload.correct_options {
+        quiz.correct_options = Some(correct_texts(&quiz.options, indices)?);
+    }
+    quiz.extra_questions = extra_questions_of(payload.questions);
+    do_insert(&quiz);
+    schedule_expiry(&quiz);
+    schedule_opening(&quiz);
+    log_event(QuizEventKind::Created, id, owner, quiz.question.clone());
+    Ok(quiz)
+}
+
+// Each payload is created or rejected on its own, in order.
+#[ic_cdk::update]
+fn create_quizzes(payloads: Vec<QuizPayload>) -> Vec<Result<Quiz, Error>> {
+    if payloads.len() > MAX_BATCH_SIZE {
+        return payloads.iter().map(|_| Err(batch_too_large())).collect();
+    }
+    // batches are always created here, shard or not
+    payloads
+        .into_iter()
+        .map(|payload| create_quiz_as(caller(), payload))
+        .collect()
+}
+
+#[ic_cdk::update]
+fn purge_quizzes(ids: Vec<u64>) -> Vec<Result<Quiz, Error>> {
+    if ids.len() > MAX_BATCH_SIZE {
+        return ids.iter().map(|_| Err(batch_too_large())).collect();
+    }
+    ids.into_iter().map(purge_quiz).collect()
+}
+
+fn batch_too_large() -> Error {
+    Error::CapacityExceeded {
+        msg: format!("a batch can have at most {} items", MAX_BATCH_SIZE),
+    }
+}
+
+fn idempotency_key(principal: Principal, key: &str) -> Result<IdempotencyKey, Error> {
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(Error::ValidationFailed {
+            field: "idempotency_key".to_string(),
+            msg: format!(
+                "idempotency key must be between 1 and {} bytes",
+                MAX_IDEMPOTENCY_KEY_LEN
+            ),
+        });
+    }
+    Ok((
+        StorablePrincipal(principal),
+        Blob::try_from(key.as_bytes()).unwrap(),
+    ))
+}
+
+// Runs hourly from a timer set up in `init` and `post_upgrade`.
+fn prune_idempotency_keys() {
+    let now = time();
+    IDEMPOTENCY_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        let expired: Vec<_> = keys
+            .iter()
+            .filter(|(_, (_, seen_at))| now.saturating_sub(*seen_at) >= IDEMPOTENCY_TTL_NS)
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired {
+            keys.remove(&key);
+        }
+    });
+}
+
+fn prune_rate_log() {
+    let now = time();
+    RATE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let expired: Vec<_> = log
+            .iter()
+            .filter(|((_, _, at), _)| now.saturating_sub(*at) >= NANOS_PER_HOUR)
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired {
+            log.remove(&key);
+        }
+    });
+}
+
+// Counts one `action` against the caller's quota, or fails with how long until
+// the oldest action in the window stops counting. Called once every other
+// check has passed, so rejected calls don't use up the quota.
+fn take_rate_slot(action: RateAction, principal: Principal) -> Result<(), Error> {
+    let limits = get_config().rate_limits.unwrap_or_default();
+    let limit = match action {
+        RateAction::CreateQuiz => limits.quizzes_per_hour,
+        RateAction::Answer => limits.answers_per_hour,
+        RateAction::Comment => limits.comments_per_hour,
+    };
+    let Some(limit) = limit.filter(|_| !is_admin(&principal)) else {
+        return Ok(());
+    };
+    let now = time();
+    let from = (
+        StorablePrincipal(principal),
+        action as u8,
+        now.saturating_sub(NANOS_PER_HOUR - 1),
+    );
+    let to = (StorablePrincipal(principal), action as u8, now);
+    RATE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let taken: u64 = log.range(from..=to).map(|(_, count)| count as u64).sum();
+        if taken >= limit {
+            let oldest = log
+                .range(from..=to)
+                .next()
+                .map_or(now, |((_, _, at), _)| at);
+            return Err(Error::RateLimited {
+                retry_after_ns: (oldest + NANOS_PER_HOUR).saturating_sub(now),
+            });
+        }
+        let count = log.get(&to).unwrap_or(0);
+        log.insert(to, count + 1);
+        Ok(())
+    })
+}
+
+// the checks `create_quiz` runs before touching any state
+fn validate_payload(payload: &QuizPayload) -> Result<(), Error> {
+    validate_question("question", &payload.question)?;
+    validate_options_for(payload.kind.unwrap_or_default(), &payload.options)?;
+    if let Some(allowed_voters) = &payload.allowed_voters {
+        if allowed_voters.len() > MAX_ALLOWED_VOTERS {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "at most {} allowed voters are supported",
+                    MAX_ALLOWED_VOTERS
+                ),
+            });
+        }
+    }
+    if let Some(metadata) = &payload.metadata {
+        validate_metadata(metadata)?;
+    }
+    if let Some(questions) = &payload.questions {
+        validate_extra_questions(questions)?;
+    }
+    if let Some(tags) = &payload.tags {
+        validate_tags(tags)?;
+    }
+    if let Some(category) = &payload.category {
+        validate_category(category)?;
+    }
+    if let Some(max_selections) = payload.max_selections {
+        validate_max_selections(max_selections, payload.options.len())?;
+    }
+    if let Some(opens_at) = payload.opens_at {
+        validate_opens_at(opens_at, payload.expires_at, time())?;
+    }
+    if let Some(secs) = payload.time_limit_secs {
+        validate_time_limit(payload.kind.unwrap_or_default(), secs)?;
+    }
+    if let Some(locale) = &payload.default_locale {
+        validate_locale(locale)?;
+    }
+    let correct = match &payload.correct_options {
+        Some(indices) => correct_texts(&payload.options, indices)?,
+        None => Vec::new(),
+    };
+    validate_answer_key(payload.kind.unwrap_or_default(), &correct)
+}
+
+// an empty list is the same as no follow-up questions at all
+fn extra_questions_of(questions: Option<Vec<QuestionPayload>>) -> Option<Vec<Question>> {
+    questions
+        .filter(|questions| !questions.is_empty())
+        .map(|questions| {
+            questions
+                .into_iter()
+                .map(|question| Question::new(question.question, question.options))
+                .collect()
+        })
+}
+
+// the options `indices` point at, rejecting out-of-range and repeated ones
+fn correct_texts(options: &[String], indices: &[u32]) -> Result<Vec<String>, Error> {
+    let mut correct: Vec<String> = Vec::new();
+    for &index in indices {
+        let option = options
+            .get(index as usize)
+            .ok_or_else(|| Error::InvalidInput {
+                msg: format!("correct option index {} is out of range", index),
+            })?;
+        if correct.contains(option) {
+            return Err(Error::InvalidInput {
+                msg: format!("correct option index {} is given more than once", index),
+            });
+        }
+        correct.push(option.clone());
+    }
+    Ok(correct)
+}
+
+fn validate_time_limit(kind: QuizKind, secs: u64) -> Result<(), Error> {
+    if kind != QuizKind::Graded {
+        return Err(Error::InvalidInput {
+            msg: "only graded quizzes have a time limit".to_string(),
+        });
+    }
+    if secs == 0 {
+        return Err(Error::InvalidInput {
+            msg: "time_limit_secs must be greater than zero".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_answer_key(kind: QuizKind, correct: &[String]) -> Result<(), Error> {
+    match kind {
+        QuizKind::Graded if correct.is_empty() => Err(Error::InvalidInput {
+            msg: "a graded quiz needs at least one correct option".to_string(),
+        }),
+        QuizKind::Ranked | QuizKind::OpenText if !correct.is_empty() => Err(Error::InvalidInput {
+            msg: "ranked and open-text quizzes have no correct options".to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Dry run for an import: expects a JSON array of quiz payloads and reports
+// which entries `create_quiz` would reject, without writing anything.
+#[ic_cdk::query]
+fn validate_import_json(json: String) -> ImportValidation {
+    let entries: Vec<serde_json::Value> = match serde_json::from_str(&json) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return ImportValidation {
+                parse_error: Some(e.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+
+    let mut report = ImportValidation::default();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let result = serde_json::from_value::<QuizPayload>(entry)
+            .map_err(|e| Error::InvalidInput {
+                msg: format!("not a quiz payload: {}", e),
+            })
+            .and_then(|payload| validate_payload(&payload));
+        match result {
+            Ok(()) => report.valid_count += 1,
+            Err(e) => report.failures.push((index as u64, e)),
+        }
+    }
+    report
+}
+
+fn ensure_capacity() -> Result<(), Error> {
+    ensure_storage_available()?;
+    let max_quizzes = get_config().max_quizzes;
+    let quiz_count = get_quiz_count();
+    if max_quizzes > 0 && quiz_count >= max_quizzes {
+        return Err(Error::StorageFull {
+            used: quiz_count,
+            limit: max_quizzes,
+            msg: format!(
+                "the canister already holds the maximum of {} quizzes",
+                max_quizzes
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn next_quiz_id() -> u64 {
+    ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(current_value + 1)
+            .unwrap_or_else(|_| panic!("Cannot increment id counter"))
+    })
+}
+
+// A candid-encoded `Vec<Quiz>` for mirroring the canister. Quizzes go out
+// whole, answer keys and invite codes included, so only admins get it.
+#[ic_cdk::query]
+fn dump_quizzes_candid() -> Result<Vec<u8>, Error> {
+    ensure_admin()?;
+    let quizzes: Vec<Quiz> = with_quizzes(|quizzes| quizzes.collect());
+    Ok(Encode!(&quizzes).expect("Cannot encode quizzes"))
+}
+
+// Quizzes in id order, with answer keys and who voted for what, so only
+// admins get them.
+#[ic_cdk::query]
+fn export_quizzes(offset: u64, limit: u64) -> Result<Vec<QuizExport>, Error> {
+    ensure_admin()?;
+    let quizzes: Vec<Quiz> = with_quizzes(|quizzes| {
+        quizzes
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect()
+    });
+    Ok(quizzes.into_iter().map(export_of).collect())
+}
+
+// Streams the whole state out: start with no cursor and keep passing back
+// `next_cursor`. A chunk always holds at least one quiz, so one bigger than
+// `max_bytes` still gets through on its own.
+#[ic_cdk::query]
+fn export_chunk(cursor: Option<u64>, max_bytes: u32) -> Result<ExportChunk, Error> {
+    ensure_admin()?;
+    let max_bytes = max_bytes.min(MAX_EXPORT_CHUNK_BYTES) as usize;
+    let mut exports = Vec::new();
+    let mut size = 0;
+    let mut next_cursor = None;
+    STORAGE.with(|service| {
+        for (id, stored) in service.borrow().range(cursor.unwrap_or(0)..) {
+            let Some(quiz) = stored.into_quiz(id) else {
+                continue;
+            };
+            let export = export_of(quiz);
+            // each record encoded alone, which overestimates its share of the chunk
+            let export_size = Encode!(&export).map(|bytes| bytes.len()).unwrap_or(0);
+            if !exports.is_empty() && size + export_size > max_bytes {
+                next_cursor = Some(id);
+                break;
+            }
+            size += export_size;
+            exports.push(export);
+        }
+    });
+    Ok(ExportChunk {
+        data: Encode!(&exports).expect("Cannot encode quizzes"),
+        next_cursor,
+    })
+}
+
+fn export_of(quiz: Quiz) -> QuizExport {
+    let votes = VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((quiz.id, 0)..=(quiz.id, u64::MAX))
+            .map(|(_, vote)| vote)
+            .collect()
+    });
+    QuizExport { quiz, votes }
+}
+
+// Restores exported quizzes under their own ids, replacing any quiz already
+// there along with everything kept about it. New quizzes are numbered after
+// the highest imported id. Every record is checked before anything is
+// written, so one bad record imports nothing. Returns how many were imported.
+#[ic_cdk::update]
+fn import_quizzes(exports: Vec<QuizExport>) -> Result<u64, Error> {
+    ensure_storage_available()?;
+    ensure_admin()?;
+    for (i, export) in exports.iter().enumerate() {
+        validate_quiz_record(&export.quiz).map_err(|e| match e {
+            Error::ValidationFailed { field, msg } => Error::ValidationFailed {
+                field: format!("exports[{}].{}", i, field),
+                msg,
+            },
+            e => e,
+        })?;
+    }
+
+    let imported = exports.len() as u64;
+    for QuizExport { quiz, votes } in exports {
+        let id = quiz.id;
+        clear_quiz_state(id);
+        do_insert(&quiz);
+        for vote in votes {
+            append_vote(id, vote);
+        }
+        schedule_expiry(&quiz);
+        schedule_opening(&quiz);
+        ID_COUNTER.with(|counter| {
+            if *counter.borrow().get() <= id {
+                counter
+                    .borrow_mut()
+                    .set(id + 1)
+                    .unwrap_or_else(|_| panic!("Cannot bump id counter"));
+            }
+        });
+    }
+    Ok(imported)
+}
+
+// The create-path checks, for a quiz that arrives whole rather than as a
+// payload.
+fn validate_quiz_record(quiz: &Quiz) -> Result<(), Error> {
+    let kind = quiz.kind.unwrap_or_default();
+    validate_question("question", &quiz.question)?;
+    validate_options_for(kind, &quiz.options)?;
+    if let Some(questions) = &quiz.extra_questions {
+        let payloads: Vec<QuestionPayload> = questions
+            .iter()
+            .map(|question| QuestionPayload {
+                question: question.text.clone(),
+                options: question.options.clone(),
+            })
+            .collect();
+        validate_extra_questions(&payloads)?;
+    }
+    validate_tags(quiz.tags())?;
+    if let Some(category) = &quiz.category {
+        validate_category(category)?;
+    }
+    if let Some(metadata) = &quiz.metadata {
+        validate_metadata(metadata)?;
+    }
+    let correct = quiz.correct_options.as_deref().unwrap_or_default();
+    if let Some(unknown) = correct.iter().find(|option| !quiz.options.contains(option)) {
+        return Err(Error::ValidationFailed {
+            field: "correct_options".to_string(),
+            msg: format!("'{}' is not one of the options", unknown),
+        });
+    }
+    validate_answer_key(kind, correct)
+}
+
+// Replaces every quiz with the ones in `blob`, under fresh ids. Tallies come
+// along in `answers`, but the vote log can't follow the old ids, so it's
+// cleared and per-voter history starts over.
+#[ic_cdk::update]
+fn load_quizzes_candid(blob: Vec<u8>) -> Result<u64, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    let quizzes = Decode!(&blob, Vec<Quiz>).map_err(|e| Error::InvalidInput {
+        msg: format!("blob is not a candid Vec<Quiz>: {}", e),
+    })?;
+    for (i, quiz) in quizzes.iter().enumerate() {
+        validate_quiz_record(quiz).map_err(|e| match e {
+            Error::ValidationFailed { field, msg } => Error::ValidationFailed {
+                field: format!("quizzes[{}].{}", i, field),
+                msg,
+            },
+            e => e,
+        })?;
+    }
+    let max_quizzes = get_config().max_quizzes;
+    let loaded = quizzes.len() as u64;
+    if max_quizzes > 0 && loaded > max_quizzes {
+        return Err(Error::StorageFull {
+            used: loaded,
+            limit: max_quizzes,
+            msg: format!("the canister holds at most {} quizzes", max_quizzes),
+        });
+    }
+
+    let ids: Vec<u64> = STORAGE.with(|service| service.borrow().iter().map(|(id, _)| id).collect());
+    for id in ids {
+        let removed = STORAGE.with(|service| service.borrow_mut().remove(&id));
+        if let Some(StoredQuiz::Quiz(quiz)) = removed {
+            unindex(&quiz);
+        }
+        clear_quiz_state(id);
+    }
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let keys: Vec<(u64, u64)> = log.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            log.remove(&key);
+        }
+    });
+
+    for mut quiz in quizzes {
+        quiz.id = next_quiz_id();
+        do_insert(&quiz);
+        schedule_expiry(&quiz);
+        schedule_opening(&quiz);
+    }
+    Ok(loaded)
+}
+
+fn do_insert(quiz: &Quiz) {
+    let previous = STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(quiz.id, StoredQuiz::Quiz(Box::new(quiz.clone())))
+    });
+    if let Some(StoredQuiz::Quiz(previous)) = previous {
+        unindex(&previous);
+    }
+    index(quiz);
+    certify(quiz);
+}
+
+// Keeps the quiz's results hash in the certified tree, keyed by its id in
+// big-endian bytes, and the tree's root in the canister's certified data.
+fn certify(quiz: &Quiz) {
+    let digest = results_digest(&sorted_results(quiz));
+    CERTIFIED_RESULTS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert(quiz.id.to_be_bytes().to_vec(), digest);
+        set_certified_data(&labeled_hash(CERTIFIED_RESULTS_LABEL, &tree.root_hash()));
+    });
+}
+
+fn uncertify(id: u64) {
+    CERTIFIED_RESULTS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.delete(&id.to_be_bytes());
+        set_certified_data(&labeled_hash(CERTIFIED_RESULTS_LABEL, &tree.root_hash()));
+    });
+}
+
+fn index(quiz: &Quiz) {
+    if let Some(owner) = quiz.owner {
+        OWNER_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .insert((StorablePrincipal(owner), quiz.id), ())
+        });
+    }
+    // tags and words feed public listings, so only listed quizzes go in
+    if !quiz.is_listed() {
+        return;
+    }
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in quiz.tags() {
+            index.insert((hashed_key(tag), quiz.id), ());
+        }
+    });
+    WORD_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for word in quiz.words() {
+            index.insert((hashed_key(&word), quiz.id), ());
+        }
+    });
+}
+
+fn unindex(quiz: &Quiz) {
+    if let Some(owner) = quiz.owner {
+        OWNER_INDEX.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&(StorablePrincipal(owner), quiz.id))
+        });
+    }
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in quiz.tags() {
+            index.remove(&(hashed_key(tag), quiz.id));
+        }
+    });
+    WORD_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for word in quiz.words() {
+            index.remove(&(hashed_key(&word), quiz.id));
+        }
+    });
+}
+
+fn hashed_key(text: &str) -> Blob<32> {
+    Blob::try_from(Sha256::digest(text.as_bytes()).as_slice()).unwrap()
+}
+
+// lowercased runs of letters and digits
+fn words_of(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// for canisters upgraded from before the indexes existed
+fn index_all() {
+    with_quizzes(|quizzes| {
+        for quiz in quizzes {
+            index(&quiz);
+        }
+    });
+}
+
+// a page of quiz ids, in id order, out of an index range
+fn indexed_page(ids: Vec<u64>, offset: u64, limit: u64) -> Page {
+    let limit = limit.min(MAX_PAGE_SIZE);
+    let total = ids.len() as u64;
+    let items = ids
+        .iter()
+        .skip(offset.min(total) as usize)
+        .take(limit as usize)
+        .filter_map(_get_quiz)
+        .map(Quiz::redacted)
+        .collect();
+    let end = offset.saturating_add(limit);
+    Page {
+        items,
+        total,
+        next_offset: (end < total).then_some(end),
+    }
+}
+
+#[ic_cdk::query]
+fn list_quizzes_by_owner(owner: Principal, offset: u64, limit: u64) -> Page {
+    // owners and admins also see the owner's unlisted and private quizzes
+    let show_all = caller() == owner || is_admin(&caller());
+    let ids = OWNER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((StorablePrincipal(owner), 0)..=(StorablePrincipal(owner), u64::MAX))
+            .map(|((_, id), _)| id)
+            .filter(|id| show_all || _get_quiz(id).is_some_and(|quiz| quiz.is_listed()))
+            .collect()
+    });
+    indexed_page(ids, offset, limit)
+}
+
+#[ic_cdk::query]
+fn list_quizzes_by_tag(tag: String, offset: u64, limit: u64) -> Page {
+    let key = hashed_key(&normalize_tag(&tag));
+    let ids = TAG_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    indexed_page(ids, offset, limit)
+}
+
+fn record_vote(quiz_id: u64, question: u32, voter: Principal, option: &str, weight: u32) {
+    append_vote(
+        quiz_id,
+        Vote {
+            voter,
+            option: option.to_string(),
+            weight,
+            timestamp: time(),
+            question: Some(question),
+        },
+    );
+}
+
+fn log_event(kind: QuizEventKind, quiz_id: u64, actor: Principal, details: String) {
+    let event = QuizEvent {
+        kind,
+        quiz_id,
+        actor,
+        timestamp: time(),
+        details,
+    };
+    let seq = EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let seq = events.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        events.insert(seq, event);
+        seq
+    });
+    EVENTS_BY_QUIZ.with(|index| index.borrow_mut().insert((quiz_id, seq), ()));
+}
+
+// Oldest first. The log names voters, so it's for the quiz's managers and
+// admins; once a quiz is deleted only admins can read its events.
+#[ic_cdk::query]
+fn get_events(quiz_id: u64, offset: u64, limit: u64) -> Result<Vec<QuizEvent>, Error> {
+    match _get_quiz(&quiz_id) {
+        Some(quiz) => ensure_can_moderate(&quiz)?,
+        None => ensure_admin()?,
+    }
+    let seqs: Vec<u64> = EVENTS_BY_QUIZ.with(|index| {
+        index
+            .borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|((_, seq), _)| seq)
+            .collect()
+    });
+    Ok(EVENTS.with(|events| {
+        let events = events.borrow();
+        seqs.iter().filter_map(|seq| events.get(seq)).collect()
+    }))
+}
+
+#[ic_cdk::query]
+fn get_all_events(offset: u64, limit: u64) -> Result<Vec<QuizEvent>, Error> {
+    ensure_admin()?;
+    Ok(EVENTS.with(|events| {
+        events
+            .borrow()
+            .range(offset..)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|(_, event)| event)
+            .collect()
+    }))
+}
+
+// Appends to the quiz's change journal, dropping the oldest entries past
+// MAX_HISTORY_ENTRIES. Every entry also goes to the audit log.
+fn journal(quiz_id: u64, description: String) {
+    let description = match description.char_indices().nth(MAX_CHANGE_DESCRIPTION_LEN) {
+        Some((end, _)) => format!("{}...", &description[..end]),
+        None => description,
+    };
+    log_event(
+        QuizEventKind::Updated,
+        quiz_id,
+        caller(),
+        description.clone(),
+    );
+    let entry = ChangeEntry {
+        timestamp: time(),
+        caller: caller(),
+        description,
+    };
+    HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let seq = match history.iter_upper_bound(&(quiz_id, u64::MAX)).next() {
+            Some(((last_quiz_id, last_seq), _)) if last_quiz_id == quiz_id => last_seq + 1,
+            _ => 0,
+        };
+        history.insert((quiz_id, seq), entry);
+        if seq >= MAX_HISTORY_ENTRIES {
+            let stale: Vec<(u64, u64)> = history
+                .range((quiz_id, 0)..(quiz_id, seq + 1 - MAX_HISTORY_ENTRIES))
+                .map(|(key, _)| key)
+                .collect();
+            for key in stale {
+                history.remove(&key);
+            }
+        }
+    });
+}
+
+fn clear_history(quiz_id: u64) {
+    HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let keys: Vec<(u64, u64)> = history
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            history.remove(&key);
+        }
+    });
+}
+
+// anyone who can see the quiz may comment on it, open or closed
+#[ic_cdk::update]
+fn add_comment(quiz_id: u64, text: String) -> Result<Comment, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    let author = caller();
+    if author == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't comment".to_string(),
+        });
+    }
+    if is_banned(author) {
+        return Err(Error::Unauthorized {
+            msg: "caller is banned from commenting".to_string(),
+        });
+    }
+    ensure_access(&quiz, &author, None)?;
+    ensure_storage_available()?;
+    let text = text.trim().to_string();
+    if text.is_empty() || text.chars().count() > MAX_COMMENT_LEN {
+        return Err(Error::ValidationFailed {
+            field: "text".to_string(),
+            msg: format!(
+                "a comment must be between 1 and {} characters",
+                MAX_COMMENT_LEN
+            ),
+        });
+    }
+    take_rate_slot(RateAction::Comment, author)?;
+
+    let id = COMMENT_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(current_value + 1)
+            .unwrap_or_else(|_| panic!("Cannot increment comment id counter"))
+    });
+    let comment = Comment {
+        id,
+        quiz_id,
+        author,
+        text,
+        created_at: time(),
+    };
+    COMMENTS.with(|comments| comments.borrow_mut().insert((quiz_id, id), comment.clone()));
+    Ok(comment)
+}
+
+// by its author, or by anyone who can moderate the quiz
+#[ic_cdk::update]
+fn delete_comment(quiz_id: u64, comment_id: u64) -> Result<Comment, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    let comment = COMMENTS
+        .with(|comments| comments.borrow().get(&(quiz_id, comment_id)))
+        .ok_or_else(|| Error::NotFound {
+            resource: Resource::Comment,
+            msg: format!(
+                "quiz with id={} has no comment with id={}",
+                quiz_id, comment_id
+            ),
+        })?;
+    if comment.author != caller() {
+        ensure_can_moderate(&quiz)?;
+    }
+    COMMENTS.with(|comments| comments.borrow_mut().remove(&(quiz_id, comment_id)));
+    Ok(comment)
+}
+
+// oldest first
+#[ic_cdk::query]
+fn get_comments(quiz_id: u64, offset: u64, limit: u64) -> Result<Vec<Comment>, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    ensure_access(&quiz, &caller(), None)?;
+    Ok(COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|(_, comment)| comment)
+            .collect()
+    }))
+}
+
+fn clear_comments(quiz_id: u64) {
+    COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        let keys: Vec<(u64, u64)> = comments
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            comments.remove(&key);
+        }
+    });
+}
+
+fn record_attempt(quiz_id: u64, question: u32, principal: Principal, option: &str) {
+    let attempt = Attempt {
+        quiz_id,
+        principal,
+        option: option.to_string(),
+        timestamp: time(),
+        question: Some(question),
+    };
+    ATTEMPTS.with(|attempts| {
+        let mut attempts = attempts.borrow_mut();
+        let seq = match attempts.iter_upper_bound(&(quiz_id, u64::MAX)).next() {
+            Some(((last_quiz_id, last_seq), _)) if last_quiz_id == quiz_id => last_seq + 1,
+            _ => 0,
+        };
+        RECEIPTS.with(|receipts| {
+            receipts
+                .borrow_mut()
+                .insert(receipt_key(&attempt_digest(&attempt)), (quiz_id, seq))
+        });
+        attempts.insert((quiz_id, seq), attempt);
+    });
+}
+
+fn attempt_digest(attempt: &Attempt) -> Hash {
+    receipt_digest(
+        attempt.quiz_id,
+        &attempt.principal,
+        attempt.question.unwrap_or(0),
+        &attempt.option,
+        attempt.timestamp,
+    )
+}
+
+fn receipt_digest(
+    quiz_id: u64,
+    voter: &Principal,
+    question: u32,
+    option: &str,
+    timestamp: u64,
+) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(b"vote-receipt");
+    hasher.update(quiz_id.to_be_bytes());
+    hasher.update([voter.as_slice().len() as u8]);
+    hasher.update(voter.as_slice());
+    hasher.update(question.to_be_bytes());
+    hasher.update(timestamp.to_be_bytes());
+    hasher.update(option.as_bytes());
+    hasher.finalize().into()
+}
+
+fn receipt_key(digest: &Hash) -> Blob<32> {
+    Blob::try_from(digest.as_slice()).unwrap()
+}
+
+// the receipt for a vote that's in the vote log
+fn receipt_for(quiz: &Quiz, vote: &Vote) -> VoteReceipt {
+    let question = vote.question_index();
+    VoteReceipt {
+        quiz_id: quiz.id,
+        question,
+        option_id: quiz
+            .option_id_of(question, &vote.option)
+            .unwrap_or(u32::MAX),
+        timestamp: vote.timestamp,
+        receipt_hash: receipt_digest(quiz.id, &vote.voter, question, &vote.option, vote.timestamp)
+            .to_vec(),
+    }
+}
+
+// receipts for the caller's answers that currently count, first question first
+#[ic_cdk::query]
+fn get_my_vote(quiz_id: u64) -> Result<Vec<VoteReceipt>, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    let voter = caller();
+    if voter == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous ballots can't be looked up".to_string(),
+        });
+    }
+    let mut receipts: Vec<VoteReceipt> = votes_of(quiz_id, &voter)
+        .iter()
+        .map(|(_, vote)| receipt_for(&quiz, vote))
+        .collect();
+    if receipts.is_empty() {
+        return Err(Error::NotFound {
+            resource: Resource::Vote,
+            msg: format!("caller hasn't answered quiz with id={}", quiz_id),
+        });
+    }
+    receipts.sort_by_key(|receipt| receipt.question);
+    Ok(receipts)
+}
+
+// Anyone holding a receipt can check it; nothing about the voter is returned.
+#[ic_cdk::query]
+fn verify_receipt(receipt: VoteReceipt) -> ReceiptStatus {
+    let attempt = <[u8; 32]>::try_from(receipt.receipt_hash.as_slice())
+        .ok()
+        .and_then(|digest| RECEIPTS.with(|receipts| receipts.borrow().get(&receipt_key(&digest))))
+        .and_then(|key| ATTEMPTS.with(|attempts| attempts.borrow().get(&key)));
+    let Some(attempt) = attempt else {
+        return ReceiptStatus {
+            recorded: false,
+            counted: false,
+        };
+    };
+    let question = attempt.question.unwrap_or(0);
+    let recorded = attempt.quiz_id == receipt.quiz_id
+        && attempt.timestamp == receipt.timestamp
+        && question == receipt.question
+        && _get_quiz(&attempt.quiz_id)
+            .and_then(|quiz| quiz.option_id_of(question, &attempt.option))
+            == Some(receipt.option_id);
+    let counted = recorded
+        && votes_of(attempt.quiz_id, &attempt.principal)
+            .iter()
+            .any(|(_, vote)| {
+                vote.question_index() == question
+                    && vote.option == attempt.option
+                    && vote.timestamp == attempt.timestamp
+            });
+    ReceiptStatus { recorded, counted }
+}
+
+// for canisters upgraded from before receipts were indexed
+fn index_receipts() {
+    ATTEMPTS.with(|attempts| {
+        RECEIPTS.with(|receipts| {
+            let mut receipts = receipts.borrow_mut();
+            for (key, attempt) in attempts.borrow().iter() {
+                receipts.insert(receipt_key(&attempt_digest(&attempt)), key);
+            }
+        })
+    });
+}
+
+fn clear_leaderboard(quiz_id: u64) {
+    LEADERBOARD.with(|board| {
+        let mut board = board.borrow_mut();
+        let keys: Vec<(u64, StorablePrincipal)> = board
+            .range((quiz_id, StorablePrincipal::default())..)
+            .take_while(|((id, _), _)| *id == quiz_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            board.remove(&key);
+        }
+    });
+}
+
+fn clear_attempts(quiz_id: u64) {
+    ATTEMPTS.with(|attempts| {
+        let mut attempts = attempts.borrow_mut();
+        let stale: Vec<((u64, u64), Attempt)> =
+            attempts.range((quiz_id, 0)..=(quiz_id, u64::MAX)).collect();
+        for (key, attempt) in stale {
+            RECEIPTS.with(|receipts| {
+                receipts
+                    .borrow_mut()
+                    .remove(&receipt_key(&attempt_digest(&attempt)))
+            });
+            attempts.remove(&key);
+        }
+    });
+}
+
+// scans every quiz's attempts, since they're keyed by quiz; anonymous
+// ballots belong to nobody, so an anonymous caller gets none
+#[ic_cdk::query]
+fn get_my_attempts() -> Vec<Attempt> {
+    let me = caller();
+    if me == Principal::anonymous() {
+        return Vec::new();
+    }
+    ATTEMPTS.with(|attempts| {
+        attempts
+            .borrow()
+            .iter()
+            .map(|(_, attempt)| attempt)
+            .filter(|attempt| attempt.principal == me)
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_quiz_attempts(quiz_id: u64) -> Result<Vec<Attempt>, Error> {
+    ensure_can_manage(&get_quiz(quiz_id)?)?;
+    Ok(ATTEMPTS.with(|attempts| {
+        attempts
+            .borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(_, attempt)| attempt)
+            .collect()
+    }))
+}
+
+#[ic_cdk::query]
+fn get_quiz_history(id: u64) -> Result<Vec<ChangeEntry>, Error> {
+    get_readable_quiz(id)?;
+    Ok(HISTORY.with(|history| {
+        history
+            .borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(_, entry)| entry)
+            .collect()
+    }))
+}
+
+// e.g. "question edited; options added: 'Maybe'; options removed: 'No'"
+fn describe_edit(quiz: &Quiz, question: &str, options: &[String]) -> String {
+    let mut changes = Vec::new();
+    if quiz.question != question {
+        changes.push("question edited".to_string());
+    }
+    let quoted = |list: Vec<&String>| {
+        list.iter()
+            .map(|option| format!("'{}'", option))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let added: Vec<&String> = options
+        .iter()
+        .filter(|option| !quiz.options.contains(option))
+        .collect();
+    let removed: Vec<&String> = quiz
+        .options
+        .iter()
+        .filter(|option| !options.contains(option))
+        .collect();
+    if !added.is_empty() {
+        changes.push(format!("options added: {}", quoted(added)));
+    }
+    if !removed.is_empty() {
+        changes.push(format!("options removed: {}", quoted(removed)));
+    }
+    if changes.is_empty() {
+        "settings updated".to_string()
+    } else {
+        changes.join("; ")
+    }
+}
+
+fn append_vote(quiz_id: u64, vote: Vote) {
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let seq = match log.iter_upper_bound(&(quiz_id, u64::MAX)).next() {
+            Some(((last_quiz_id, last_seq), _)) if last_quiz_id == quiz_id => last_seq + 1,
+            _ => 0,
+        };
+        log.insert((quiz_id, seq), vote);
+    });
+}
+
+#[ic_cdk::update]
+fn update_quiz(id: u64, payload: QuizPayload) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let quiz_option: Option<Quiz> = _get_quiz(&id);
+
+    match quiz_option {
+        Some(mut quiz) => {
+            ensure_can_manage(&quiz)?;
+            quiz.ensure_editable()?;
+            validate_question("question", &payload.question)?;
+            validate_options_for(
+                payload.kind.or(quiz.kind).unwrap_or_default(),
+                &payload.options,
+            )?;
+            if let Some(metadata) = &payload.metadata {
+                validate_metadata(metadata)?;
+            }
+            if let Some(questions) = &payload.questions {
+                validate_extra_questions(questions)?;
+            }
+            if let Some(tags) = &payload.tags {
+                validate_tags(tags)?;
+            }
+            if let Some(category) = &payload.category {
+                validate_category(category)?;
+            }
+            if let Some(max_selections) = payload.max_selections {
+                validate_max_selections(max_selections, payload.options.len())?;
+            }
+            if let Some(opens_at) = payload.opens_at {
+                quiz.ensure_schedulable()?;
+                validate_opens_at(opens_at, payload.expires_at.or(quiz.expires_at), time())?;
+            }
+            if let Some(secs) = payload.time_limit_secs {
+                validate_time_limit(payload.kind.or(quiz.kind).unwrap_or_default(), secs)?;
+            }
+            if let Some(locale) = &payload.default_locale {
+                validate_locale(locale)?;
+            }
+            let kind = payload.kind.or(quiz.kind).unwrap_or_default();
+            let correct = match &payload.correct_options {
+                Some(indices) => correct_texts(&payload.options, indices)?,
+                None => quiz
+                    .correct_options
+                    .iter()
+                    .flatten()
+                    .filter(|option| payload.options.contains(option))
+                    .cloned()
+                    .collect(),
+            };
+            validate_answer_key(kind, &correct)?;
+
+            let mut answers = BTreeMap::new();
+
+            for option in &payload.options {
+                answers.insert(String::from(option), 0);
+            }
+            let description = describe_edit(&quiz, &payload.question, &payload.options);
+            quiz.question = payload.question;
+            quiz.options = payload.options;
+            quiz.retain_option_colors();
+            quiz.sync_option_details();
+            quiz.answers = answers;
+            if payload.questions.is_some() {
+                quiz.extra_questions = extra_questions_of(payload.questions);
+            } else {
+                quiz.reset_tallies();
+            }
+            if let Some(lock_on_first_vote) = payload.lock_on_first_vote {
+                quiz.lock_on_first_vote = Some(lock_on_first_vote);
+            }
+            if payload.expires_at.is_some() {
+                quiz.expires_at = payload.expires_at;
+            }
+            if let Some(allow_vote_change) = payload.allow_vote_change {
+                quiz.allow_vote_change = Some(allow_vote_change);
+            }
+            if payload.max_total_votes.is_some() {
+                quiz.max_total_votes = payload.max_total_votes;
+            }
+            if payload.metadata.is_some() {
+                quiz.metadata = payload.metadata;
+            }
+            if payload.allow_anonymous.is_some() {
+                quiz.allow_anonymous = payload.allow_anonymous;
+            }
+            if payload.results_visibility.is_some() {
+                quiz.results_visibility = payload.results_visibility;
+            }
+            if let Some(tags) = payload.tags {
+                quiz.tags = Some(normalize_tags(tags));
+            }
+            if let Some(category) = &payload.category {
+                quiz.category = Some(normalize_tag(category));
+            }
+            if let Some(max_selections) = payload.max_selections {
+                quiz.set_max_selections(max_selections);
+            }
+            if payload.visibility.is_some() {
+                quiz.visibility = payload.visibility;
+            }
+            if payload.opens_at.is_some() {
+                quiz.opens_at = payload.opens_at;
+            }
+            if payload.shuffle_per_voter.is_some() {
+                quiz.shuffle_per_voter = payload.shuffle_per_voter;
+            }
+            if payload.time_limit_secs.is_some() {
+                quiz.time_limit_secs = payload.time_limit_secs;
+            }
+            if let Some(locale) = &payload.default_locale {
+                quiz.set_default_locale(normalize_locale(locale));
+            }
+            if payload.token_gate.is_some() {
+                quiz.token_gate = payload.token_gate;
+            }
+            quiz.kind = Some(kind);
+            quiz.correct_options = (!correct.is_empty()).then_some(correct);
+            quiz.updated_at = Some(time());
+            // the tallies start over, so the votes behind them go too
+            clear_votes(id);
+            do_insert(&quiz);
+            if payload.expires_at.is_some() {
+                schedule_expiry(&quiz);
+            }
+            if payload.opens_at.is_some() {
+                schedule_opening(&quiz);
+            }
+            journal(id, description);
+            Ok(quiz)
+        }
+        None => Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!("couldn't update a quiz with id={}. quiz not found", id),
+        }),
+    }
+}
+
+// `update_quiz` without the reset: options that stay keep their counts and
+// votes, and only removed options lose theirs. Follow-up questions are left
+// alone.
+#[ic_cdk::update]
+fn patch_quiz(id: u64, payload: UpdateQuizPayload) -> Result<PatchResult, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    quiz.ensure_editable()?;
+    let reschedule_expiry = payload.expires_at.is_some();
+    let reschedule_opening = payload.opens_at.is_some();
+    let Patched {
+        description,
+        removed,
+        discarded_votes,
+    } = apply_patch(&mut quiz, payload)?;
+
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let stale: Vec<(u64, u64)> = log
+            .range((id, 0)..=(id, u64::MAX))
+            .filter(|(_, vote)| vote.question_index() == 0 && removed.contains(&vote.option))
+            .map(|(key, _)| key)
+            .collect();
+        for key in stale {
+            log.remove(&key);
+        }
+    });
+    do_insert(&quiz);
+    if reschedule_expiry {
+        schedule_expiry(&quiz);
+    }
+    if reschedule_opening {
+        schedule_opening(&quiz);
+    }
+    journal(id, description);
+    Ok(PatchResult {
+        quiz,
+        discarded_votes,
+    })
+}
+
+// what `apply_patch` changed, for the caller to clean up after
+struct Patched {
+    description: String,
+    removed: Vec<String>,
+    discarded_votes: u64,
+}
+
+// Validates the payload and applies it to `quiz` in memory; nothing is stored.
+fn apply_patch(quiz: &mut Quiz, payload: UpdateQuizPayload) -> Result<Patched, Error> {
+    if let Some(question) = &payload.question {
+        validate_question("question", question)?;
+    }
+    if let Some(options) = &payload.options {
+        validate_options_for(quiz.kind.unwrap_or_default(), options)?;
+    }
+    if let Some(metadata) = &payload.metadata {
+        validate_metadata(metadata)?;
+    }
+    if let Some(tags) = &payload.tags {
+        validate_tags(tags)?;
+    }
+    if let Some(category) = &payload.category {
+        validate_category(category)?;
+    }
+    let question = payload.question.unwrap_or_else(|| quiz.question.clone());
+    let options = payload.options.unwrap_or_else(|| quiz.options.clone());
+    if let Some(max_selections) = payload.max_selections {
+        validate_max_selections(max_selections, options.len())?;
+    }
+    if let Some(opens_at) = payload.opens_at {
+        quiz.ensure_schedulable()?;
+        validate_opens_at(opens_at, payload.expires_at.or(quiz.expires_at), time())?;
+    }
+    if let Some(secs) = payload.time_limit_secs {
+        validate_time_limit(quiz.kind.unwrap_or_default(), secs)?;
+    }
+    if let Some(locale) = &payload.default_locale {
+        validate_locale(locale)?;
+    }
+    let correct = match &payload.correct_options {
+        Some(indices) => correct_texts(&options, indices)?,
+        None => quiz
+            .correct_options
+            .iter()
+            .flatten()
+            .filter(|option| options.contains(option))
+            .cloned()
+            .collect(),
+    };
+    validate_answer_key(quiz.kind.unwrap_or_default(), &correct)?;
+
+    let description = describe_edit(quiz, &question, &options);
+    let removed: Vec<String> = quiz
+        .options
+        .iter()
+        .filter(|option| !options.contains(option))
+        .cloned()
+        .collect();
+    let discarded_votes: u64 = removed
+        .iter()
+        .map(|option| *quiz.answers.get(option).unwrap_or(&0) as u64)
+        .sum();
+    quiz.answers = options
+        .iter()
+        .map(|option| (option.clone(), *quiz.answers.get(option).unwrap_or(&0)))
+        .collect();
+    quiz.question = question;
+    quiz.options = options;
+    quiz.correct_options = (!correct.is_empty()).then_some(correct);
+    quiz.retain_option_colors();
+    quiz.sync_option_details();
+    if let Some(lock_on_first_vote) = payload.lock_on_first_vote {
+        quiz.lock_on_first_vote = Some(lock_on_first_vote);
+    }
+    if payload.expires_at.is_some() {
+        quiz.expires_at = payload.expires_at;
+    }
+    if let Some(allow_vote_change) = payload.allow_vote_change {
+        quiz.allow_vote_change = Some(allow_vote_change);
+    }
+    if payload.max_total_votes.is_some() {
+        quiz.max_total_votes = payload.max_total_votes;
+    }
+    if payload.metadata.is_some() {
+        quiz.metadata = payload.metadata;
+    }
+    if payload.allow_anonymous.is_some() {
+        quiz.allow_anonymous = payload.allow_anonymous;
+    }
+    if payload.results_visibility.is_some() {
+        quiz.results_visibility = payload.results_visibility;
+    }
+    if let Some(tags) = payload.tags {
+        quiz.tags = Some(normalize_tags(tags));
+    }
+    if let Some(category) = &payload.category {
+        quiz.category = Some(normalize_tag(category));
+    }
+    if let Some(max_selections) = payload.max_selections {
+        quiz.set_max_selections(max_selections);
+    }
+    if payload.visibility.is_some() {
+        quiz.visibility = payload.visibility;
+    }
+    if payload.opens_at.is_some() {
+        quiz.opens_at = payload.opens_at;
+    }
+    if payload.shuffle_per_voter.is_some() {
+        quiz.shuffle_per_voter = payload.shuffle_per_voter;
+    }
+    if payload.token_gate.is_some() {
+        quiz.token_gate = payload.token_gate;
+    }
+    if payload.time_limit_secs.is_some() {
+        quiz.time_limit_secs = payload.time_limit_secs;
+    }
+    if let Some(locale) = &payload.default_locale {
+        quiz.set_default_locale(normalize_locale(locale));
+    }
+    quiz.updated_at = Some(time());
+    Ok(Patched {
+        description,
+        removed,
+        discarded_votes,
+    })
+}
+
+// A fresh copy of quiz `id` owned by the caller, with zeroed tallies.
+// `overrides` are applied before the copy is stored, so a bad override
+// leaves nothing behind.
+#[ic_cdk::update]
+fn clone_quiz(id: u64, overrides: Option<UpdateQuizPayload>) -> Result<Quiz, Error> {
+    let source = get_quiz(id)?;
+    ensure_access(&source, &caller(), None)?;
+    ensure_capacity()?;
+    take_rate_slot(RateAction::CreateQuiz, caller())?;
+
+    let mut clone = source.fork(next_quiz_id(), source.options.clone());
+    withhold_answer_key(&source, &mut clone);
+    clone.max_total_votes = source.max_total_votes;
+    clone.extra_questions = source.extra_questions.clone();
+    clone.reset_tallies();
+    if let Some(overrides) = overrides {
+        if overrides.opens_at.is_some() {
+            clone.status = Some(QuizStatus::Draft);
+        }
+        apply_patch(&mut clone, overrides)?;
+    }
+    do_insert(&clone);
+    schedule_expiry(&clone);
+    schedule_opening(&clone);
+    journal(clone.id, format!("cloned from quiz {}", id));
+    log_event(
+        QuizEventKind::Created,
+        clone.id,
+        caller(),
+        clone.question.clone(),
+    );
+    Ok(clone)
+}
+
+// The answer key only goes along for someone who could already read it;
+// anyone else gets a copy without one.
+fn withhold_answer_key(source: &Quiz, clone: &mut Quiz) {
+    if ensure_can_manage(source).is_err() {
+        clone.correct_options = None;
+    }
+}
+
+fn clear_votes(quiz_id: u64) {
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let keys: Vec<(u64, u64)> = log
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            log.remove(&key);
+        }
+    });
+    RANKED_BALLOTS.with(|ballots| {
+        let mut ballots = ballots.borrow_mut();
+        let keys: Vec<(u64, StorablePrincipal)> = ballots
+            .range((quiz_id, StorablePrincipal::default())..)
+            .take_while(|((id, _), _)| *id == quiz_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            ballots.remove(&key);
+        }
+    });
+    let authors: Vec<Principal> = text_responses(quiz_id)
+        .into_iter()
+        .map(|(principal, _)| principal)
+        .collect();
+    TEXT_RESPONSES.with(|responses| {
+        let mut responses = responses.borrow_mut();
+        for principal in authors {
+            responses.remove(&(quiz_id, StorablePrincipal(principal)));
+        }
+    });
+}
+
+// The vote log is the source of truth; `answers` is a cache of it.
+#[ic_cdk::update]
+fn rebuild_answer_counts(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    let mut quiz = get_quiz(id)?;
+
+    quiz.reset_tallies();
+    VOTE_LOG.with(|log| {
+        for (_, vote) in log.borrow().range((id, 0)..=(id, u64::MAX)) {
+            let count = quiz
+                .tallies_mut(vote.question_index())
+                .and_then(|answers| answers.get_mut(&vote.option));
+            if let Some(count) = count {
+                *count = count.saturating_add(vote.weight);
+            }
+        }
+    });
+
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "answer counts rebuilt from the vote log".to_string());
+    Ok(quiz)
+}
+
+// Merges options that collide under the current normalization into the
+// trimmed text of the first one, summing their counts and re-pointing the
+// vote log, ranked ballots, correct option and colors at it. A voter who
+// picked two of the merged options keeps one vote for the result.
+#[ic_cdk::update]
+fn normalize_options(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+
+    // (normalized key, canonical text), in original option order
+    let mut canonical: Vec<(String, String)> = Vec::new();
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    for option in &quiz.options {
+        let key = normalize_option(option);
+        let target = match canonical.iter().find(|(existing, _)| *existing == key) {
+            Some((_, text)) => text.clone(),
+            None => {
+                let text = option.trim().to_string();
+                canonical.push((key, text.clone()));
+                text
+            }
+        };
+        renamed.insert(option.clone(), target);
+    }
+    if canonical.len() < 2 {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "normalizing quiz with id={} would leave fewer than two options",
+                id
+            ),
+        });
+    }
+
+    let mut answers: BTreeMap<String, u32> = BTreeMap::new();
+    for (option, count) in &quiz.answers {
+        let target = renamed.get(option).unwrap_or(option).clone();
+        let total = answers.entry(target).or_insert(0);
+        *total = total.saturating_add(*count);
+    }
+    quiz.options = canonical.into_iter().map(|(_, text)| text).collect();
+    quiz.answers = answers;
+    // merged options keep the details of the first one
+    for detail in quiz.option_details.iter_mut().flatten() {
+        if let Some(target) = renamed.get(&detail.label) {
+            detail.label = target.clone();
+        }
+    }
+    quiz.sync_option_details();
+    if let Some(correct) = quiz.correct_options.take() {
+        let mut remapped: Vec<String> = Vec::new();
+        for option in correct {
+            let target = renamed.get(&option).cloned().unwrap_or(option);
+            if !remapped.contains(&target) {
+                remapped.push(target);
+            }
+        }
+        quiz.correct_options = Some(remapped);
+    }
+    if let Some(colors) = quiz.option_colors.take() {
+        let mut remapped = BTreeMap::new();
+        for (option, color) in colors {
+            let target = renamed.get(&option).cloned().unwrap_or(option);
+            remapped.entry(target).or_insert(color);
+        }
+        quiz.option_colors = Some(remapped);
+    }
+
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let votes: Vec<LoggedVote> = log
+            .range((id, 0)..=(id, u64::MAX))
+            .filter(|(_, vote)| vote.question_index() == 0)
+            .collect();
+        // anonymous ballots all share one principal, so they're never merged
+        let mut seen: BTreeSet<(Principal, String)> = BTreeSet::new();
+        for (key, mut vote) in votes {
+            let target = renamed
+                .get(&vote.option)
+                .cloned()
+                .unwrap_or(vote.option.clone());
+            if vote.voter != Principal::anonymous() && !seen.insert((vote.voter, target.clone())) {
+                log.remove(&key);
+                if let Some(count) = quiz.answers.get_mut(&target) {
+                    *count = count.saturating_sub(vote.weight);
+                }
+            } else if target != vote.option {
+                vote.option = target;
+                log.insert(key, vote);
+            }
+        }
+    });
+    RANKED_BALLOTS.with(|ballots| {
+        let mut ballots = ballots.borrow_mut();
+        let stale: Vec<((u64, StorablePrincipal), RankedBallot)> = ballots
+            .range((id, StorablePrincipal::default())..)
+            .take_while(|((quiz_id, _), _)| *quiz_id == id)
+            .filter(|(_, ballot)| {
+                ballot
+                    .ranking
+                    .iter()
+                    .any(|option| renamed.get(option).is_some_and(|t| t != option))
+            })
+            .collect();
+        for (key, mut ballot) in stale {
+            let mut ranking: Vec<String> = Vec::new();
+            for option in ballot.ranking {
+                let target = renamed.get(&option).cloned().unwrap_or(option);
+                if !ranking.contains(&target) {
+                    ranking.push(target);
+                }
+            }
+            ballot.ranking = ranking;
+            ballots.insert(key, ballot);
+        }
+    });
+
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "options normalized".to_string());
+    Ok(quiz)
+}
+
+// Sets counts from `option,votes` rows, e.g. migrated from another system.
+// Splits on the last comma so options may contain commas, skips blank lines
+// and an optional `option,votes` header, and rejects the whole import if any
+// option is unknown. Options not listed keep their counts. Imported counts
+// have no votes behind them, so `rebuild_answer_counts` would undo them.
+#[ic_cdk::update]
+fn import_results_csv(id: u64, csv: String) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    let mut quiz = get_quiz(id)?;
+
+    let mut counts: Vec<(String, u32)> = Vec::new();
+    for (line_number, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (line_number == 0 && line.eq_ignore_ascii_case("option,votes")) {
+            continue;
+        }
+        let (option, votes) = line.rsplit_once(',').ok_or_else(|| Error::InvalidInput {
+            msg: format!("line {}: expected `option,votes`", line_number + 1),
+        })?;
+        let votes: u32 = votes.trim().parse().map_err(|_| Error::InvalidInput {
+            msg: format!(
+                "line {}: '{}' is not a vote count",
+                line_number + 1,
+                votes.trim()
+            ),
+        })?;
+        let option = quiz
+            .resolve_option(option.trim())
+            .ok_or_else(|| Error::InvalidInput {
+                msg: format!(
+                    "line {}: '{}' is not an option of quiz with id={}",
+                    line_number + 1,
+                    option.trim(),
+                    id
+                ),
+            })?;
+        counts.push((option, votes));
+    }
+
+    for (option, votes) in counts {
+        quiz.answers.insert(option, votes);
+    }
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "results imported from CSV".to_string());
+    Ok(quiz)
+}
+
+// Forks `options_to_move` into a new quiz with the same question and voting
+// settings. Counts and the votes behind them move along with the options.
+#[ic_cdk::update]
+fn split_quiz(id: u64, options_to_move: Vec<String>) -> Result<(Quiz, Quiz), Error> {
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    quiz.ensure_editable()?;
+
+    let mut moved: Vec<String> = Vec::new();
+    for option in &options_to_move {
+        let resolved = quiz.resolve_option(option).ok_or_else(|| Error::NotFound {
+            resource: Resource::Option,
+            msg: format!("'{}' is not an option of quiz with id={}", option, id),
+        })?;
+        if !moved.contains(&resolved) {
+            moved.push(resolved);
+        }
+    }
+    if moved.len() < 2 || quiz.options.len() - moved.len() < 2 {
+        return Err(Error::InvalidInput {
+            msg: "both quizzes must keep at least two options".to_string(),
+        });
+    }
+    ensure_capacity()?;
+    take_rate_slot(RateAction::CreateQuiz, caller())?;
+
+    let mut forked = quiz.fork(next_quiz_id(), moved.clone());
+    for option in &moved {
+        let count = quiz.answers.remove(option).unwrap_or(0);
+        forked.answers.insert(option.clone(), count);
+    }
+    quiz.options.retain(|option| !moved.contains(option));
+    quiz.retain_option_colors();
+    quiz.sync_option_details();
+    quiz.retain_correct_options();
+
+    let moved_votes: Vec<LoggedVote> = VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .filter(|(_, vote)| vote.question_index() == 0 && moved.contains(&vote.option))
+            .collect()
+    });
+    for (key, vote) in moved_votes {
+        VOTE_LOG.with(|log| log.borrow_mut().remove(&key));
+        append_vote(forked.id, vote);
+    }
+
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    do_insert(&forked);
+    schedule_expiry(&forked);
+    journal(
+        id,
+        format!("options moved to quiz {}: {}", forked.id, moved.join(", ")),
+    );
+    journal(forked.id, format!("split from quiz {}", id));
+    Ok((quiz, forked))
+}
+
+// Takes the quiz out of listings and voting without losing anything;
+// `restore_quiz` undoes it.
+#[ic_cdk::update]
+fn archive_quiz(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    set_archived(id, true)
+}
+
+#[ic_cdk::update]
+fn restore_quiz(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    set_archived(id, false)
+}
+
+fn set_archived(id: u64, archived: bool) -> Result<Quiz, Error> {
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if quiz.is_archived() == archived {
+        return Ok(quiz);
+    }
+    quiz.archived = archived.then_some(true);
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(
+        id,
+        if archived { "archived" } else { "restored" }.to_string(),
+    );
+    Ok(quiz)
+}
+
+// Everything kept under quiz `id` apart from the record itself and its audit
+// events, for when the id is purged or taken over by another quiz.
+fn clear_quiz_state(id: u64) {
+    uncertify(id);
+    clear_votes(id);
+    clear_history(id);
+    clear_attempts(id);
+    clear_leaderboard(id);
+    clear_comments(id);
+    clear_started_attempts(id);
+    clear_activity(id);
+    clear_final_results(id);
+    SNAPSHOTS.with(|snapshots| remove_quiz_keys(&mut snapshots.borrow_mut(), id, 0));
+    PAYOUTS.with(|payouts| {
+        remove_quiz_keys(&mut payouts.borrow_mut(), id, StorablePrincipal::default())
+    });
+    BADGES.with(|badges| {
+        remove_quiz_keys(&mut badges.borrow_mut(), id, StorablePrincipal::default())
+    });
+    ANONYMOUS_BALLOTS
+        .with(|ballots| remove_quiz_keys(&mut ballots.borrow_mut(), id, Blob::default()));
+    USED_NONCES.with(|nonces| remove_quiz_keys(&mut nonces.borrow_mut(), id, Blob::default()));
+}
+
+// `first` is the smallest second half a key can have
+fn remove_quiz_keys<K, V>(map: &mut StableBTreeMap<(u64, K), V, Memory>, quiz_id: u64, first: K)
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let keys: Vec<(u64, K)> = map
+        .range((quiz_id, first)..)
+        .take_while(|((id, _), _)| *id == quiz_id)
+        .map(|(key, _)| key)
+        .collect();
+    for key in keys {
+        map.remove(&key);
+    }
+}
+
+// The only way to remove a quiz's data for good; owners archive instead.
+#[ic_cdk::update]
+fn purge_quiz(id: u64) -> Result<Quiz, Error> {
+    ensure_admin()?;
+    let removed = STORAGE.with(|service| service.borrow_mut().remove(&id));
+    if let Some(StoredQuiz::Quiz(quiz)) = &removed {
+        unindex(quiz);
+    }
+    if removed.is_some() {
+        log_event(QuizEventKind::Deleted, id, caller(), "purged".to_string());
+        clear_quiz_state(id);
+    }
+    match removed {
+        Some(StoredQuiz::Quiz(quiz)) => Ok(*quiz),
+        Some(StoredQuiz::Corrupt(_)) => Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!("quiz with id={} was corrupt and has been removed", id),
+        }),
+        None => Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!("couldn't purge a quiz with id={}. quiz not found.", id),
+        }),
+    }
+}
+
+// The one way into a token-gated quiz, since checking the balance takes a
+// call to the ledger.
+#[ic_cdk::update]
+async fn answer_quiz(
+    id: u64,
+    question_index: u32,
+    option: String,
+    invite_code: Option<String>,
+) -> Result<Quiz, Error> {
+    let token_gate_passed = match get_quiz(id)?.token_gate {
+        Some(gate) => {
+            ensure_min_balance(id, &gate, caller()).await?;
+            true
+        }
+        None => false,
+    };
+    cast_vote_admitted(
+        id,
+        question_index,
+        vec![option],
+        1,
+        caller(),
+        Admission {
+            invite_code: invite_code.as_deref(),
+            token_gate_passed,
+        },
+    )
+}
+
+async fn ensure_min_balance(id: u64, gate: &TokenGate, voter: Principal) -> Result<(), Error> {
+    let account = Account {
+        owner: voter,
+        subaccount: None,
+    };
+    let (balance,): (Nat,) = ic_cdk::call(gate.ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(_, msg)| Error::CallFailed {
+            canister: gate.ledger,
+            msg: format!("couldn't check the caller's balance: {}", msg),
+        })?;
+    if balance < gate.min_balance {
+        return Err(Error::AccessDenied {
+            msg: format!(
+                "quiz with id={} needs a balance of at least {} on ledger {}",
+                id, gate.min_balance, gate.ledger
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Swaps the caller's answer to the first question for `new_option`. Only an
+// existing answer can be changed, and only while the quiz allows it.
+#[ic_cdk::update]
+fn change_answer(quiz_id: u64, new_option: String) -> Result<Quiz, Error> {
+    let voter = caller();
+    let quiz = get_quiz(quiz_id)?;
+    ensure_retractable(&quiz, &voter)?;
+    // cast_vote turns ranked quizzes away
+    cast_vote(quiz_id, 0, vec![new_option], 1, voter)
+}
+
+// Takes back all of the caller's votes on the quiz, across every question.
+#[ic_cdk::update]
+fn retract_answer(quiz_id: u64) -> Result<Quiz, Error> {
+    let voter = caller();
+    let mut quiz = get_quiz(quiz_id)?;
+    ensure_retractable(&quiz, &voter)?;
+    let previous = votes_of(quiz_id, &voter);
+    for (_, vote) in &previous {
+        if let Some(answer_count) = quiz
+            .tallies_mut(vote.question_index())
+            .and_then(|answers| answers.get_mut(&vote.option))
+        {
+            *answer_count = answer_count.saturating_sub(vote.weight);
+        }
+    }
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        for (key, _) in &previous {
+            log.remove(key);
+        }
+    });
+    RANKED_BALLOTS.with(|ballots| {
+        ballots
+            .borrow_mut()
+            .remove(&(quiz_id, StorablePrincipal(voter)))
+    });
+    let retracted: Vec<String> = previous
+        .iter()
+        .map(|(_, vote)| format!("question {}: {}", vote.question_index(), vote.option))
+        .collect();
+    log_event(
+        QuizEventKind::Retracted,
+        quiz_id,
+        voter,
+        retracted.join("; "),
+    );
+    Ok(quiz)
+}
+
+// the checks, in order, behind `accepts_votes`
+fn ensure_open(quiz: &Quiz) -> Result<(), Error> {
+    let id = quiz.id;
+    let (reason, msg) = if quiz.status() == QuizStatus::Closed {
+        (
+            ClosedReason::Closed,
+            format!("quiz with id={} is closed", id),
+        )
+    } else if quiz.status() == QuizStatus::Draft {
+        let msg = match quiz.opens_at {
+            Some(opens_at) => format!("quiz with id={} opens at {}", id, opens_at),
+            None => format!("quiz with id={} hasn't been opened yet", id),
+        };
+        (ClosedReason::NotStarted, msg)
+    } else if quiz.is_archived() {
+        (
+            ClosedReason::Archived,
+            format!("quiz with id={} is archived", id),
+        )
+    } else if quiz.is_expired(time()) {
+        (
+            ClosedReason::Expired,
+            format!("quiz with id={} has expired", id),
+        )
+    } else {
+        return Ok(());
+    };
+    Err(Error::QuizClosed {
+        quiz_id: id,
+        reason,
+        msg,
+    })
+}
+
+// Graded answers are final and anonymous ballots can't be traced back to a
+// caller, so neither can be taken back.
+fn ensure_retractable(quiz: &Quiz, voter: &Principal) -> Result<(), Error> {
+    ensure_open(quiz)?;
+    if quiz.is_graded() || !quiz.allows_vote_change() {
+        return Err(Error::Locked {
+            msg: format!("answers to quiz with id={} can't be changed", quiz.id),
+        });
+    }
+    if *voter == Principal::anonymous() || !has_voted(quiz.id, voter) {
+        return Err(Error::NotFound {
+            resource: Resource::Vote,
+            msg: format!("caller hasn't answered quiz with id={}", quiz.id),
+        });
+    }
+    Ok(())
+}
+
+// Starts the caller's clock on a timed quiz. Calling it again doesn't
+// restart the clock.
+#[ic_cdk::update]
+fn start_attempt(quiz_id: u64) -> Result<AttemptStatus, Error> {
+    ensure_storage_available()?;
+    let quiz = get_quiz(quiz_id)?;
+    let Some(time_limit_secs) = quiz.time_limit_secs else {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} has no time limit", quiz_id),
+        });
+    };
+    let participant = caller();
+    if participant == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't start a timed quiz".to_string(),
+        });
+    }
+    ensure_open(&quiz)?;
+    ensure_access(&quiz, &participant, None)?;
+    if has_voted(quiz_id, &participant) {
+        return Err(Error::AlreadyAnswered {
+            quiz_id,
+            msg: format!("caller has already answered quiz with id={}", quiz_id),
+        });
+    }
+    let key = (quiz_id, StorablePrincipal(participant));
+    let started_at = STARTED_ATTEMPTS.with(|started| {
+        let mut started = started.borrow_mut();
+        match started.get(&key) {
+            Some(started_at) => started_at,
+            None => {
+                started.insert(key, time());
+                time()
+            }
+        }
+    });
+    Ok(attempt_status(started_at, time_limit_secs))
+}
+
+#[ic_cdk::query]
+fn get_attempt_status(quiz_id: u64) -> Result<AttemptStatus, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    let Some(time_limit_secs) = quiz.time_limit_secs else {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} has no time limit", quiz_id),
+        });
+    };
+    let started_at = started_at(quiz_id, &caller()).ok_or_else(|| Error::NotFound {
+        resource: Resource::Attempt,
+        msg: format!("caller hasn't started quiz with id={}", quiz_id),
+    })?;
+    Ok(attempt_status(started_at, time_limit_secs))
+}
+
+fn started_at(quiz_id: u64, participant: &Principal) -> Option<u64> {
+    STARTED_ATTEMPTS.with(|started| {
+        started
+            .borrow()
+            .get(&(quiz_id, StorablePrincipal(*participant)))
+    })
+}
+
+fn attempt_status(started_at: u64, time_limit_secs: u64) -> AttemptStatus {
+    let deadline = started_at.saturating_add(time_limit_secs.saturating_mul(NANOS_PER_SEC));
+    let remaining_ns = deadline.saturating_sub(time());
+    AttemptStatus {
+        started_at,
+        deadline,
+        remaining_ns,
+        expired: remaining_ns == 0,
+    }
+}
+
+// on a timed quiz, only answers from participants whose clock is still
+// running are taken
+fn ensure_within_time_limit(quiz: &Quiz, voter: &Principal) -> Result<(), Error> {
+    let Some(time_limit_secs) = quiz.time_limit_secs else {
+        return Ok(());
+    };
+    let Some(started_at) = started_at(quiz.id, voter) else {
+        return Err(Error::NotFound {
+            resource: Resource::Attempt,
+            msg: format!(
+                "call start_attempt before answering quiz with id={}",
+                quiz.id
+            ),
+        });
+    };
+    let status = attempt_status(started_at, time_limit_secs);
+    if status.expired {
+        return Err(Error::TimeExpired {
+            quiz_id: quiz.id,
+            deadline: status.deadline,
+            msg: format!("the time limit for quiz with id={} has passed", quiz.id),
+        });
+    }
+    Ok(())
+}
+
+fn clear_started_attempts(quiz_id: u64) {
+    STARTED_ATTEMPTS.with(|started| {
+        let mut started = started.borrow_mut();
+        let keys: Vec<(u64, StorablePrincipal)> = started
+            .range((quiz_id, StorablePrincipal::default())..)
+            .take_while(|((id, _), _)| *id == quiz_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            started.remove(&key);
+        }
+    });
+}
+
+// A graded quiz takes one answer per principal, whatever `allow_vote_change`
+// says, so a score can't be farmed by resubmitting. On a multi-select quiz
+// the answer is correct only if it picks exactly the correct options. The
+// answer key is for the first question, so that's the one answered here.
+#[ic_cdk::update]
+fn submit_answer(id: u64, selections: Vec<String>) -> Result<AnswerResult, Error> {
+    let quiz = get_quiz(id)?;
+    if !quiz.is_graded() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} is not graded", id),
+        });
+    }
+    let voter = caller();
+    if voter == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't submit graded answers".to_string(),
+        });
+    }
+    if has_voted(id, &voter) {
+        return Err(Error::AlreadyAnswered {
+            quiz_id: id,
+            msg: format!("caller has already answered quiz with id={}", id),
+        });
+    }
+    if quiz.is_multi_select() {
+        vote_multiple(id, selections)?;
+    } else if selections.len() == 1 {
+        cast_vote(id, 0, selections, 1, voter)?;
+    } else {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} takes exactly one option", id),
+        });
+    }
+
+    let votes: Vec<Vote> = votes_of(id, &voter)
+        .into_iter()
+        .map(|(_, vote)| vote)
+        .filter(|vote| vote.question_index() == 0)
+        .collect();
+    let receipts = votes.iter().map(|vote| receipt_for(&quiz, vote)).collect();
+    let chosen: Vec<String> = votes.into_iter().map(|vote| vote.option).collect();
+    let multi_select = quiz.is_multi_select();
+    let correct_options = quiz.correct_options.unwrap_or_default();
+    let correct = chosen.iter().all(|option| correct_options.contains(option))
+        && (!multi_select || chosen.len() == correct_options.len());
+
+    let score = USER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        let key = StorablePrincipal(voter);
+        let mut user_stats = stats.get(&key).unwrap_or_default();
+        let score = user_stats.score.unwrap_or(0) + correct as u64;
+        user_stats.score = Some(score);
+        stats.insert(key, user_stats);
+        score
+    });
+    LEADERBOARD.with(|board| {
+        board
+            .borrow_mut()
+            .insert((id, StorablePrincipal(voter)), correct as u64)
+    });
+    if badge_earned(id, &voter) {
+        set_timer(Duration::ZERO, move || {
+            ic_cdk::spawn(async move {
+                if let Err(Error::CallFailed { msg, .. } | Error::Conflict { msg }) =
+                    mint_badge(id, voter).await
+                {
+                    ic_cdk::println!("couldn't mint a badge for quiz {}: {}", id, msg);
+                }
+            })
+        });
+    }
+    Ok(AnswerResult {
+        correct,
+        score,
+        receipts,
+    })
+}
+
+// Everyone who answered the graded quiz, best first; ties keep principal order.
+#[ic_cdk::query]
+fn get_leaderboard(quiz_id: u64, top_n: u64) -> Result<Vec<LeaderboardEntry>, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    if !quiz.is_graded() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} is not graded", quiz_id),
+        });
+    }
+    let entries = LEADERBOARD.with(|board| {
+        board
+            .borrow()
+            .range((quiz_id, StorablePrincipal::default())..)
+            .take_while(|((id, _), _)| *id == quiz_id)
+            .map(|((_, principal), score)| LeaderboardEntry {
+                principal: principal.0,
+                score,
+            })
+            .collect()
+    });
+    Ok(top_entries(entries, top_n))
+}
+
+// Where the prize for `quiz_id` has to be sent before `set_prize`: this
+// canister's subaccount for the quiz, which holds nothing else.
+#[ic_cdk::query]
+fn get_prize_account(quiz_id: u64) -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(prize_subaccount(quiz_id)),
+    }
+}
+
+// the quiz id, big-endian, in the last 8 of 32 bytes
+fn prize_subaccount(quiz_id: u64) -> Vec<u8> {
+    let mut subaccount = vec![0; 32];
+    subaccount[24..].copy_from_slice(&quiz_id.to_be_bytes());
+    subaccount
+}
+
+// The prize must already be in the quiz's prize account, along with enough
+// on top for one transfer fee per winner.
+#[ic_cdk::update]
+async fn set_prize(quiz_id: u64, prize: Prize) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let quiz = get_quiz(quiz_id)?;
+    ensure_can_manage(&quiz)?;
+    if !quiz.is_graded() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} is not graded", quiz_id),
+        });
+    }
+    if prize.winners == 0 || prize.amount == 0u64 {
+        return Err(Error::InvalidInput {
+            msg: "a prize needs an amount and at least one winner".to_string(),
+        });
+    }
+    if !payouts_of(quiz_id).is_empty() {
+        return Err(Error::Conflict {
+            msg: format!("the prize for quiz with id={} is being paid out", quiz_id),
+        });
+    }
+    let (balance,): (Nat,) = ic_cdk::call(
+        prize.ledger,
+        "icrc1_balance_of",
+        (get_prize_account(quiz_id),),
+    )
+    .await
+    .map_err(|(_, msg)| Error::CallFailed {
+        canister: prize.ledger,
+        msg: format!("couldn't check the prize account's balance: {}", msg),
+    })?;
+    if balance < prize.amount {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "the prize account holds {}, less than the prize of {}",
+                balance, prize.amount
+            ),
+        });
+    }
+    let mut quiz = get_quiz(quiz_id)?;
+    quiz.prize = Some(prize);
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(quiz_id, "prize set".to_string());
+    Ok(quiz)
+}
+
+// The first call fixes the winners from the leaderboard and each one's share;
+// every call then pays whatever isn't paid yet, so after a partial failure the
+// owner just calls it again.
+#[ic_cdk::update]
+async fn distribute_rewards(quiz_id: u64) -> Result<Vec<PayoutRecord>, Error> {
+    ensure_storage_available()?;
+    let quiz = get_quiz(quiz_id)?;
+    ensure_can_manage(&quiz)?;
+    if quiz.status() != QuizStatus::Closed {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} has to be closed first", quiz_id),
+        });
+    }
+    let prize = quiz.prize.clone().ok_or_else(|| Error::InvalidInput {
+        msg: format!("quiz with id={} has no prize", quiz_id),
+    })?;
+    if payouts_of(quiz_id).is_empty() {
+        let winners: Vec<LeaderboardEntry> = get_leaderboard(quiz_id, prize.winners as u64)?
+            .into_iter()
+            .filter(|entry| entry.score > 0)
+            .collect();
+        if winners.is_empty() {
+            return Err(Error::InvalidInput {
+                msg: format!("quiz with id={} has nobody to reward", quiz_id),
+            });
+        }
+        let share = prize.amount.clone() / Nat::from(winners.len() as u64);
+        PAYOUTS.with(|payouts| {
+            let mut payouts = payouts.borrow_mut();
+            for entry in &winners {
+                payouts.insert(
+                    (quiz_id, StorablePrincipal(entry.principal)),
+                    Payout {
+                        amount: share.clone(),
+                        status: PayoutStatus::Pending,
+                        attempts: 0,
+                        created_at_time: time(),
+                    },
+                );
+            }
+        });
+    }
+
+    let due: Vec<PayoutRecord> = payouts_of(quiz_id)
+        .into_iter()
+        .filter(|record| {
+            matches!(
+                record.payout.status,
+                PayoutStatus::Pending | PayoutStatus::Failed { .. }
+            )
+        })
+        .collect();
+    for record in due {
+        let key = (quiz_id, StorablePrincipal(record.winner));
+        let mut payout = record.payout;
+        payout.attempts += 1;
+        payout.status = PayoutStatus::InFlight;
+        PAYOUTS.with(|payouts| payouts.borrow_mut().insert(key, payout.clone()));
+
+        let status = pay(&prize, quiz_id, record.winner, &mut payout).await;
+        let (kind, details) = match &status {
+            PayoutStatus::Paid { block_index } => (
+                QuizEventKind::RewardPaid,
+                format!("{} at block {}", payout.amount, block_index),
+            ),
+            PayoutStatus::Failed { reason } => (
+                QuizEventKind::RewardFailed,
+                format!("{}: {}", payout.amount, reason),
+            ),
+            _ => unreachable!("`pay` settles every payout"),
+        };
+        payout.status = status;
+        PAYOUTS.with(|payouts| payouts.borrow_mut().insert(key, payout));
+        log_event(kind, quiz_id, record.winner, details);
+    }
+    Ok(payouts_of(quiz_id))
+}
+
+// A transfer the ledger already saw counts as paid. One the ledger finds too
+// old to deduplicate gets a fresh `created_at_time` for the next attempt.
+async fn pay(prize: &Prize, quiz_id: u64, winner: Principal, payout: &mut Payout) -> PayoutStatus {
+    let arg = TransferArg {
+        from_subaccount: Some(prize_subaccount(quiz_id)),
+        to: Account {
+            owner: winner,
+            subaccount: None,
+        },
+        amount: payout.amount.clone(),
+        fee: None,
+        memo: Some(quiz_id.to_be_bytes().to_vec()),
+        created_at_time: Some(payout.created_at_time),
+    };
+    let result: Result<(TransferResult,), _> =
+        ic_cdk::call(prize.ledger, "icrc1_transfer", (arg,)).await;
+    match result {
+        Ok((Ok(block_index),)) => PayoutStatus::Paid { block_index },
+        Ok((Err(TransferError::Duplicate { duplicate_of }),)) => PayoutStatus::Paid {
+            block_index: duplicate_of,
+        },
+        Ok((Err(TransferError::TooOld),)) => {
+            payout.created_at_time = time();
+            PayoutStatus::Failed {
+                reason: "transfer too old to deduplicate".to_string(),
+            }
+        }
+        Ok((Err(error),)) => PayoutStatus::Failed {
+            reason: format!("{:?}", error),
+        },
+        Err((_, msg)) => PayoutStatus::Failed { reason: msg },
+    }
+}
+
+// in winner principal order
+#[ic_cdk::query]
+fn get_payouts(quiz_id: u64) -> Vec<PayoutRecord> {
+    payouts_of(quiz_id)
+}
+
+fn payouts_of(quiz_id: u64) -> Vec<PayoutRecord> {
+    PAYOUTS.with(|payouts| {
+        payouts
+            .borrow()
+            .range((quiz_id, StorablePrincipal::default())..)
+            .take_while(|((id, _), _)| *id == quiz_id)
+            .map(|((_, winner), payout)| PayoutRecord {
+                winner: winner.0,
+                payout,
+            })
+            .collect()
+    })
+}
+
+// `submit_answer` mints the badge on its own; this is for a mint that failed,
+// or one earned before the collection was set. Claiming a badge already minted
+// just returns it.
+#[ic_cdk::update]
+async fn claim_badge(quiz_id: u64) -> Result<Badge, Error> {
+    ensure_storage_available()?;
+    let quiz = get_quiz(quiz_id)?;
+    let participant = caller();
+    if participant == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't hold badges".to_string(),
+        });
+    }
+    if !quiz.is_graded() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} is not graded", quiz_id),
+        });
+    }
+    if !badge_earned(quiz_id, &participant) {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "caller hasn't earned the badge for quiz with id={}",
+                quiz_id
+            ),
+        });
+    }
+    mint_badge(quiz_id, participant).await
+}
+
+#[ic_cdk::query]
+fn get_badge(quiz_id: u64, participant: Principal) -> Option<Badge> {
+    BADGES.with(|badges| {
+        badges
+            .borrow()
+            .get(&(quiz_id, StorablePrincipal(participant)))
+    })
+}
+
+#[ic_cdk::update]
+fn set_badge_collection(collection: Option<Principal>) -> Result<Config, Error> {
+    update_config(|config| config.badge_collection = collection)
+}
+
+#[ic_cdk::update]
+fn set_badge_min_score(min_score: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.badge_min_score = min_score)
+}
+
+// only once a collection is set, so nothing's owed from before that
+fn badge_earned(quiz_id: u64, participant: &Principal) -> bool {
+    let config = get_config();
+    let score = LEADERBOARD.with(|board| {
+        board
+            .borrow()
+            .get(&(quiz_id, StorablePrincipal(*participant)))
+    });
+    config.badge_collection.is_some()
+        && score.is_some_and(|score| score >= config.badge_min_score.unwrap_or(1))
+}
+
+// An existing token id means an earlier mint went through after all, so the
+// badge counts as minted. A failed mint keeps its collection and token id.
+async fn mint_badge(quiz_id: u64, participant: Principal) -> Result<Badge, Error> {
+    let key = (quiz_id, StorablePrincipal(participant));
+    let mut badge = match BADGES.with(|badges| badges.borrow().get(&key)) {
+        Some(
+            badge @ Badge {
+                status: BadgeStatus::Minted { .. },
+                ..
+            },
+        ) => return Ok(badge),
+        Some(Badge {
+            status: BadgeStatus::Minting,
+            ..
+        }) => {
+            return Err(Error::Conflict {
+                msg: format!("the badge for quiz with id={} is being minted", quiz_id),
+            })
+        }
+        Some(badge) => badge,
+        None => Badge {
+            collection: get_config()
+                .badge_collection
+                .ok_or_else(|| Error::InvalidInput {
+                    msg: "no badge collection has been set".to_string(),
+                })?,
+            token_id: badge_token_id(quiz_id, &participant),
+            status: BadgeStatus::Minting,
+            attempts: 0,
+        },
+    };
+    badge.attempts += 1;
+    badge.status = BadgeStatus::Minting;
+    BADGES.with(|badges| badges.borrow_mut().insert(key, badge.clone()));
+
+    let arg = MintArg {
+        token_id: badge.token_id.clone(),
+        owner: Account {
+            owner: participant,
+            subaccount: None,
+        },
+        metadata: vec![
+            (
+                "quiz_id".to_string(),
+                MetadataValue::Nat(Nat::from(quiz_id)),
+            ),
+            (
+                "name".to_string(),
+                MetadataValue::Text(format!("Completed quiz #{}", quiz_id)),
+            ),
+        ],
+    };
+    let result: Result<(MintResult,), _> =
+        ic_cdk::call(badge.collection, "icrc7_mint", (arg,)).await;
+    let failure = match result {
+        Ok((Ok(_) | Err(MintError::TokenIdAlreadyExists),)) => None,
+        Ok((Err(error),)) => Some(format!("{:?}", error)),
+        Err((_, msg)) => Some(msg),
+    };
+    badge.status = match &failure {
+        None => BadgeStatus::Minted { minted_at: time() },
+        Some(reason) => BadgeStatus::Failed {
+            reason: reason.clone(),
+        },
+    };
+    BADGES.with(|badges| badges.borrow_mut().insert(key, badge.clone()));
+    match failure {
+        None => {
+            log_event(
+                QuizEventKind::BadgeMinted,
+                quiz_id,
+                participant,
+                format!("token {}", badge.token_id),
+            );
+            Ok(badge)
+        }
+        Some(reason) => Err(Error::CallFailed {
+            canister: badge.collection,
+            msg: format!("couldn't mint the badge: {}", reason),
+        }),
+    }
+}
+
+// the first 16 bytes of a hash of the quiz and the participant
+fn badge_token_id(quiz_id: u64, participant: &Principal) -> Nat {
+    let mut hasher = Sha256::new();
+    hasher.update(b"badge");
+    hasher.update(quiz_id.to_be_bytes());
+    hasher.update(participant.as_slice());
+    let digest: Hash = hasher.finalize().into();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Nat::from(u128::from_be_bytes(bytes))
+}
+
+// Scores across all graded quizzes, kept up to date by `submit_answer`.
+#[ic_cdk::query]
+fn get_global_leaderboard(top_n: u64) -> Vec<LeaderboardEntry> {
+    let entries = USER_STATS.with(|stats| {
+        stats
+            .borrow()
+            .iter()
+            .filter_map(|(principal, user_stats)| {
+                user_stats.score.map(|score| LeaderboardEntry {
+                    principal: principal.0,
+                    score,
+                })
+            })
+            .collect()
+    });
+    top_entries(entries, top_n)
+}
+
+fn top_entries(mut entries: Vec<LeaderboardEntry>, top_n: u64) -> Vec<LeaderboardEntry> {
+    // stable, so equal scores stay in the map's principal order
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+    entries.truncate(top_n.min(MAX_LEADERBOARD_SIZE) as usize);
+    entries
+}
+
+// Anonymous ballot: the vote is logged without the caller's principal, and the
+// single-use nonce is what stops the same ballot being cast twice. Each caller
+// gets one such ballot per quiz and round, tracked by a salted hash of their
+// principal so fresh nonces can't be used to vote again.
+#[ic_cdk::update]
+fn answer_quiz_nonce(id: u64, option: String, nonce: String) -> Result<Quiz, Error> {
+    let voter = caller();
+    if voter == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "sign in to cast an anonymous ballot".to_string(),
+        });
+    }
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("nonce must be between 1 and {} bytes", MAX_NONCE_LEN),
+        });
+    }
+    let key = (id, Blob::try_from(nonce.as_bytes()).unwrap());
+    if USED_NONCES.with(|nonces| nonces.borrow().contains_key(&key)) {
+        return Err(Error::Conflict {
+            msg: format!("nonce has already been used for quiz with id={}", id),
+        });
+    }
+    let quiz = get_quiz(id)?;
+    let ballot = (id, ballot_digest(&quiz, &voter));
+    if ANONYMOUS_BALLOTS.with(|ballots| ballots.borrow().contains_key(&ballot)) {
+        return Err(Error::Conflict {
+            msg: format!(
+                "caller has already cast an anonymous ballot for quiz with id={}",
+                id
+            ),
+        });
+    }
+
+    let quiz = cast_vote(id, 0, vec![option], 1, Principal::anonymous())?;
+    USED_NONCES.with(|nonces| nonces.borrow_mut().insert(key, ()));
+    ANONYMOUS_BALLOTS.with(|ballots| ballots.borrow_mut().insert(ballot, ()));
+    Ok(quiz)
+}
+
+// Same salt as `voter_seed`, so it's unguessable once that has been fetched.
+fn ballot_digest(quiz: &Quiz, voter: &Principal) -> Blob<32> {
+    let salt = get_config()
+        .shuffle_salt
+        .unwrap_or_else(|| quiz.display_seed().to_be_bytes().to_vec());
+    let mut hasher = Sha256::new();
+    hasher.update(&salt);
+    hasher.update(quiz.id.to_be_bytes());
+    hasher.update(quiz.round().to_be_bytes());
+    hasher.update(voter.as_slice());
+    Blob::try_from(hasher.finalize().as_slice()).unwrap()
+}
+
+#[ic_cdk::update]
+fn vote_with_weight(id: u64, option: String, weight: u32) -> Result<Quiz, Error> {
+    if weight == 0 {
+        return Err(Error::InvalidInput {
+            msg: "weight must be greater than zero".to_string(),
+        });
+    }
+    if let Some(max_weight) = get_quiz(id)?.max_weight_per_vote {
+        if weight > max_weight {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "weight must be at most {} for quiz with id={}",
+                    max_weight, id
+                ),
+            });
+        }
+    }
+    cast_vote(id, 0, vec![option], weight, caller())
+}
+
+// Mirrors the checks `cast_vote` runs, in the same order, for a single
+// unweighted vote.
+#[ic_cdk::query]
+fn can_vote(id: u64, principal: Principal) -> Result<VoteEligibility, Error> {
+    let quiz = get_quiz(id)?;
+    let reason = if is_banned(principal) {
+        Some(IneligibleReason::Banned)
+    } else if quiz.status() == QuizStatus::Closed || quiz.is_archived() {
+        Some(IneligibleReason::Closed)
+    } else if quiz.status() == QuizStatus::Draft {
+        Some(IneligibleReason::NotStarted)
+    } else if quiz.is_expired(time()) {
+        Some(IneligibleReason::Expired)
+    } else if ensure_may_vote(&quiz, &principal, None).is_err() {
+        Some(IneligibleReason::NotAllowed)
+    } else if principal == Principal::anonymous() && !quiz.admits_anonymous() {
+        Some(IneligibleReason::Anonymous)
+    } else if !quiz.allows_vote_change()
+        && principal != Principal::anonymous()
+        && has_voted(id, &principal)
+    {
+        Some(IneligibleReason::AlreadyVoted)
+    } else if quiz
+        .max_total_votes
+        .is_some_and(|max_total_votes| quiz.total_votes() >= max_total_votes)
+    {
+        Some(IneligibleReason::Full)
+    } else {
+        None
+    };
+
+    Ok(VoteEligibility {
+        eligible: reason.is_none(),
+        reason,
+    })
+}
+
+fn cast_vote(
+    id: u64,
+    question: u32,
+    selections: Vec<String>,
+    weight: u32,
+    voter: Principal,
+) -> Result<Quiz, Error> {
+    cast_vote_admitted(
+        id,
+        question,
+        selections,
+        weight,
+        voter,
+        Admission::default(),
+    )
+}
+
+// Ranked quizzes only take full rankings, through `answer_quiz_ranked`.
+fn cast_vote_admitted(
+    id: u64,
+    question: u32,
+    selections: Vec<String>,
+    weight: u32,
+    voter: Principal,
+    admission: Admission,
+) -> Result<Quiz, Error> {
+    if _get_quiz(&id).is_some_and(|quiz| quiz.is_ranked()) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "quiz with id={} is ranked; answer it with answer_quiz_ranked",
+                id
+            ),
+        });
+    }
+    cast_ballot(id, question, selections, weight, voter, admission)
+}
+
+// `max_total_votes` caps each question separately.
+fn cast_ballot(
+    id: u64,
+    question: u32,
+    selections: Vec<String>,
+    weight: u32,
+    voter: Principal,
+    admission: Admission,
+) -> Result<Quiz, Error> {
+    // checked against the real caller so anonymous ballots can't dodge a ban
+    if is_banned(caller()) {
+        return Err(Error::Unauthorized {
+            msg: "caller is banned from voting".to_string(),
+        });
+    }
+
+    let quiz_option: Option<Quiz> = _get_quiz(&id);
+
+    match quiz_option {
+        Some(mut quiz) => {
+            ensure_open(&quiz)?;
+            ensure_may_vote(&quiz, &caller(), admission.invite_code)?;
+            ensure_token_gate_passed(&quiz, admission.token_gate_passed)?;
+            ensure_within_time_limit(&quiz, &voter)?;
+            ensure_storage_available()?;
+            ensure_attempt_capacity(id)?;
+            if caller() == Principal::anonymous() && !quiz.admits_anonymous() {
+                return Err(Error::AnonymousNotAllowed {
+                    msg: format!("quiz with id={} doesn't take anonymous answers", id),
+                });
+            }
+            let options = quiz
+                .question_options(question)
+                .ok_or_else(|| Error::NotFound {
+                    resource: Resource::Question,
+                    msg: format!("quiz with id={} has no question {}", id, question),
+                })?
+                .to_vec();
+            // A principal's earlier ballot on the question is replaced, never
+            // added to, so each one counts once. Anonymous ballots are
+            // deduplicated by nonce.
+            let previous: Vec<LoggedVote> = if voter == Principal::anonymous() {
+                Vec::new()
+            } else {
+                votes_of(id, &voter)
+                    .into_iter()
+                    .filter(|(_, vote)| vote.question_index() == question)
+                    .collect()
+            };
+            if !previous.is_empty() && !quiz.allows_vote_change() {
+                return Err(Error::AlreadyAnswered {
+                    quiz_id: id,
+                    msg: format!("caller has already answered quiz with id={}", id),
+                });
+            }
+            let selections = selections
+                .iter()
+                .map(|option| {
+                    resolve_in(&options, option).ok_or_else(|| Error::NotFound {
+                        resource: Resource::Option,
+                        msg: format!("The option '{}' is not found for this quiz.", option),
+                    })
+                })
+                .collect::<Result<Vec<String>, Error>>()?;
+            let replaced: u64 = previous.iter().map(|(_, vote)| vote.weight as u64).sum();
+            if let Some(max_total_votes) = quiz.max_total_votes {
+                let incoming = weight as u64 * selections.len() as u64;
+                let question_total: u64 = quiz
+                    .tallies(question)
+                    .map(|answers| answers.values().map(|count| *count as u64).sum())
+                    .unwrap_or(0);
+                if question_total.saturating_sub(replaced) + incoming > max_total_votes {
+                    return Err(Error::Full {
+                        msg: format!(
+                            "quiz with id={} has reached its limit of {} votes",
+                            id, max_total_votes
+                        ),
+                    });
+                }
+            }
+            take_rate_slot(RateAction::Answer, caller())?;
+            let total_before = quiz.total_votes();
+
+            if let Some(answers) = quiz.tallies_mut(question) {
+                for (_, vote) in &previous {
+                    if let Some(answer_count) = answers.get_mut(&vote.option) {
+                        *answer_count = answer_count.saturating_sub(vote.weight);
+                    }
+                }
+                for option in &selections {
+                    if let Some(answer_count) = answers.get_mut(option) {
+                        *answer_count = answer_count.saturating_add(weight);
+                    }
+                }
+            }
+            if quiz.lock_on_first_vote == Some(true) {
+                quiz.locked = Some(true);
+            }
+            quiz.updated_at = Some(time());
+            do_insert(&quiz);
+            if let Some(milestone) = VOTE_MILESTONES
+                .into_iter()
+                .rfind(|milestone| (total_before + 1..=quiz.total_votes()).contains(milestone))
+            {
+                enqueue_webhook(&quiz, &format!("milestone_{}", milestone));
+            }
+            if voter != Principal::anonymous() {
+                update_user_stats(id, voter);
+            }
+            VOTE_LOG.with(|log| {
+                let mut log = log.borrow_mut();
+                for (key, _) in &previous {
+                    log.remove(key);
+                }
+            });
+            for option in &selections {
+                record_vote(id, question, voter, option, weight);
+                record_attempt(id, question, voter, option);
+            }
+            note_activity(id);
+            log_event(
+                QuizEventKind::Answered,
+                id,
+                voter,
+                format!(
+                    "question {}: {} (weight {})",
+                    question,
+                    selections.join(", "),
+                    weight
+                ),
+            );
+            Ok(quiz)
+        }
+        None => Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!("couldn't cast a quiz with id={}. quiz not found", id),
+        }),
+    }
+}
+
+#[ic_cdk::update]
+fn answer_quiz_multi(id: u64, options: Vec<String>) -> Result<Quiz, Error> {
+    vote_multiple(id, options)
+}
+
+// One response per caller; sending another replaces it, if the quiz allows
+// changing answers, and puts it back in the moderation queue.
+#[ic_cdk::update]
+fn submit_text_answer(id: u64, text: String) -> Result<(), Error> {
+    let quiz = get_quiz(id)?;
+    if !quiz.is_open_text() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} doesn't take text answers", id),
+        });
+    }
+    let author = caller();
+    if is_banned(author) {
+        return Err(Error::Unauthorized {
+            msg: "caller is banned from voting".to_string(),
+        });
+    }
+    if author == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't submit text answers".to_string(),
+        });
+    }
+    ensure_open(&quiz)?;
+    ensure_may_vote(&quiz, &author, None)?;
+    ensure_token_gate_passed(&quiz, false)?;
+    ensure_storage_available()?;
+    let text = text.trim().to_string();
+    if text.is_empty() || text.chars().count() > MAX_TEXT_RESPONSE_LEN {
+        return Err(Error::ValidationFailed {
+            field: "text".to_string(),
+            msg: format!(
+                "a response must be between 1 and {} characters",
+                MAX_TEXT_RESPONSE_LEN
+            ),
+        });
+    }
+    let key = (id, StorablePrincipal(author));
+    if !quiz.allows_vote_change()
+        && TEXT_RESPONSES.with(|responses| responses.borrow().contains_key(&key))
+    {
+        return Err(Error::AlreadyAnswered {
+            quiz_id: id,
+            msg: format!("caller has already answered quiz with id={}", id),
+        });
+    }
+    take_rate_slot(RateAction::Answer, caller())?;
+    TEXT_RESPONSES.with(|responses| {
+        responses.borrow_mut().insert(
+            key,
+            TextResponse {
+                text,
+                status: ResponseStatus::Pending,
+                submitted_at: time(),
+                moderated_at: None,
+            },
+        )
+    });
+    note_activity(id);
+    log_event(
+        QuizEventKind::Answered,
+        id,
+        author,
+        "text response".to_string(),
+    );
+    Ok(())
+}
+
+// oldest first
+#[ic_cdk::query]
+fn list_pending_responses(id: u64) -> Result<Vec<PendingResponse>, Error> {
+    ensure_can_manage(&get_quiz(id)?)?;
+    let mut pending: Vec<PendingResponse> = text_responses(id)
+        .into_iter()
+        .filter(|(_, response)| response.status == ResponseStatus::Pending)
+        .map(|(principal, response)| PendingResponse {
+            principal,
+            text: response.text,
+            submitted_at: response.submitted_at,
+        })
+        .collect();
+    pending.sort_by_key(|response| (response.submitted_at, response.principal));
+    Ok(pending)
+}
+
+#[ic_cdk::update]
+fn approve_response(id: u64, principal: Principal) -> Result<(), Error> {
+    ensure_storage_available()?;
+    moderate_response(id, principal, ResponseStatus::Approved)
+}
+
+#[ic_cdk::update]
+fn reject_response(id: u64, principal: Principal) -> Result<(), Error> {
+    ensure_storage_available()?;
+    moderate_response(id, principal, ResponseStatus::Rejected)
+}
+
+fn moderate_response(id: u64, principal: Principal, status: ResponseStatus) -> Result<(), Error> {
+    ensure_can_manage(&get_quiz(id)?)?;
+    let key = (id, StorablePrincipal(principal));
+    let mut response = TEXT_RESPONSES
+        .with(|responses| responses.borrow().get(&key))
+        .ok_or_else(|| Error::NotFound {
+            resource: Resource::Vote,
+            msg: format!("{} hasn't answered quiz with id={}", principal, id),
+        })?;
+    response.status = status;
+    response.moderated_at = Some(time());
+    TEXT_RESPONSES.with(|responses| responses.borrow_mut().insert(key, response));
+    Ok(())
+}
+
+// the approved texts, without who wrote them, oldest first
+#[ic_cdk::query]
+fn get_approved_responses(id: u64) -> Result<Vec<String>, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_results_visible(&quiz)?;
+    let mut approved: Vec<TextResponse> = text_responses(id)
+        .into_iter()
+        .map(|(_, response)| response)
+        .filter(|response| response.status == ResponseStatus::Approved)
+        .collect();
+    approved.sort_by_key(|response| response.submitted_at);
+    Ok(approved.into_iter().map(|response| response.text).collect())
+}
+
+fn text_responses(id: u64) -> Vec<(Principal, TextResponse)> {
+    TEXT_RESPONSES.with(|responses| {
+        responses
+            .borrow()
+            .range((id, StorablePrincipal::default())..)
+            .take_while(|((quiz_id, _), _)| *quiz_id == id)
+            .map(|((_, principal), response)| (principal.0, response))
+            .collect()
+    })
+}
+
+// The ranking must name every option exactly once. The first preference is
+// also cast as a normal vote, so `answers` shows first-preference counts and
+// the usual vote rules apply.
+#[ic_cdk::update]
+fn answer_quiz_ranked(id: u64, ranking: Vec<String>) -> Result<Quiz, Error> {
+    let quiz = get_quiz(id)?;
+    if !quiz.is_ranked() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} is not ranked", id),
+        });
+    }
+    let voter = caller();
+    if voter == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "anonymous callers can't cast ranked ballots".to_string(),
+        });
+    }
+    let ranking = ranking
+        .iter()
+        .map(|option| {
+            quiz.resolve_option(option).ok_or_else(|| Error::NotFound {
+                resource: Resource::Option,
+                msg: format!("The option '{}' is not found for this quiz.", option),
+            })
+        })
+        .collect::<Result<Vec<String>, Error>>()?;
+    if let Some((_, duplicate)) = ranking
+        .iter()
+        .enumerate()
+        .find(|(i, option)| ranking[..*i].contains(option))
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("option '{}' is ranked more than once", duplicate),
+        });
+    }
+    if ranking.len() != quiz.options.len() {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "a ranking of quiz with id={} must include all {} options",
+                id,
+                quiz.options.len()
+            ),
+        });
+    }
+
+    let quiz = cast_ballot(
+        id,
+        0,
+        vec![ranking[0].clone()],
+        1,
+        voter,
+        Admission::default(),
+    )?;
+    RANKED_BALLOTS.with(|ballots| {
+        ballots.borrow_mut().insert(
+            (id, StorablePrincipal(voter)),
+            RankedBallot {
+                ranking,
+                timestamp: time(),
+            },
+        )
+    });
+    Ok(quiz)
+}
+
+// Instant runoff: each round counts every ballot for its highest option still
+// standing. An option with more than half of them wins; otherwise the
+// option(s) with the fewest are knocked out and the count goes again.
+#[ic_cdk::query]
+fn get_ranked_result(id: u64) -> Result<RankedResult, Error> {
+    let quiz = get_quiz(id)?;
+    if !quiz.is_ranked() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} is not ranked", id),
+        });
+    }
+    ensure_results_visible(&quiz)?;
+    let ballots: Vec<Vec<String>> = RANKED_BALLOTS.with(|ballots| {
+        ballots
+            .borrow()
+            .range((id, StorablePrincipal::default())..)
+            .take_while(|((quiz_id, _), _)| *quiz_id == id)
+            .map(|(_, ballot)| ballot.ranking)
+            .collect()
+    });
+
+    let mut standing = quiz.options.clone();
+    let mut rounds = Vec::new();
+    let winner = loop {
+        let counts: Vec<(String, u32)> = standing
+            .iter()
+            .map(|option| {
+                let count = ballots
+                    .iter()
+                    .filter(|ranking| {
+                        ranking.iter().find(|choice| standing.contains(choice)) == Some(option)
+                    })
+                    .count() as u32;
+                (option.clone(), count)
+            })
+            .collect();
+        let counted: u32 = counts.iter().map(|(_, count)| count).sum();
+        if counted == 0 {
+            rounds.push(RankedRound {
+                counts,
+                eliminated: Vec::new(),
+            });
+            break None;
+        }
+        if let Some((option, _)) = counts.iter().find(|(_, count)| *count * 2 > counted) {
+            let option = option.clone();
+            rounds.push(RankedRound {
+                counts,
+                eliminated: Vec::new(),
+            });
+            break Some(option);
+        }
+        let fewest = counts.iter().map(|(_, count)| *count).min().unwrap_or(0);
+        let eliminated: Vec<String> = counts
+            .iter()
+            .filter(|(_, count)| *count == fewest)
+            .map(|(option, _)| option.clone())
+            .collect();
+        // everyone left is tied, so there's no one to knock out
+        let tied = eliminated.len() == standing.len();
+        rounds.push(RankedRound {
+            counts,
+            eliminated: if tied { Vec::new() } else { eliminated.clone() },
+        });
+        if tied {
+            break None;
+        }
+        standing.retain(|option| !eliminated.contains(option));
+    };
+
+    Ok(RankedResult { winner, rounds })
+}
+
+// A new ballot replaces the caller's earlier one as a whole, so the limit is
+// on each ballot rather than on picks added up over time.
+#[ic_cdk::update]
+fn vote_multiple(id: u64, options: Vec<String>) -> Result<Quiz, Error> {
+    let quiz = get_quiz(id)?;
+    if !quiz.is_multi_select() {
+        return Err(Error::InvalidInput {
+            msg: format!("quiz with id={} only accepts a single option", id),
+        });
+    }
+    if options.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "at least one option must be selected".to_string(),
+        });
+    }
+    if let Some(max_selections) = quiz.max_selections {
+        if options.len() > max_selections as usize {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "quiz with id={} takes at most {} options per ballot",
+                    id, max_selections
+                ),
+            });
+        }
+    }
+    let normalized: Vec<String> = options
+        .iter()
+        .map(|option| normalize_option(option))
+        .collect();
+    if let Some((_, duplicate)) = options
+        .iter()
+        .enumerate()
+        .find(|(i, _)| normalized[..*i].contains(&normalized[*i]))
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("option '{}' was selected more than once", duplicate),
+        });
+    }
+    cast_vote(id, 0, options, 1, caller())
+}
+
+// Flipping is allowed at any time: counts cast under either mode stay valid.
+// Any `max_selections` limit is dropped along the way.
+#[ic_cdk::update]
+fn set_multi_select(id: u64, multi: bool) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    quiz.multi_select = Some(multi);
+    quiz.max_selections = None;
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("multi_select set to {}", multi));
+    Ok(quiz)
+}
+
+// All-or-nothing: one bad option rejects the whole batch.
+#[ic_cdk::update]
+fn add_options(id: u64, options: Vec<String>) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    quiz.ensure_editable()?;
+    if options.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "at least one option must be given".to_string(),
+        });
+    }
+
+    let added: Vec<String> = options
+        .iter()
+        .map(|option| option.trim().to_string())
+        .collect();
+    let mut combined = quiz.options.clone();
+    combined.extend(added.iter().cloned());
+    validate_options_for(quiz.kind.unwrap_or_default(), &combined)?;
+
+    for option in added {
+        quiz.answers.insert(option.clone(), 0);
+        quiz.options.push(option);
+    }
+    quiz.sync_option_details();
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("options added: {}", options.join(", ")));
+    Ok(quiz)
+}
+
+// stricter `add_options` for a single option: only allowed before any vote
+#[ic_cdk::update]
+fn add_option_pre_vote(id: u64, option: String) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    if get_quiz(id)?.total_votes() > 0 {
+        return Err(Error::Locked {
+            msg: format!("quiz with id={} already has votes", id),
+        });
+    }
+    add_options(id, vec![option])
+}
+
+// Edits one option by its id. A new label takes the option's votes, ranked
+// ballots, color and place in the answer key with it. An empty description
+// or image url clears it.
+#[ic_cdk::update]
+fn edit_option(id: u64, option_id: u32, patch: OptionPatch) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    let Some(current) = quiz.option_label(option_id) else {
+        return Err(Error::NotFound {
+            resource: Resource::Option,
+            msg: format!("quiz with id={} has no option with id={}", id, option_id),
+        });
+    };
+    if let Some(description) = &patch.description {
+        validate_option_description(description)?;
+    }
+    if let Some(image_url) = &patch.image_url {
+        validate_image_url(image_url)?;
+    }
+    let label = patch.label.as_deref().map(str::trim).unwrap_or(&current);
+    if label != current {
+        let relabeled: Vec<String> = quiz
+            .options
+            .iter()
+            .map(|option| {
+                if *option == current {
+                    label.to_string()
+                } else {
+                    option.clone()
+                }
+            })
+            .collect();
+        validate_options_for(quiz.kind.unwrap_or_default(), &relabeled)?;
+        relabel_option(&mut quiz, &current, label);
+    }
+
+    if let Some(detail) = quiz
+        .option_details
+        .iter_mut()
+        .flatten()
+        .find(|detail| detail.id == option_id)
+    {
+        if let Some(description) = patch.description {
+            detail.description = (!description.is_empty()).then_some(description);
+        }
+        if let Some(image_url) = patch.image_url {
+            detail.image_url = (!image_url.is_empty()).then_some(image_url);
+        }
+    }
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("option {} edited", option_id));
+    Ok(quiz)
+}
+
+// renames `from` to `to` everywhere the first question's options are keyed
+// by label
+fn relabel_option(quiz: &mut Quiz, from: &str, to: &str) {
+    let id = quiz.id;
+    for option in quiz.options.iter_mut().filter(|option| *option == from) {
+        *option = to.to_string();
+    }
+    if let Some(count) = quiz.answers.remove(from) {
+        quiz.answers.insert(to.to_string(), count);
+    }
+    for option in quiz.correct_options.iter_mut().flatten() {
+        if option == from {
+            *option = to.to_string();
+        }
+    }
+    if let Some(colors) = quiz.option_colors.as_mut() {
+        if let Some(color) = colors.remove(from) {
+            colors.insert(to.to_string(), color);
+        }
+    }
+    for detail in quiz.option_details.iter_mut().flatten() {
+        if detail.label == from {
+            detail.label = to.to_string();
+        }
+    }
+
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let stale: Vec<LoggedVote> = log
+            .range((id, 0)..=(id, u64::MAX))
+            .filter(|(_, vote)| vote.question_index() == 0 && vote.option == from)
+            .collect();
+        for (key, mut vote) in stale {
+            vote.option = to.to_string();
+            log.insert(key, vote);
+        }
+    });
+    RANKED_BALLOTS.with(|ballots| {
+        let mut ballots = ballots.borrow_mut();
+        let stale: Vec<((u64, StorablePrincipal), RankedBallot)> = ballots
+            .range((id, StorablePrincipal::default())..)
+            .take_while(|((quiz_id, _), _)| *quiz_id == id)
+            .filter(|(_, ballot)| ballot.ranking.iter().any(|option| option == from))
+            .collect();
+        for (key, mut ballot) in stale {
+            for option in ballot.ranking.iter_mut().filter(|option| *option == from) {
+                *option = to.to_string();
+            }
+            ballots.insert(key, ballot);
+        }
+    });
+}
+
+// Adds or replaces the quiz's text in `locale`.
+#[ic_cdk::update]
+fn add_translation(id: u64, locale: String, content: LocalizedContent) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    validate_locale(&locale)?;
+    let locale = normalize_locale(&locale);
+    if locale == quiz.locale() {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "{} is already the default locale of quiz with id={}",
+                locale, id
+            ),
+        });
+    }
+    validate_question("question", &content.question)?;
+    for (option_id, label) in &content.options {
+        if quiz.option_label(*option_id).is_none() {
+            return Err(Error::NotFound {
+                resource: Resource::Option,
+                msg: format!("quiz with id={} has no option with id={}", id, option_id),
+            });
+        }
+        validate_option_label(&format!("options[{}]", option_id), label)?;
+    }
+    let translations = quiz.translations.get_or_insert_with(BTreeMap::new);
+    if !translations.contains_key(&locale) && translations.len() >= MAX_TRANSLATIONS {
+        return Err(Error::StorageFull {
+            used: translations.len() as u64,
+            limit: MAX_TRANSLATIONS as u64,
+            msg: format!("a quiz can have at most {} translations", MAX_TRANSLATIONS),
+        });
+    }
+    translations.insert(
+        locale.clone(),
+        LocalizedContent {
+            question: content.question,
+            options: content
+                .options
+                .into_iter()
+                .map(|(option_id, label)| (option_id, label.trim().to_string()))
+                .collect(),
+        },
+    );
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("translation added: {}", locale));
+    Ok(quiz)
+}
+
+#[ic_cdk::update]
+fn remove_translation(id: u64, locale: String) -> Result<Quiz, Error> {
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    let locale = normalize_locale(&locale);
+    let removed = quiz
+        .translations
+        .as_mut()
+        .and_then(|translations| translations.remove(&locale));
+    if removed.is_none() {
+        return Err(Error::NotFound {
+            resource: Resource::Translation,
+            msg: format!("quiz with id={} has no {} translation", id, locale),
+        });
+    }
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("translation removed: {}", locale));
+    Ok(quiz)
+}
+
+// The quiz's text in the closest locale it has to `locale`; see
+// `best_translation`. Options missing from the translation keep their
+// default label.
+#[ic_cdk::query]
+fn get_quiz_localized(
+    id: u64,
+    locale: String,
+    invite_code: Option<String>,
+) -> Result<LocalizedQuiz, Error> {
+    let quiz = get_quiz(id)?;
+    ensure_access(&quiz, &caller(), invite_code.as_deref())?;
+    let quiz = quiz.redacted();
+    let (locale, question, options) = match quiz.best_translation(&normalize_locale(&locale)) {
+        Some((locale, content)) => {
+            let options = quiz
+                .option_details
+                .iter()
+                .flatten()
+                .map(|detail| {
+                    content
+                        .options
+                        .get(&detail.id)
+                        .unwrap_or(&detail.label)
+                        .clone()
+                })
+                .collect();
+            (locale.clone(), content.question.clone(), options)
+        }
+        None => (quiz.locale(), quiz.question.clone(), quiz.options.clone()),
+    };
+    Ok(LocalizedQuiz {
+        locale,
+        question,
+        options,
+        quiz,
+    })
+}
+
+// `None` clears the option's color
+#[ic_cdk::update]
+fn set_option_color(id: u64, option: String, color: Option<String>) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if !quiz.options.contains(&option) {
+        return Err(Error::NotFound {
+            resource: Resource::Option,
+            msg: format!("'{}' is not an option of quiz with id={}", option, id),
+        });
+    }
+    match color {
+        Some(color) => {
+            if !is_hex_color(&color) {
+                return Err(Error::InvalidInput {
+                    msg: format!("'{}' is not a #RRGGBB color", color),
+                });
+            }
+            quiz.option_colors
+                .get_or_insert_with(BTreeMap::new)
+                .insert(option, color);
+        }
+        None => {
+            if let Some(colors) = quiz.option_colors.as_mut() {
+                colors.remove(&option);
+            }
+        }
+    }
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "option color changed".to_string());
+    Ok(quiz)
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[ic_cdk::update]
+fn set_correct_option(id: u64, option: String) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if !quiz.options.contains(&option) {
+        return Err(Error::NotFound {
+            resource: Resource::Option,
+            msg: format!("'{}' is not an option of quiz with id={}", option, id),
+        });
+    }
+    quiz.correct_options = Some(vec![option]);
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "correct option set".to_string());
+    Ok(quiz)
+}
+
+#[ic_cdk::update]
+fn set_metadata(id: u64, json: String) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    validate_metadata(&json)?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    quiz.metadata = Some(json);
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "metadata replaced".to_string());
+    Ok(quiz)
+}
+
+#[ic_cdk::update]
+fn feature_quiz(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    set_featured(id, true)
+}
+
+#[ic_cdk::update]
+fn unfeature_quiz(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    set_featured(id, false)
+}
+
+fn set_featured(id: u64, featured: bool) -> Result<Quiz, Error> {
+    ensure_controller()?;
+    let mut quiz = get_quiz(id)?;
+    quiz.featured = Some(featured);
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("featured set to {}", featured));
+    Ok(quiz)
+}
+
+// most recently touched first
+#[ic_cdk::query]
+fn get_featured_quizzes() -> Vec<Quiz> {
+    let mut featured: Vec<Quiz> = with_listed_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.featured == Some(true) && !quiz.is_archived())
+            .filter_map(visible)
+            .collect()
+    });
+    featured.sort_by_key(|quiz| std::cmp::Reverse(quiz.updated_at.unwrap_or(quiz.created_at)));
+    featured
+}
+
+// lowercase with "-" between subtags, so "pt_BR" and "pt-br" are one locale
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_lowercase().replace('_', "-")
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+// first occurrence wins, so the order callers gave is kept
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|tag| normalize_tag(tag)) {
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+#[ic_cdk::query]
+fn list_categories() -> Vec<(String, u64)> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    with_quizzes(|quizzes| {
+        for quiz in quizzes.filter(|quiz| !quiz.is_archived()) {
+            if let Some(category) = quiz.category {
+                *counts.entry(category).or_insert(0) += 1;
+            }
+        }
+    });
+    let mut categories: Vec<(String, u64)> = counts.into_iter().collect();
+    categories.sort_by(|(category_a, count_a), (category_b, count_b)| {
+        count_b
+            .cmp(count_a)
+            .then_with(|| category_a.cmp(category_b))
+    });
+    categories
+}
+
+#[ic_cdk::query]
+fn list_tags() -> Vec<(String, u64)> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    with_quizzes(|quizzes| {
+        for quiz in quizzes.filter(|quiz| !quiz.is_archived()) {
+            for tag in quiz.tags.into_iter().flatten() {
+                *counts.entry(tag).or_insert(0) += 1;
+            }
+        }
+    });
+    let mut tags: Vec<(String, u64)> = counts.into_iter().collect();
+    tags.sort_by(|(tag_a, count_a), (tag_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| tag_a.cmp(tag_b))
+    });
+    tags
+}
+
+#[ic_cdk::update]
+fn add_tag_to_quizzes(ids: Vec<u64>, tag: String) -> Vec<Result<u64, Error>> {
+    let tag = normalize_tag(&tag);
+    ids.into_iter()
+        .map(|id| {
+            if tag.is_empty() {
+                return Err(Error::InvalidInput {
+                    msg: "tag must not be empty".to_string(),
+                });
+            }
+            ensure_storage_available()?;
+            let mut quiz = _get_quiz(&id).ok_or_else(|| Error::NotFound {
+                resource: Resource::Quiz,
+                msg: format!("couldn't tag a quiz with id={}. quiz not found", id),
+            })?;
+            ensure_can_manage(&quiz)?;
+            let tags = quiz.tags.get_or_insert_with(Vec::new);
+            if tags.contains(&tag) {
+                return Ok(id);
+            }
+            if tags.len() >= MAX_TAGS {
+                return Err(Error::InvalidInput {
+                    msg: format!("quiz with id={} already has {} tags", id, MAX_TAGS),
+                });
+            }
+            tags.push(tag.clone());
+            quiz.updated_at = Some(time());
+            do_insert(&quiz);
+            journal(id, format!("tag added: {}", tag));
+            Ok(id)
+        })
+        .collect()
+}
+
+#[ic_cdk::update]
+fn add_allowed_voter(id: u64, principal: Principal) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    let allowed = quiz.allowed_voters.get_or_insert_with(Vec::new);
+    if !allowed.contains(&principal) {
+        if allowed.len() >= MAX_ALLOWED_VOTERS {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "at most {} allowed voters are supported",
+                    MAX_ALLOWED_VOTERS
+                ),
+            });
+        }
+        allowed.push(principal);
+        quiz.updated_at = Some(time());
+        do_insert(&quiz);
+        journal(id, format!("allowed voter added: {}", principal));
+    }
+    Ok(quiz)
+}
+
+#[ic_cdk::update]
+fn set_max_weight(id: u64, cap: Option<u32>) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    quiz.max_weight_per_vote = cap;
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, format!("max weight per vote set to {:?}", cap));
+    Ok(quiz)
+}
+
+// Removing the last principal leaves an empty allowlist, which admits nobody.
+#[ic_cdk::update]
+fn remove_allowed_voter(id: u64, principal: Principal) -> Result<Quiz, Error> {
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if let Some(allowed) = quiz.allowed_voters.as_mut() {
+        allowed.retain(|p| *p != principal);
+        quiz.updated_at = Some(time());
+        do_insert(&quiz);
+        journal(id, format!("allowed voter removed: {}", principal));
+    }
+    Ok(quiz)
+}
+
+#[ic_cdk::update]
+fn answer_many(answers: Vec<(u64, u32, String)>) -> Vec<Result<Quiz, Error>> {
+    answers
+        .into_iter()
+        .map(|(id, question_index, option)| {
+            cast_vote(id, question_index, vec![option], 1, caller())
+        })
+        .collect()
+}
+
+// must run before the vote is recorded so a first answer can be told apart
+fn update_user_stats(quiz_id: u64, voter: Principal) {
+    let first_answer = !has_voted(quiz_id, &voter);
+    USER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        let key = StorablePrincipal(voter);
+        let mut user_stats = stats.get(&key).unwrap_or_default();
+        if first_answer {
+            user_stats.quizzes_answered += 1;
+        }
+        user_stats.last_answered_at = Some(time());
+        stats.insert(key, user_stats);
+    });
+}
+
+// Account linking: `from`'s votes become `into`'s. Where both voted on the
+// same quiz, `into`'s choice stands and `from`'s votes are taken back out of
+// the tallies. Returns how many quizzes were touched.
+#[ic_cdk::update]
+fn merge_voter(from: Principal, into: Principal) -> Result<u64, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    if from == into {
+        return Err(Error::InvalidInput {
+            msg: "can't merge a voter into itself".to_string(),
+        });
+    }
+
+    let mut by_quiz: BTreeMap<u64, Vec<LoggedVote>> = BTreeMap::new();
+    VOTE_LOG.with(|log| {
+        for (key, vote) in log.borrow().iter().filter(|(_, vote)| vote.voter == from) {
+            by_quiz.entry(key.0).or_default().push((key, vote));
+        }
+    });
+
+    let mut reassigned_quizzes = 0;
+    for (quiz_id, votes) in &by_quiz {
+        if has_voted(*quiz_id, &into) {
+            if let Some(mut quiz) = _get_quiz(quiz_id) {
+                for (_, vote) in votes {
+                    let count = quiz
+                        .tallies_mut(vote.question_index())
+                        .and_then(|answers| answers.get_mut(&vote.option));
+                    if let Some(count) = count {
+                        *count = count.saturating_sub(vote.weight);
+                    }
+                }
+                quiz.updated_at = Some(time());
+                do_insert(&quiz);
+            }
+            VOTE_LOG.with(|log| {
+                let mut log = log.borrow_mut();
+                for (key, _) in votes {
+                    log.remove(key);
+                }
+            });
+        } else {
+            VOTE_LOG.with(|log| {
+                let mut log = log.borrow_mut();
+                for (key, vote) in votes {
+                    let mut vote = vote.clone();
+                    vote.voter = into;
+                    log.insert(*key, vote);
+                }
+            });
+            reassigned_quizzes += 1;
+        }
+    }
+
+    USER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        if let Some(from_stats) = stats.remove(&StorablePrincipal(from)) {
+            let key = StorablePrincipal(into);
+            let mut into_stats = stats.get(&key).unwrap_or_default();
+            into_stats.quizzes_answered += reassigned_quizzes;
+            into_stats.last_answered_at =
+                into_stats.last_answered_at.max(from_stats.last_answered_at);
+            stats.insert(key, into_stats);
+        }
+    });
+    Ok(by_quiz.len() as u64)
+}
+
+#[ic_cdk::query]
+fn get_user_stats(principal: Principal) -> UserStats {
+    USER_STATS.with(|stats| {
+        stats
+            .borrow()
+            .get(&StorablePrincipal(principal))
+            .unwrap_or_default()
+    })
+}
+
+#[ic_cdk::query]
+fn get_my_quizzes() -> Vec<Quiz> {
+    let me = caller();
+    with_quizzes(|quizzes| quizzes.filter(|quiz| quiz.owner == Some(me)).collect())
+}
+
+#[ic_cdk::query]
+fn get_own_unanswered(creator: Principal) -> Vec<Quiz> {
+    with_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.owner == Some(creator))
+            .filter(|quiz| !has_voted(quiz.id, &creator))
+            .filter_map(visible)
+            .collect()
+    })
+}
+
+fn has_voted(quiz_id: u64, voter: &Principal) -> bool {
+    VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .any(|(_, vote)| vote.voter == *voter)
+    })
+}
+
+fn votes_of(quiz_id: u64, voter: &Principal) -> Vec<LoggedVote> {
+    VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .filter(|(_, vote)| vote.voter == *voter)
+            .collect()
+    })
+}
+
+fn distinct_voters(quiz_id: u64) -> Vec<Principal> {
+    let mut voters: Vec<Principal> = VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .map(|(_, vote)| vote.voter)
+            .collect()
+    });
+    voters.sort();
+    voters.dedup();
+    voters
+}
+
+fn voted_for(quiz_id: u64, voter: &Principal, option: &str) -> bool {
+    VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .any(|(_, vote)| vote.voter == *voter && vote.option == option)
+    })
+}
+
+fn last_vote_of(quiz_id: u64, voter: &Principal) -> Option<Vote> {
+    VOTE_LOG.with(|log| {
+        log.borrow()
+            .range((quiz_id, 0)..=(quiz_id, u64::MAX))
+            .filter(|(_, vote)| vote.voter == *voter)
+            .last()
+            .map(|(_, vote)| vote)
+    })
+}
+
+#[ic_cdk::update]
+fn create_quiz_set(payload: QuizSetPayload) -> Result<QuizSet, Error> {
+    ensure_storage_available()?;
+    if let Some(missing) = payload.quiz_ids.iter().find(|id| _get_quiz(id).is_none()) {
+        return Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!(
+                "couldn't create a quiz set. quiz with id={} not found",
+                missing
+            ),
+        });
+    }
+
+    let id = SET_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(current_value + 1)
+            .unwrap_or_else(|_| panic!("Cannot increment set id counter"))
+    });
+
+    let mut quiz_ids: Vec<u64> = Vec::new();
+    for quiz_id in payload.quiz_ids {
+        if !quiz_ids.contains(&quiz_id) {
+            quiz_ids.push(quiz_id);
+        }
+    }
+
+    let set = QuizSet {
+        id,
+        name: payload.name,
+        quiz_ids,
+        created_at: time(),
+        updated_at: None,
+    };
+    do_insert_set(&set);
+    Ok(set)
+}
+
+fn do_insert_set(set: &QuizSet) {
+    SETS.with(|sets| sets.borrow_mut().insert(set.id, set.clone()));
+}
+
+#[ic_cdk::query]
+fn get_quiz_set(set_id: u64) -> Result<QuizSet, Error> {
+    _get_quiz_set(&set_id).ok_or_else(|| Error::NotFound {
+        resource: Resource::QuizSet,
+        msg: format!("Quiz set with id={} not found", set_id),
+    })
+}
+
+fn _get_quiz_set(set_id: &u64) -> Option<QuizSet> {
+    SETS.with(|sets| sets.borrow().get(set_id))
+}
+
+#[ic_cdk::query]
+fn list_templates() -> Vec<QuizTemplate> {
+    TEMPLATES.with(|templates| {
+        templates
+            .borrow()
+            .iter()
+            .map(|(_, template)| template)
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn create_template(payload: TemplatePayload) -> Result<QuizTemplate, Error> {
+    ensure_storage_available()?;
+    ensure_admin()?;
+    insert_template(payload)
+}
+
+fn insert_template(payload: TemplatePayload) -> Result<QuizTemplate, Error> {
+    if payload.name.trim().is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "template name must not be empty".to_string(),
+        });
+    }
+    validate_question("question", &payload.question)?;
+    validate_options_for(payload.kind.unwrap_or_default(), &payload.options)?;
+
+    let id = TEMPLATE_ID_COUNTER.with(|counter| {
+        let current_value = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(current_value + 1)
+            .unwrap_or_else(|_| panic!("Cannot increment template id counter"))
+    });
+    let template = QuizTemplate {
+        id,
+        name: payload.name.trim().to_string(),
+        question: payload.question,
+        options: payload.options,
+        kind: payload.kind,
+        created_at: time(),
+    };
+    TEMPLATES.with(|templates| templates.borrow_mut().insert(id, template.clone()));
+    Ok(template)
+}
+
+#[ic_cdk::update]
+fn delete_template(template_id: u64) -> Result<QuizTemplate, Error> {
+    ensure_admin()?;
+    TEMPLATES
+        .with(|templates| templates.borrow_mut().remove(&template_id))
+        .ok_or_else(|| Error::NotFound {
+            resource: Resource::Template,
+            msg: format!("template with id={} not found", template_id),
+        })
+}
+
+// `substitutions` are (placeholder, text) pairs; every placeholder the
+// template uses needs one
+#[ic_cdk::update]
+async fn create_quiz_from_template(
+    template_id: u64,
+    substitutions: Vec<(String, String)>,
+) -> Result<Quiz, Error> {
+    let template = TEMPLATES
+        .with(|templates| templates.borrow().get(&template_id))
+        .ok_or_else(|| Error::NotFound {
+            resource: Resource::Template,
+            msg: format!("template with id={} not found", template_id),
+        })?;
+    let question = fill_placeholders(&template.question, &substitutions)?;
+    let options = template
+        .options
+        .iter()
+        .map(|option| fill_placeholders(option, &substitutions))
+        .collect::<Result<Vec<String>, Error>>()?;
+    let payload = QuizPayload {
+        question,
+        options,
+        kind: template.kind,
+        ..Default::default()
+    };
+    create_routed(caller(), payload).await
+}
+
+fn fill_placeholders(text: &str, substitutions: &[(String, String)]) -> Result<String, Error> {
+    let mut filled = text.to_string();
+    for (name, value) in substitutions {
+        filled = filled.replace(&format!("{{{}}}", name), value);
+    }
+    // whatever still looks like `{name}` had no substitution
+    if let Some(start) = filled.find('{') {
+        if let Some(len) = filled[start..].find('}') {
+            let placeholder = &filled[start..=start + len];
+            if !placeholder.contains(char::is_whitespace) {
+                return Err(Error::InvalidInput {
+                    msg: format!("no substitution given for {}", placeholder),
+                });
+            }
+        }
+    }
+    Ok(filled)
+}
+
+// the formats every canister starts out with; only seeded once, so ones an
+// admin deleted stay gone
+fn seed_templates() {
+    if TEMPLATE_ID_COUNTER.with(|counter| *counter.borrow().get()) != 0 {
+        return;
+    }
+    let builtin = [
+        ("Yes/No", "{question}", vec!["Yes", "No"]),
+        (
+            "Rating 1-5",
+            "How would you rate {subject}?",
+            vec!["1", "2", "3", "4", "5"],
+        ),
+        (
+            "Multiple choice A-D",
+            "{question}",
+            vec!["A: {a}", "B: {b}", "C: {c}", "D: {d}"],
+        ),
+    ];
+    for (name, question, options) in builtin {
+        let payload = TemplatePayload {
+            name: name.to_string(),
+            question: question.to_string(),
+            options: options.into_iter().map(String::from).collect(),
+            kind: None,
+        };
+        if let Err(Error::InvalidInput { msg } | Error::ValidationFailed { msg, .. }) =
+            insert_template(payload)
+        {
+            ic_cdk::println!("couldn't seed template '{}': {}", name, msg);
+        }
+    }
+}
+
+#[ic_cdk::update]
+fn add_quiz_to_set(set_id: u64, quiz_id: u64) -> Result<QuizSet, Error> {
+    let mut set = get_quiz_set(set_id)?;
+    if _get_quiz(&quiz_id).is_none() {
+        return Err(Error::NotFound {
+            resource: Resource::Quiz,
+            msg: format!("Quiz with id={} not found", quiz_id),
+        });
+    }
+
+    if !set.quiz_ids.contains(&quiz_id) {
+        set.quiz_ids.push(quiz_id);
+        set.updated_at = Some(time());
+        do_insert_set(&set);
+    }
+    Ok(set)
+}
+
+#[ic_cdk::update]
+fn clone_into_set(source_id: u64, set_id: u64) -> Result<Quiz, Error> {
+    let source = get_quiz(source_id)?;
+    ensure_access(&source, &caller(), None)?;
+    let mut set = get_quiz_set(set_id)?;
+    ensure_capacity()?;
+    take_rate_slot(RateAction::CreateQuiz, caller())?;
+
+    let mut clone = source.fork(next_quiz_id(), source.options.clone());
+    withhold_answer_key(&source, &mut clone);
+    clone.max_total_votes = source.max_total_votes;
+    clone.extra_questions = source.extra_questions.clone();
+    clone.reset_tallies();
+    do_insert(&clone);
+    schedule_expiry(&clone);
+    schedule_opening(&clone);
+    journal(clone.id, format!("cloned from quiz {}", source_id));
+
+    set.quiz_ids.push(clone.id);
+    set.updated_at = Some(time());
+    do_insert_set(&set);
+    Ok(clone)
+}
+
+#[ic_cdk::update]
+fn set_prerequisite(id: u64, prerequisite: Option<(u64, String)>) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if let Some((prior_id, option)) = &prerequisite {
+        if *prior_id == id {
+            return Err(Error::InvalidInput {
+                msg: "a quiz can't be its own prerequisite".to_string(),
+            });
+        }
+        let prior = get_quiz(*prior_id)?;
+        if !prior.options.contains(option) {
+            return Err(Error::NotFound {
+                resource: Resource::Option,
+                msg: format!("'{}' is not an option of quiz with id={}", option, prior_id),
+            });
+        }
+    }
+    quiz.prerequisite = prerequisite;
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "prerequisite changed".to_string());
+    Ok(quiz)
+}
+
+// The first quiz in set order the voter hasn't answered yet and whose
+// prerequisite, if any, they've met; None once nothing is left.
+#[ic_cdk::query]
+fn get_next_eligible_in_set(set_id: u64, voter: Principal) -> Result<Option<Quiz>, Error> {
+    let set = get_quiz_set(set_id)?;
+    Ok(set
+        .quiz_ids
+        .iter()
+        .filter(|quiz_id| !has_voted(**quiz_id, &voter))
+        .filter_map(_get_quiz)
+        .filter_map(visible)
+        .find(|quiz| {
+            quiz.prerequisite
+                .as_ref()
+                .is_none_or(|(prior_id, option)| voted_for(*prior_id, &voter, option))
+        }))
+}
+
+#[ic_cdk::query]
+fn get_set_participation(set_id: u64, voter: Principal) -> Result<ParticipationReport, Error> {
+    let set = get_quiz_set(set_id)?;
+    let unanswered: Vec<u64> = set
+        .quiz_ids
+        .iter()
+        .copied()
+        .filter(|quiz_id| !has_voted(*quiz_id, &voter))
+        .collect();
+
+    Ok(ParticipationReport {
+        set_id,
+        answered: (set.quiz_ids.len() - unanswered.len()) as u64,
+        total: set.quiz_ids.len() as u64,
+        unanswered,
+    })
+}
+
+// Closing is one-way: a second call leaves the quiz untouched and returns the
+// snapshot taken by the first one.
+#[ic_cdk::update]
+fn close_and_snapshot(id: u64) -> Result<QuizSnapshot, Error> {
+    ensure_storage_available()?;
+    let mut quiz = _get_quiz(&id).ok_or_else(|| Error::NotFound {
+        resource: Resource::Quiz,
+        msg: format!("couldn't close a quiz with id={}. quiz not found", id),
+    })?;
+    ensure_can_moderate(&quiz)?;
+
+    if quiz.status() == QuizStatus::Closed {
+        if let Some(snapshot) = latest_snapshot(id) {
+            return Ok(snapshot);
+        }
+    }
+    Ok(close(&mut quiz, "closed"))
+}
+
+// the part of `close_and_snapshot` past its checks
+fn close(quiz: &mut Quiz, description: &str) -> QuizSnapshot {
+    let closed_at = time();
+    quiz.status = Some(QuizStatus::Closed);
+    quiz.updated_at = Some(closed_at);
+    do_insert(quiz);
+    journal(quiz.id, description.to_string());
+
+    freeze_result(quiz, closed_at);
+    let snapshot = snapshot_of(quiz, closed_at);
+    store_snapshot(&snapshot);
+    notify_quiz_closed(&snapshot);
+    enqueue_webhook(quiz, "closed");
+    snapshot
+}
+
+// Timers live on the heap, so they're lost on upgrade; `post_upgrade`
+// schedules them again. A timer left over from an earlier `expires_at` finds
+// the quiz not yet expired and does nothing.
+fn schedule_expiry(quiz: &Quiz) {
+    if quiz.status() == QuizStatus::Closed {
+        return;
+    }
+    if let Some(expires_at) = quiz.expires_at {
+        let id = quiz.id;
+        let delay = Duration::from_nanos(expires_at.saturating_sub(time()));
+        set_timer(delay, move || close_if_expired(id));
+    }
+}
+
+// Same deal as `schedule_expiry`: a stale timer finds the quiz already open,
+// or its `opens_at` moved later, and does nothing.
+fn schedule_opening(quiz: &Quiz) {
+    if !quiz.is_scheduled() {
+        return;
+    }
+    if let Some(opens_at) = quiz.opens_at {
+        let id = quiz.id;
+        let delay = Duration::from_nanos(opens_at.saturating_sub(time()));
+        set_timer(delay, move || open_if_due(id));
+    }
+}
+
+fn open_if_due(id: u64) {
+    if let Some(mut quiz) = _get_quiz(&id) {
+        if quiz.is_scheduled() && quiz.opens_at.is_some_and(|opens_at| opens_at <= time()) {
+            quiz.status = Some(QuizStatus::Open);
+            quiz.updated_at = Some(time());
+            do_insert(&quiz);
+            journal(id, "opened on schedule".to_string());
+        }
+    }
+}
+
+fn close_if_expired(id: u64) {
+    if let Some(mut quiz) = _get_quiz(&id) {
+        if quiz.status() == QuizStatus::Open && quiz.is_expired(time()) {
+            close(&mut quiz, "closed on expiry");
+        }
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    let args = args.unwrap_or_default();
+    write_config(|config| {
+        config.schema_version = Some(SCHEMA_VERSION);
+        config.id_base = args.id_base;
+    });
+    if let Some(id_base) = args.id_base {
+        ID_COUNTER.with(|counter| {
+            counter
+                .borrow_mut()
+                .set(id_base)
+                .unwrap_or_else(|_| panic!("Cannot set id counter"))
+        });
+    }
+    add_admins(args.admins);
+    seed_templates();
+    schedule_pruning();
+    schedule_salt_fetch();
+    schedule_shard_check();
+}
+
+fn schedule_pruning() {
+    ic_cdk_timers::set_timer_interval(Duration::from_nanos(NANOS_PER_HOUR), || {
+        prune_idempotency_keys();
+        prune_rate_log();
+        decay_activity();
+    });
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    write_config(|config| config.last_upgraded_at = Some(time()));
+    migrate_all();
+    add_admins(args.unwrap_or_default().admins);
+    seed_templates();
+    schedule_pruning();
+    schedule_salt_fetch();
+    schedule_shard_check();
+    if !WEBHOOK_QUEUE.with(|queue| queue.borrow().is_empty()) {
+        schedule_webhook_delivery(Duration::ZERO);
+    }
+    // indexing is idempotent, so a quiz-less or tag-less canister redoing it
+    // costs nothing but cycles
+    let unindexed = OWNER_INDEX.with(|index| index.borrow().is_empty())
+        || TAG_INDEX.with(|index| index.borrow().is_empty())
+        || WORD_INDEX.with(|index| index.borrow().is_empty());
+    if unindexed {
+        index_all();
+    }
+    if LAST_ANSWERED.with(|last| last.borrow().is_empty()) {
+        rebuild_activity();
+    }
+    if RECEIPTS.with(|receipts| receipts.borrow().is_empty()) {
+        index_receipts();
+    }
+    with_quizzes(|quizzes| {
+        for quiz in quizzes {
+            certify(&quiz);
+            schedule_expiry(&quiz);
+            schedule_opening(&quiz);
+        }
+    });
+    FINAL_RESULTS.with(|results| {
+        for ((_, index), snapshot) in results.borrow().iter() {
+            certify_final_result(index, &snapshot);
+        }
+    });
+}
+
+// Records the current results without closing the quiz, so a poll can be
+// compared against itself later with `diff_snapshots`.
+#[ic_cdk::update]
+fn take_snapshot(id: u64) -> Result<u64, Error> {
+    ensure_storage_available()?;
+    let quiz = _get_quiz(&id).ok_or_else(|| Error::NotFound {
+        resource: Resource::Quiz,
+        msg: format!("couldn't snapshot a quiz with id={}. quiz not found", id),
+    })?;
+    ensure_can_manage(&quiz)?;
+    Ok(store_snapshot(&snapshot_of(&quiz, time())))
+}
+
+// `closed_at` is just the capture time for snapshots taken while still open.
+fn snapshot_of(quiz: &Quiz, taken_at: u64) -> QuizSnapshot {
+    QuizSnapshot {
+        quiz_id: quiz.id,
+        question: quiz.question.clone(),
+        options_with_counts: quiz.ordered_answers(),
+        total_votes: quiz.total_votes(),
+        closed_at: taken_at,
+    }
+}
+
+// appends after the quiz's latest snapshot and returns the new index
+fn store_snapshot(snapshot: &QuizSnapshot) -> u64 {
+    let id = snapshot.quiz_id;
+    SNAPSHOTS.with(|snapshots| {
+        let mut snapshots = snapshots.borrow_mut();
+        let index = match snapshots.iter_upper_bound(&(id, u64::MAX)).next() {
+            Some(((quiz_id, last_index), _)) if quiz_id == id => last_index + 1,
+            _ => 0,
+        };
+        snapshots.insert((id, index), snapshot.clone());
+        index
+    })
+}
+
+#[ic_cdk::update]
+fn archive_older_than(cutoff_ns: u64) -> Result<u64, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    let old: Vec<Quiz> = with_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| !quiz.is_archived() && quiz.created_at < cutoff_ns)
+            .collect()
+    });
+    let now = time();
+    for mut quiz in old.iter().cloned() {
+        quiz.archived = Some(true);
+        quiz.updated_at = Some(now);
+        do_insert(&quiz);
+        journal(quiz.id, "archived".to_string());
+    }
+    Ok(old.len() as u64)
+}
+
+// Expiry timers normally close quizzes on their own; this is a manual sweep
+// for any they missed. Quizzes closed here get a snapshot and a notification
+// just as if `close_and_snapshot` had been called on them. One that fails to
+// close doesn't stop the sweep; it's left for the next one and not counted.
+#[ic_cdk::update]
+fn close_expired_now() -> Result<u64, Error> {
+    ensure_controller()?;
+    let now = time();
+    let expired: Vec<u64> = with_quizzes(|quizzes| {
+        quizzes
+            .filter(|quiz| quiz.status() == QuizStatus::Open && quiz.is_expired(now))
+            .map(|quiz| quiz.id)
+            .collect()
+    });
+    let closed = expired
+        .into_iter()
+        .filter(|id| close_and_snapshot(*id).is_ok())
+        .count();
+    Ok(closed as u64)
+}
+
+// Opens a draft, or reopens a closed quiz with its tallies kept (see
+// `start_new_round` for a fresh start).
+#[ic_cdk::update]
+fn open_quiz(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if quiz.status() != QuizStatus::Open {
+        let description = match quiz.status() {
+            QuizStatus::Draft => "opened",
+            _ => "reopened",
+        };
+        quiz.status = Some(QuizStatus::Open);
+        quiz.updated_at = Some(time());
+        do_insert(&quiz);
+        journal(id, description.to_string());
+    }
+    Ok(quiz)
+}
+
+// `close_and_snapshot` for callers who want the quiz back, not the snapshot
+#[ic_cdk::update]
+fn close_quiz(id: u64) -> Result<Quiz, Error> {
+    close_and_snapshot(id)?;
+    get_quiz(id)
+}
+
+// Each id succeeds or fails on its own, through `close_and_snapshot` or
+// `open_quiz`. Nothing goes back to being a draft.
+#[ic_cdk::update]
+fn set_status_bulk(ids: Vec<u64>, status: QuizStatus) -> Vec<Result<u64, Error>> {
+    ids.into_iter()
+        .map(|id| {
+            match status {
+                QuizStatus::Closed => {
+                    close_and_snapshot(id)?;
+                }
+                QuizStatus::Open => {
+                    open_quiz(id)?;
+                }
+                QuizStatus::Draft => {
+                    return Err(Error::InvalidInput {
+                        msg: "a quiz can't be turned back into a draft".to_string(),
+                    });
+                }
+            }
+            Ok(id)
+        })
+        .collect()
+}
+
+// Private quizzes let in their managers, admins, participants, anyone who
+// already voted, and holders of the invite code. Archived ones only let in
+// their owner and admins. Everything else is open.
+fn ensure_access(
+    quiz: &Quiz,
+    principal: &Principal,
+    invite_code: Option<&str>,
+) -> Result<(), Error> {
+    if quiz.is_archived() {
+        if quiz.owner == Some(*principal) || is_admin(principal) {
+            return Ok(());
+        }
+        return Err(Error::AccessDenied {
+            msg: format!("quiz with id={} is archived", quiz.id),
+        });
+    }
+    if !quiz.is_private()
+        || quiz.owner == Some(*principal)
+        || is_admin(principal)
+        || quiz
+            .allowed_voters
+            .as_ref()
+            .is_some_and(|allowed| allowed.contains(principal))
+        || (invite_code.is_some() && invite_code == quiz.invite_code.as_deref())
+        || (*principal != Principal::anonymous() && has_voted(quiz.id, principal))
+    {
+        return Ok(());
+    }
+    Err(Error::AccessDenied {
+        msg: format!("quiz with id={} is private", quiz.id),
+    })
+}
+
+fn ensure_token_gate_passed(quiz: &Quiz, passed: bool) -> Result<(), Error> {
+    if quiz.token_gate.is_some() && !passed {
+        return Err(Error::AccessDenied {
+            msg: format!(
+                "quiz with id={} is token-gated; answer it with answer_quiz",
+                quiz.id
+            ),
+        });
+    }
+    Ok(())
+}
+
+// On a private quiz the invite code stands in for `allowed_voters`.
+fn ensure_may_vote(
+    quiz: &Quiz,
+    principal: &Principal,
+    invite_code: Option<&str>,
+) -> Result<(), Error> {
+    if quiz.is_private() {
+        return ensure_access(quiz, principal, invite_code);
+    }
+    if !quiz.is_allowed_voter(principal) {
+        return Err(Error::Unauthorized {
+            msg: format!("caller is not allowed to vote on quiz with id={}", quiz.id),
+        });
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn add_participant(id: u64, principal: Principal) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    add_allowed_voter(id, principal)
+}
+
+#[ic_cdk::update]
+fn remove_participant(id: u64, principal: Principal) -> Result<Quiz, Error> {
+    remove_allowed_voter(id, principal)
+}
+
+// Replaces the quiz's invite code with a fresh random one, which stops the
+// old one from working.
+#[ic_cdk::update]
+async fn regenerate_invite_code(id: u64) -> Result<String, Error> {
+    ensure_storage_available()?;
+    ensure_can_manage(&get_quiz(id)?)?;
+    let (bytes,) = raw_rand().await.map_err(|(_, msg)| Error::CallFailed {
+        canister: Principal::management_canister(),
+        msg: format!("couldn't get randomness: {}", msg),
+    })?;
+    let code: String = bytes[..INVITE_CODE_BYTES]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    // the quiz may have changed while waiting for randomness
+    let mut quiz = get_quiz(id)?;
+    quiz.invite_code = Some(code.clone());
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "invite code regenerated".to_string());
+    Ok(code)
+}
+
+// the quiz's owner or a controller
+fn ensure_can_manage(quiz: &Quiz) -> Result<(), Error> {
+    if quiz.owner == Some(caller()) || is_controller(&caller()) {
+        return Ok(());
+    }
+    Err(Error::Unauthorized {
+        msg: format!("caller can't manage quiz with id={}", quiz.id),
+    })
+}
+
+// `None` removes the webhook. Deliveries already queued still go out.
+#[ic_cdk::update]
+fn set_webhook(id: u64, url: Option<String>) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if let Some(url) = &url {
+        if !url.starts_with("https://") || url.len() > MAX_WEBHOOK_URL_LEN {
+            return Err(Error::ValidationFailed {
+                field: "url".to_string(),
+                msg: format!(
+                    "a webhook url must be https and at most {} bytes",
+                    MAX_WEBHOOK_URL_LEN
+                ),
+            });
+        }
+    }
+    quiz.webhook_url = url;
+    quiz.updated_at = Some(time());
+    do_insert(&quiz);
+    journal(id, "webhook changed".to_string());
+    Ok(quiz)
+}
+
+fn enqueue_webhook(quiz: &Quiz, event: &str) {
+    let Some(url) = quiz.webhook_url.clone() else {
+        return;
+    };
+    let body = serde_json::json!({
+        "event": event,
+        "quiz_id": quiz.id,
+        "question": quiz.question,
+        "status": match quiz.status() {
+            QuizStatus::Open => "open",
+            QuizStatus::Closed => "closed",
+            QuizStatus::Draft => "draft",
+        },
+        "total_votes": quiz.total_votes(),
+        "results": quiz.ordered_answers(),
+        "timestamp": time(),
+    });
+    WEBHOOK_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let seq = queue.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        queue.insert(
+            seq,
+            WebhookDelivery {
+                quiz_id: quiz.id,
+                url,
+                body: body.to_string(),
+                attempts: 0,
+                next_attempt_at: time(),
+            },
+        );
+    });
+    schedule_webhook_delivery(Duration::ZERO);
+}
+
+fn schedule_webhook_delivery(delay: Duration) {
+    set_timer(delay, || ic_cdk::spawn(deliver_webhooks()));
+}
+
+// Sends every due delivery, one at a time. A failed one waits twice as long
+// as the time before, until MAX_WEBHOOK_ATTEMPTS.
+async fn deliver_webhooks() {
+    if DELIVERING_WEBHOOKS.with(|delivering| delivering.replace(true)) {
+        return;
+    }
+    let now = time();
+    let due: Vec<(u64, WebhookDelivery)> = WEBHOOK_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .filter(|(_, delivery)| delivery.next_attempt_at <= now)
+            .collect()
+    });
+    for (seq, mut delivery) in due {
+        if post_webhook(seq, &delivery).await {
+            WEBHOOK_QUEUE.with(|queue| queue.borrow_mut().remove(&seq));
+            continue;
+        }
+        delivery.attempts += 1;
+        if delivery.attempts >= MAX_WEBHOOK_ATTEMPTS {
+            ic_cdk::println!(
+                "giving up on webhook {} for quiz id={} after {} attempts",
+                seq,
+                delivery.quiz_id,
+                delivery.attempts
+            );
+            WEBHOOK_QUEUE.with(|queue| queue.borrow_mut().remove(&seq));
+            continue;
+        }
+        let backoff = WEBHOOK_RETRY_BASE_NS << (delivery.attempts - 1);
+        delivery.next_attempt_at = time() + backoff;
+        WEBHOOK_QUEUE.with(|queue| queue.borrow_mut().insert(seq, delivery));
+        schedule_webhook_delivery(Duration::from_nanos(backoff));
+    }
+    DELIVERING_WEBHOOKS.with(|delivering| *delivering.borrow_mut() = false);
+}
+
+// Every replica sends the request, so receivers see it several times; the
+// Idempotency-Key header is there to tell the copies apart from real repeats.
+async fn post_webhook(seq: u64, delivery: &WebhookDelivery) -> bool {
+    let request = CanisterHttpRequestArgument {
+        url: delivery.url.clone(),
+        max_response_bytes: Some(WEBHOOK_MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+            HttpHeader {
+                name: "Idempotency-Key".to_string(),
+                value: format!("{}-{}", ic_cdk::id(), seq),
+            },
+        ],
+        body: Some(delivery.body.clone().into_bytes()),
+        transform: Some(TransformContext::from_name(
+            "transform_webhook_response".to_string(),
+            vec![],
+        )),
+    };
+    match outcall(request, WEBHOOK_CYCLES).await {
+        Ok((response,)) => response.status >= 200u16 && response.status < 300u16,
+        Err((_, msg)) => {
+            ic_cdk::println!("webhook {} failed: {}", seq, msg);
+            false
+        }
+    }
+}
+
+// Replicas only agree on a response if it's byte-for-byte the same, so all
+// but the status is dropped.
+#[ic_cdk::query]
+fn transform_webhook_response(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+// Fire-and-forget: a missing or failing target must never block closing.
+fn notify_quiz_closed(snapshot: &QuizSnapshot) {
+    if let Some(target) = get_config().close_notification_target {
+        let _ = notify(target, "on_quiz_closed", (snapshot.clone(),));
+    }
+}
+
+fn latest_snapshot(quiz_id: u64) -> Option<QuizSnapshot> {
+    SNAPSHOTS.with(|snapshots| {
+        match snapshots
+            .borrow()
+            .iter_upper_bound(&(quiz_id, u64::MAX))
+            .next()
+        {
+            Some(((id, _), snapshot)) if id == quiz_id => Some(snapshot),
+            _ => None,
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn get_snapshot(id: u64) -> Result<QuizSnapshot, Error> {
+    get_quiz_for_results(id)?;
+    latest_snapshot(id).ok_or_else(|| Error::NotFound {
+        resource: Resource::Snapshot,
+        msg: format!("no snapshot found for quiz with id={}", id),
+    })
+}
+
+#[ic_cdk::query]
+fn diff_snapshots(id: u64, from_index: u64, to_index: u64) -> Result<SnapshotDiff, Error> {
+    if from_index >= to_index {
+        return Err(Error::InvalidInput {
+            msg: "from_index must be lower than to_index".to_string(),
+        });
+    }
+    get_quiz_for_results(id)?;
+    let snapshot_at = |index: u64| {
+        SNAPSHOTS
+            .with(|snapshots| snapshots.borrow().get(&(id, index)))
+            .ok_or_else(|| Error::NotFound {
+                resource: Resource::Snapshot,
+                msg: format!("no snapshot {} found for quiz with id={}", index, id),
+            })
+    };
+    let from = snapshot_at(from_index)?;
+    let to = snapshot_at(to_index)?;
+
+    let before: HashMap<&str, u32> = from
+        .options_with_counts
+        .iter()
+        .map(|(option, count)| (option.as_str(), *count))
+        .collect();
+    let mut deltas: Vec<(String, i64)> = to
+        .options_with_counts
+        .iter()
+        .map(|(option, count)| {
+            let previous = before.get(option.as_str()).copied().unwrap_or(0);
+            (option.clone(), *count as i64 - previous as i64)
+        })
+        .collect();
+    for (option, count) in &from.options_with_counts {
+        if !to.options_with_counts.iter().any(|(o, _)| o == option) {
+            deltas.push((option.clone(), -(*count as i64)));
+        }
+    }
+
+    Ok(SnapshotDiff {
+        quiz_id: id,
+        from_index,
+        to_index,
+        deltas,
+        total_delta: to.total_votes as i64 - from.total_votes as i64,
+    })
+}
+
+// Starts the quiz over with empty tallies. The previous round's results only
+// survive in its snapshots, so one must have been taken when it closed.
+#[ic_cdk::update]
+fn start_new_round(id: u64) -> Result<Quiz, Error> {
+    ensure_storage_available()?;
+    let mut quiz = get_quiz(id)?;
+    ensure_can_manage(&quiz)?;
+    if quiz.status() != QuizStatus::Closed || latest_snapshot(id).is_none() {
+        return Err(Error::Conflict {
+            msg: format!(
+                "quiz with id={} must be closed with a snapshot before a new round",
+                id
+            ),
+        });
+    }
+
+    let now = time();
+    quiz.reset_tallies();
+    clear_votes(id);
+    quiz.status = Some(QuizStatus::Open);
+    quiz.locked = Some(false);
+    if quiz.is_expired(now) {
+        quiz.expires_at = None;
+    }
+    quiz.round = Some(quiz.round().saturating_add(1));
+    quiz.updated_at = Some(now);
+    do_insert(&quiz);
+    journal(id, format!("round {} started", quiz.round()));
+    Ok(quiz)
+}
+
+// the leader's share at each snapshot, oldest first; 0 for empty snapshots
+#[ic_cdk::query]
+fn get_decisiveness_trend(id: u64) -> Result<Vec<f64>, Error> {
+    get_quiz_for_results(id)?;
+    Ok(SNAPSHOTS.with(|snapshots| {
+        snapshots
+            .borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(_, snapshot)| {
+                let leader = snapshot
+                    .options_with_counts
+                    .iter()
+                    .map(|(_, count)| *count)
+                    .max()
+                    .unwrap_or(0);
+                if snapshot.total_votes == 0 {
+                    0.0
+                } else {
+                    round_result(leader as f64 / snapshot.total_votes as f64)
+                }
+            })
+            .collect()
+    }))
+}
+
+// managers plus admins, who may delete or close any quiz
+fn ensure_can_moderate(quiz: &Quiz) -> Result<(), Error> {
+    if ensure_can_manage(quiz).is_ok() || is_admin(&caller()) {
+        return Ok(());
+    }
+    Err(Error::Unauthorized {
+        msg: format!("caller can't moderate quiz with id={}", quiz.id),
+    })
+}
+
+// controllers count as admins without being listed
+fn is_admin(principal: &Principal) -> bool {
+    is_controller(principal)
+        || ADMINS.with(|admins| admins.borrow().contains_key(&StorablePrincipal(*principal)))
+}
+
+fn ensure_admin() -> Result<(), Error> {
+    if !is_admin(&caller()) {
+        return Err(Error::Unauthorized {
+            msg: "only an admin of this canister can do this".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn add_admins(principals: Vec<Principal>) {
+    ADMINS.with(|admins| {
+        let mut admins = admins.borrow_mut();
+        for principal in principals {
+            admins.insert(StorablePrincipal(principal), ());
+        }
+    });
+}
+
+#[ic_cdk::update]
+fn add_admin(principal: Principal) -> Result<(), Error> {
+    ensure_storage_available()?;
+    ensure_admin()?;
+    add_admins(vec![principal]);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn remove_admin(principal: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    ADMINS.with(|admins| admins.borrow_mut().remove(&StorablePrincipal(principal)));
+    Ok(())
+}
+
+// listed admins only; controllers are admins too but aren't listed
+#[ic_cdk::query]
+fn get_admins() -> Vec<Principal> {
+    ADMINS.with(|admins| {
+        admins
+            .borrow()
+            .iter()
+            .map(|(principal, _)| principal.0)
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn add_shard(canister: Principal) -> Result<(), Error> {
+    ensure_storage_available()?;
+    ensure_admin()?;
+    SHARDS.with(|shards| shards.borrow_mut().insert(StorablePrincipal(canister), ()));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn remove_shard(canister: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    SHARDS.with(|shards| shards.borrow_mut().remove(&StorablePrincipal(canister)));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_shards() -> Vec<Principal> {
+    SHARDS.with(|shards| {
+        shards
+            .borrow()
+            .iter()
+            .map(|(canister, _)| canister.0)
+            .collect()
+    })
+}
+
+fn is_shard(principal: &Principal) -> bool {
+    SHARDS.with(|shards| shards.borrow().contains_key(&StorablePrincipal(*principal)))
+}
+
+// What a peer fetches from each shard: this canister's raw tallies for the
+// quiz, whatever its results visibility. Only for registered shards and admins,
+// since the peer does the visibility check for its own caller.
+#[ic_cdk::query]
+fn get_shard_tallies(quiz_id: u64) -> ShardTallies {
+    if !is_shard(&caller()) && !is_admin(&caller()) {
+        return Err(Error::Unauthorized {
+            msg: "only a registered shard can read raw tallies".to_string(),
+        });
+    }
+    Ok(get_quiz(quiz_id)?.ordered_answers())
+}
+
+// The quiz's tallies here plus those of every registered shard, summed per
+// option in this canister's option order. A shard that doesn't have the quiz
+// adds nothing; one that can't be reached is listed in `failed_shards`.
+#[ic_cdk::query(composite = true)]
+async fn get_aggregated_results(quiz_id: u64) -> Result<AggregatedResults, Error> {
+    let quiz = get_quiz(quiz_id)?;
+    ensure_access(&quiz, &caller(), None)?;
+    ensure_results_visible(&quiz)?;
+
+    let mut totals: BTreeMap<String, u64> = quiz
+        .ordered_answers()
+        .into_iter()
+        .map(|(option, count)| (option, count as u64))
+        .collect();
+    let mut failed_shards = Vec::new();
+    let shards = get_shards();
+    for shard in &shards {
+        let result: Result<(ShardTallies,), _> =
+            ic_cdk::call(*shard, "get_shard_tallies", (quiz_id,)).await;
+        match result {
+            Ok((Ok(tallies),)) => {
+                for (option, count) in tallies {
+                    // options a shard has and this canister doesn't are dropped
+                    if let Some(total) = totals.get_mut(&option) {
+                        *total += count as u64;
+                    }
+                }
+            }
+            Ok((Err(Error::NotFound { .. }),)) => {}
+            _ => failed_shards.push(*shard),
+        }
+    }
+
+    let results: Vec<(String, u64)> = quiz
+        .options
+        .iter()
+        .map(|option| (option.clone(), totals.get(option).copied().unwrap_or(0)))
+        .collect();
+    Ok(AggregatedResults {
+        quiz_id,
+        total_votes: results.iter().map(|(_, count)| count).sum(),
+        results,
+        shards_queried: shards.len() as u32,
+        failed_shards,
+    })
+}
+
+// The shard taking new quizzes, if this canister has spawned one. A shard
+// that fills up spawns its own, so each canister only ever needs one.
+fn active_shard() -> Option<Principal> {
+    ROUTES.with(|routes| routes.borrow().last_key_value().map(|(_, shard)| shard.0))
+}
+
+// None for ids this canister holds itself
+fn shard_for(id: u64) -> Option<Principal> {
+    ROUTES.with(|routes| {
+        routes
+            .borrow()
+            .iter_upper_bound(&id.saturating_add(1))
+            .next()
+            .map(|(_, shard)| shard.0)
+    })
+}
+
+// where to send calls about quiz `id`, following one hop; the shard may
+// forward it further
+#[ic_cdk::query]
+fn resolve_quiz_canister(id: u64) -> Principal {
+    shard_for(id).unwrap_or_else(ic_cdk::id)
+}
+
+// Starts over when `first` is set; returns the wasm's size so far.
+#[ic_cdk::update]
+fn upload_shard_wasm(chunk: Vec<u8>, first: bool) -> Result<u64, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    SHARD_WASM.with(|wasm| {
+        let mut wasm = wasm.borrow_mut();
+        if first {
+            let chunks: Vec<u32> = wasm.iter().map(|(index, _)| index).collect();
+            for index in chunks {
+                wasm.remove(&index);
+            }
+        }
+        let next = wasm.last_key_value().map_or(0, |(index, _)| index + 1);
+        wasm.insert(next, chunk);
+        Ok(wasm.iter().map(|(_, chunk)| chunk.len() as u64).sum())
+    })
+}
+
+#[ic_cdk::update]
+fn set_shard_threshold(threshold_bytes: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.shard_threshold_bytes = threshold_bytes)
+}
+
+// for spawning a shard ahead of the threshold
+#[ic_cdk::update]
+async fn spawn_shard() -> Result<Principal, Error> {
+    ensure_storage_available()?;
+    ensure_controller()?;
+    spawn_shard_once().await
+}
+
+fn schedule_shard_check() {
+    ic_cdk_timers::set_timer_interval(Duration::from_nanos(NANOS_PER_HOUR), || {
+        ic_cdk::spawn(spawn_shard_if_full())
+    });
+}
+
+async fn spawn_shard_if_full() {
+    let Some(threshold) = get_config().shard_threshold_bytes else {
+        return;
+    };
+    let used = stable_bytes_used();
+    if used < threshold || active_shard().is_some() {
+        return;
+    }
+    if let Err(
+        Error::InvalidInput { msg } | Error::Conflict { msg } | Error::CallFailed { msg, .. },
+    ) = spawn_shard_once().await
+    {
+        ic_cdk::println!("couldn't spawn a shard: {}", msg);
+    }
+}
+
+async fn spawn_shard_once() -> Result<Principal, Error> {
+    if active_shard().is_some() {
+        return Err(Error::Conflict {
+            msg: "this canister already has a shard".to_string(),
+        });
+    }
+    if SPAWNING_SHARD.with(|spawning| spawning.replace(true)) {
+        return Err(Error::Conflict {
+            msg: "a shard is already being spawned".to_string(),
+        });
+    }
+    let result = create_shard().await;
+    SPAWNING_SHARD.with(|spawning| *spawning.borrow_mut() = false);
+    result
+}
+
+// If installing fails the empty canister is left behind; the next attempt
+// creates a fresh one.
+async fn create_shard() -> Result<Principal, Error> {
+    let wasm_module: Vec<u8> =
+        SHARD_WASM.with(|wasm| wasm.borrow().iter().flat_map(|(_, chunk)| chunk).collect());
+    if wasm_module.is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "upload the shard wasm with upload_shard_wasm first".to_string(),
+        });
+    }
+    let settings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::id()]),
+        ..Default::default()
+    };
+    let (record,) = create_canister(
+        CreateCanisterArgument {
+            settings: Some(settings),
+        },
+        SHARD_CYCLES,
+    )
+    .await
+    .map_err(|(_, msg)| Error::CallFailed {
+        canister: Principal::management_canister(),
+        msg: format!("couldn't create a shard: {}", msg),
+    })?;
+    let shard = record.canister_id;
+    let id_base = get_config().id_base.unwrap_or(0) + SHARD_ID_RANGE;
+    let args = Some(InitArgs {
+        admins: get_admins(),
+        id_base: Some(id_base),
+    });
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: shard,
+        wasm_module,
+        arg: Encode!(&args).unwrap(),
+    })
+    .await
+    .map_err(|(_, msg)| Error::CallFailed {
+        canister: Principal::management_canister(),
+        msg: format!("couldn't install shard {}: {}", shard, msg),
+    })?;
+    ROUTES.with(|routes| {
+        routes
+            .borrow_mut()
+            .insert(id_base, StorablePrincipal(shard))
+    });
+    Ok(shard)
+}
+
+fn ensure_controller() -> Result<(), Error> {
+    if !is_controller(&caller()) {
+        return Err(Error::Unauthorized {
+            msg: "only a controller of this canister can do this".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// the salt stays secret, or anyone could work out every voter's option order
+#[ic_cdk::query(name = "get_config")]
+fn get_public_config() -> Config {
+    Config {
+        shuffle_salt: None,
+        ..get_config()
+    }
+}
+
+fn get_config() -> Config {
+    CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn update_config(f: impl FnOnce(&mut Config)) -> Result<Config, Error> {
+    ensure_controller()?;
+    Ok(write_config(f))
+}
+
+fn write_config(f: impl FnOnce(&mut Config)) -> Config {
+    CONFIG.with(|config| {
+        let mut updated = config.borrow().get().clone();
+        f(&mut updated);
+        config
+            .borrow_mut()
+            .set(updated.clone())
+            .unwrap_or_else(|_| panic!("Cannot update the config"));
+        updated
+    })
+}
+
+#[ic_cdk::update]
+fn set_max_quizzes(max_quizzes: u64) -> Result<Config, Error> {
+    update_config(|config| config.max_quizzes = max_quizzes)
+}
+
+// checked against each quiz's attempt log on every answer
+#[ic_cdk::update]
+fn set_max_attempts_per_quiz(max_attempts: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.max_attempts_per_quiz = max_attempts)
+}
+
+// Raising it past what the subnet allows only moves the failure back to a
+// trap.
+#[ic_cdk::update]
+fn set_storage_budget(budget_bytes: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.storage_budget_bytes = budget_bytes)
+}
+
+#[ic_cdk::query]
+fn get_rate_limits() -> RateLimits {
+    get_config().rate_limits.unwrap_or_default()
+}
+
+// takes effect on the next call; what's already been counted stays counted
+#[ic_cdk::update]
+fn set_rate_limits(limits: RateLimits) -> Result<RateLimits, Error> {
+    ensure_admin()?;
+    if limits.quizzes_per_hour == Some(0)
+        || limits.answers_per_hour == Some(0)
+        || limits.comments_per_hour == Some(0)
+    {
+        return Err(Error::InvalidInput {
+            msg: "a rate limit must be at least 1; None removes it".to_string(),
+        });
+    }
+    write_config(|config| config.rate_limits = Some(limits));
+    Ok(limits)
+}
+
+#[ic_cdk::update]
+fn ban_voter(principal: Principal) -> Result<(), Error> {
+    ensure_controller()?;
+    BANNED.with(|banned| banned.borrow_mut().insert(StorablePrincipal(principal), ()));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn unban_voter(principal: Principal) -> Result<(), Error> {
+    ensure_controller()?;
+    BANNED.with(|banned| banned.borrow_mut().remove(&StorablePrincipal(principal)));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn is_banned(principal: Principal) -> bool {
+    BANNED.with(|banned| banned.borrow().contains_key(&StorablePrincipal(principal)))
+}
+
+#[ic_cdk::update]
+fn set_normalization_mode(mode: NormalizationMode) -> Result<Config, Error> {
+    update_config(|config| config.normalization_mode = Some(mode))
+}
+
+// values above MAX_RESULT_PRECISION are clamped to it
+#[ic_cdk::update]
+fn set_result_precision(result_precision: u8) -> Result<Config, Error> {
+    update_config(|config| {
+        config.result_precision = Some(result_precision.min(MAX_RESULT_PRECISION))
+    })
+}
+
+#[ic_cdk::update]
+fn set_max_question_len(max_question_len: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.max_question_len = max_question_len)
+}
+
+#[ic_cdk::update]
+fn set_allow_anonymous(allow_anonymous: bool) -> Result<Config, Error> {
+    ensure_admin()?;
+    Ok(write_config(|config| {
+        config.allow_anonymous = Some(allow_anonymous)
+    }))
+}
+
+fn anonymous_allowed() -> bool {
+    get_config().allow_anonymous == Some(true)
+}
+
+#[ic_cdk::update]
+fn set_max_option_len(max_option_len: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.max_option_len = max_option_len)
+}
+
+#[ic_cdk::update]
+fn set_min_option_len(min_option_len: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.min_option_len = min_option_len)
+}
+
+#[ic_cdk::update]
+fn set_default_expiry(default_expiry_ns: Option<u64>) -> Result<Config, Error> {
+    update_config(|config| config.default_expiry_ns = default_expiry_ns)
+}
+
+#[ic_cdk::update]
+fn set_close_notification_target(target: Option<Principal>) -> Result<Config, Error> {
+    update_config(|config| config.close_notification_target = target)
+}
+
+#[ic_cdk::query]
+fn check_integrity() -> Result<IntegrityReport, Error> {
+    ensure_controller()?;
+    Ok(find_orphans())
+}
+
+fn find_orphans() -> IntegrityReport {
+    let orphaned_set_refs = SETS.with(|sets| {
+        sets.borrow()
+            .iter()
+            .flat_map(|(set_id, set)| {
+                set.quiz_ids
+                    .into_iter()
+                    .filter(|quiz_id| _get_quiz(quiz_id).is_none())
+                    .map(move |quiz_id| (set_id, quiz_id))
+            })
+            .collect()
+    });
+    let orphaned_vote_keys = VOTE_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(quiz_id, _)| _get_quiz(quiz_id).is_none())
+            .collect()
+    });
+
+    IntegrityReport {
+        orphaned_set_refs,
+        orphaned_vote_keys,
+    }
+}
+
+#[ic_cdk::update]
+fn purge_orphans() -> Result<PurgeSummary, Error> {
+    ensure_controller()?;
+    let report = find_orphans();
+
+    for (set_id, quiz_id) in &report.orphaned_set_refs {
+        if let Some(mut set) = _get_quiz_set(set_id) {
+            set.quiz_ids.retain(|id| id != quiz_id);
+            set.updated_at = Some(time());
+            do_insert_set(&set);
+        }
+    }
+    VOTE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        for key in &report.orphaned_vote_keys {
+            log.remove(key);
+        }
+    });
+
+    Ok(PurgeSummary {
+        set_refs_removed: report.orphaned_set_refs.len() as u64,
+        vote_records_removed: report.orphaned_vote_keys.len() as u64,
+    })
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Serialize)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// Public, read-only results for dashboards: GET /quiz/<id> is JSON and
+// /quiz/<id>.html a bare page. Callers here are anonymous, so tallies follow
+// `results_visibility` as for any other anonymous caller.
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return http_response(405, "text/plain", b"method not allowed".to_vec());
+    }
+    let path = request.url.split(['?', '#']).next().unwrap_or_default();
+    let Some(rest) = path.strip_prefix("/quiz/") else {
+        return http_response(404, "text/plain", b"not found".to_vec());
+    };
+    let (id, html) = match rest.strip_suffix(".html") {
+        Some(id) => (id, true),
+        None => (rest, false),
+    };
+    // private quizzes aren't served over plain http
+    let Some(quiz) = id
+        .parse::<u64>()
+        .ok()
+        .and_then(|id| _get_quiz(&id))
+        .filter(|quiz| !quiz.is_private())
+    else {
+        return http_response(404, "text/plain", b"quiz not found".to_vec());
+    };
+    let quiz = quiz.redacted();
+    // cleared by `redacted` when the caller may not see them
+    let results = (!quiz.answers.is_empty()).then(|| quiz.ordered_answers());
+
+    if html {
+        let mut page = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{0}</title></head><body><h1>{0}</h1><ul>",
+            escape_html(&quiz.question)
+        );
+        for option in &quiz.options {
+            let count = results
+                .as_ref()
+                .and_then(|results| results.iter().find(|(text, _)| text == option))
+                .map(|(_, count)| format!(": {}", count))
+                .unwrap_or_default();
+            page.push_str(&format!("<li>{}{}</li>", escape_html(option), count));
+        }
+        page.push_str("</ul></body></html>");
+        return http_response(200, "text/html; charset=utf-8", page.into_bytes());
+    }
+
+    let json = serde_json::json!({
+        "id": quiz.id,
+        "question": quiz.question,
+        "options": quiz.options,
+        "status": quiz.status(),
+        // null while results are hidden
+        "results": results.as_ref().map(|results| {
+            results
+                .iter()
+                .map(|(option, votes)| serde_json::json!({ "option": option, "votes": votes }))
+                .collect::<Vec<_>>()
+        }),
+        "total_votes": results.as_ref().map(|_| quiz.total_votes()),
+    });
+    http_response(200, "application/json", json.to_string().into_bytes())
+}
+
+fn http_response(status_code: u16, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), content_type.to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+        ],
+        body,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// The variant and its typed fields are for code to branch on; `msg` is for
+// people and its wording may change.
+#[derive(candid::CandidType, Deserialize, Serialize, Debug)]
+enum Error {
+    NotFound {
+        resource: Resource,
+        msg: String,
+    },
+    InvalidInput {
+        msg: String,
+    },
+    Locked {
+        msg: String,
+    },
+    QuizClosed {
+        quiz_id: u64,
+        reason: ClosedReason,
+        msg: String,
+    },
+    Unauthorized {
+        msg: String,
+    },
+    Conflict {
+        msg: String,
+    },
+    // a batch was larger than MAX_BATCH_SIZE
+    CapacityExceeded {
+        msg: String,
+    },
+    // the quiz has taken its `max_total_votes`
+    Full {
+        msg: String,
+    },
+    AlreadyAnswered {
+        quiz_id: u64,
+        msg: String,
+    },
+    // `field` names the part of the input that was rejected
+    ValidationFailed {
+        field: String,
+        msg: String,
+    },
+    AnonymousNotAllowed {
+        msg: String,
+    },
+    AccessDenied {
+        msg: String,
+    },
+    RateLimited {
+        retry_after_ns: u64,
+    },
+    // `used` has reached `limit`; both count whatever `msg` names
+    StorageFull {
+        used: u64,
+        limit: u64,
+        msg: String,
+    },
+    // the caller's `start_attempt` window on a timed quiz closed at `deadline`
+    TimeExpired {
+        quiz_id: u64,
+        deadline: u64,
+        msg: String,
+    },
+    // another canister, e.g. a ledger or shard, rejected or didn't answer
+    CallFailed {
+        canister: Principal,
+        msg: String,
+    },
+}
+
+// what an `Error::NotFound` was looking for
+#[derive(candid::CandidType, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+enum Resource {
+    Quiz,
+    Question,
+    Option,
+    Vote,
+    QuizSet,
+    Template,
+    Snapshot,
+    Comment,
+    // a started attempt on a timed quiz
+    Attempt,
+    Translation,
+}
+
+#[derive(candid::CandidType, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+enum ClosedReason {
+    Closed,
+    // still a draft
+    NotStarted,
+    Archived,
+    Expired,
+}
+
+ic_cdk::export_candid!();
+
+// Each test runs on its own thread, so it starts from empty stable memory.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // stands in for the system API; see the imports at the top
+    pub(crate) mod system {
+        use candid::utils::ArgumentEncoder;
+        use candid::Principal;
+        use ic_cdk::api::call::RejectionCode;
+        use ic_cdk_timers::TimerId;
+        use std::cell::{Cell, RefCell};
+        use std::time::Duration;
+
+        thread_local! {
+            static CALLER: Cell<Principal> = Cell::new(super::owner());
+            static NOW: Cell<u64> = const { Cell::new(1_700_000_000_000_000_000) };
+            static NOTIFIED: RefCell<Vec<(Principal, String)>> = const { RefCell::new(Vec::new()) };
+        }
+
+        pub(crate) fn caller() -> Principal {
+            CALLER.with(Cell::get)
+        }
+
+        pub(crate) fn time() -> u64 {
+            NOW.with(Cell::get)
+        }
+
+        pub(crate) fn is_controller(principal: &Principal) -> bool {
+            *principal == super::controller()
+        }
+
+        pub(crate) fn set_certified_data(_data: &[u8]) {}
+
+        pub(crate) fn data_certificate() -> Option<Vec<u8>> {
+            None
+        }
+
+        // only remembers who would have been notified, see `notified`
+        pub(crate) fn notify<T: ArgumentEncoder>(
+            id: Principal,
+            method: &str,
+            _args: T,
+        ) -> Result<(), RejectionCode> {
+            NOTIFIED.with(|notified| notified.borrow_mut().push((id, method.to_string())));
+            Ok(())
+        }
+
+        pub(crate) fn notified() -> Vec<(Principal, String)> {
+            NOTIFIED.with(|notified| notified.borrow().clone())
+        }
+
+        // timers never fire in tests
+        pub(crate) fn set_timer(_delay: Duration, _func: impl FnOnce() + 'static) -> TimerId {
+            TimerId::default()
+        }
+
+        pub(crate) fn set_caller(principal: Principal) {
+            CALLER.with(|caller| caller.set(principal));
+        }
+
+        pub(crate) fn advance(ns: u64) {
+            NOW.with(|now| now.set(now.get() + ns));
+        }
+    }
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[7, n])
+    }
+
+    fn owner() -> Principal {
+        user(0)
+    }
+
+    fn controller() -> Principal {
+        Principal::from_slice(&[9, 9])
+    }
+
+    fn as_caller<R>(principal: Principal, f: impl FnOnce() -> R) -> R {
+        let previous = caller();
+        system::set_caller(principal);
+        let result = f();
+        system::set_caller(previous);
+        result
+    }
+
+    fn payload(options: &[&str]) -> QuizPayload {
+        QuizPayload {
+            question: "Which one?".to_string(),
+            options: options.iter().map(|option| option.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn create(payload: QuizPayload) -> Quiz {
+        create_quiz_as(owner(), payload).unwrap()
+    }
+
+    fn poll(options: &[&str]) -> Quiz {
+        create(payload(options))
+    }
+
+    fn graded(options: &[&str], correct: u32) -> Quiz {
+        create(QuizPayload {
+            kind: Some(QuizKind::Graded),
+            correct_options: Some(vec![correct]),
+            ..payload(options)
+        })
+    }
+
+    fn vote(voter: Principal, id: u64, option: &str) -> Result<Quiz, Error> {
+        as_caller(voter, || {
+            cast_vote(id, 0, vec![option.to_string()], 1, voter)
+        })
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn answers_ordered_follows_options_not_map_order() {
+        let quiz = poll(&["Zebra", "apple", "Mango"]);
+        vote(user(1), quiz.id, "Mango").unwrap();
+        vote(user(2), quiz.id, "Zebra").unwrap();
+        vote(user(3), quiz.id, "Mango").unwrap();
+
+        let ordered = get_answers_ordered(quiz.id).unwrap();
+        assert_eq!(
+            ordered,
+            vec![
+                ("Zebra".to_string(), 1),
+                ("apple".to_string(), 0),
+                ("Mango".to_string(), 2)
+            ]
+        );
+        // the BTreeMap itself iterates "Mango", "Zebra", "apple"
+        let answers = get_quiz(quiz.id).unwrap().answers;
+        let map_order: Vec<&String> = answers.keys().collect();
+        assert_ne!(
+            map_order,
+            ordered.iter().map(|(option, _)| option).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn answers_ordered_rejects_unknown_quiz() {
+        assert!(matches!(
+            get_answers_ordered(42),
+            Err(Error::NotFound {
+                resource: Resource::Quiz,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn resolve_in_prefers_exact_then_normalized() {
+        let options = strings(&["Yes", "yes ", "No"]);
+        assert_eq!(resolve_in(&options, "yes "), Some("yes ".to_string()));
+        assert_eq!(resolve_in(&options, " YES"), Some("Yes".to_string()));
+        assert_eq!(resolve_in(&options, "Maybe"), None);
+    }
+
+    #[test]
+    fn normalization_follows_the_configured_mode() {
+        assert_eq!(normalize_option("  \u{c9}T\u{c9} "), "\u{e9}t\u{e9}");
+        write_config(|config| config.normalization_mode = Some(NormalizationMode::Ascii));
+        assert_eq!(normalize_option("  \u{c9}T\u{c9} "), "\u{c9}t\u{c9}");
+        assert_eq!(
+            normalize_tags(strings(&[" Rust", "rust", "ICP "])),
+            strings(&["rust", "icp"])
+        );
+    }
+
+    #[test]
+    fn migrate_brings_unversioned_records_up_to_date() {
+        let mut quiz = Quiz::new(1, "Q?".to_string(), strings(&["A", "B"]));
+        quiz.schema_version = None;
+        quiz.option_details = None;
+        quiz.next_option_id = None;
+
+        let migrated = migrate(quiz);
+        assert_eq!(migrated.schema_version, Some(SCHEMA_VERSION));
+        let ids: Vec<(u32, String)> = migrated
+            .option_details
+            .unwrap()
+            .into_iter()
+            .map(|detail| (detail.id, detail.label))
+            .collect();
+        assert_eq!(ids, vec![(0, "A".to_string()), (1, "B".to_string())]);
+        assert_eq!(migrated.next_option_id, Some(2));
+    }
+
+    #[test]
+    fn redacted_hides_answer_key_and_invite_code_from_voters() {
+        let mut quiz = graded(&["A", "B"], 1);
+        quiz.invite_code = Some("secret".to_string());
+        do_insert(&quiz);
+
+        let seen = as_caller(user(1), || get_quiz(quiz.id).unwrap().redacted());
+        assert!(seen.correct_options.is_none());
+        assert!(seen.invite_code.is_none());
+
+        let seen = get_quiz(quiz.id).unwrap().redacted();
+        assert_eq!(seen.correct_options, Some(strings(&["B"])));
+        assert_eq!(seen.invite_code, Some("secret".to_string()));
+
+        let mut closed = get_quiz(quiz.id).unwrap();
+        closed.status = Some(QuizStatus::Closed);
+        do_insert(&closed);
+        let seen = as_caller(user(1), || get_quiz(quiz.id).unwrap().redacted());
+        assert_eq!(seen.correct_options, Some(strings(&["B"])));
+        assert!(seen.invite_code.is_none());
+    }
+
+    #[test]
+    fn instant_runoff_transfers_eliminated_ballots() {
+        let quiz = create(QuizPayload {
+            kind: Some(QuizKind::Ranked),
+            ..payload(&["A", "B", "C"])
+        });
+        let ballots = [
+            (1, ["A", "B", "C"]),
+            (2, ["A", "C", "B"]),
+            (3, ["B", "C", "A"]),
+            (4, ["B", "A", "C"]),
+            (5, ["C", "B", "A"]),
+        ];
+        for (voter, ranking) in ballots {
+            as_caller(user(voter), || {
+                answer_quiz_ranked(quiz.id, strings(&ranking)).unwrap()
+            });
+        }
+
+        let result = get_ranked_result(quiz.id).unwrap();
+        // C goes out first and its ballot moves to B
+        assert_eq!(result.winner, Some("B".to_string()));
+        assert_eq!(result.rounds.len(), 2);
+        assert_eq!(result.rounds[0].eliminated, strings(&["C"]));
+    }
+
+    #[test]
+    fn weighted_vote_counts_its_weight_up_to_the_cap() {
+        let quiz = poll(&["A", "B"]);
+        set_max_weight(quiz.id, Some(5)).unwrap();
+
+        let quiz = as_caller(user(1), || vote_with_weight(quiz.id, "A".to_string(), 5)).unwrap();
+        assert_eq!(quiz.answers.get("A"), Some(&5));
+        assert!(matches!(
+            as_caller(user(2), || vote_with_weight(quiz.id, "B".to_string(), 6)),
+            Err(Error::InvalidInput { .. })
+        ));
+
+        // removing the cap re-allows large weights
+        set_max_weight(quiz.id, None).unwrap();
+        let quiz = as_caller(user(2), || vote_with_weight(quiz.id, "B".to_string(), 100)).unwrap();
+        assert_eq!(quiz.answers.get("B"), Some(&100));
+    }
+
+    #[test]
+    fn weighted_vote_rejects_zero_and_starts_capped_at_one() {
+        let quiz = poll(&["A", "B"]);
+        assert!(matches!(
+            as_caller(user(1), || vote_with_weight(quiz.id, "A".to_string(), 0)),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            as_caller(user(1), || vote_with_weight(quiz.id, "A".to_string(), 2)),
+            Err(Error::InvalidInput { .. })
+        ));
+        let quiz = as_caller(user(1), || vote_with_weight(quiz.id, "A".to_string(), 1)).unwrap();
+        assert_eq!(quiz.answers.get("A"), Some(&1));
+    }
+
+    #[test]
+    fn nonce_ballot_is_single_use() {
+        let quiz = create(QuizPayload {
+            allow_anonymous: Some(true),
+            ..payload(&["A", "B"])
+        });
+        let ballot = |voter, nonce: &str| {
+            as_caller(voter, || {
+                answer_quiz_nonce(quiz.id, "A".to_string(), nonce.to_string())
+            })
+        };
+
+        assert_eq!(ballot(user(1), "n-1").unwrap().answers.get("A"), Some(&1));
+        assert!(matches!(
+            ballot(user(2), "n-1"),
+            Err(Error::Conflict { .. })
+        ));
+        assert_eq!(ballot(user(2), "n-2").unwrap().answers.get("A"), Some(&2));
+    }
+
+    #[test]
+    fn nonce_ballot_allows_one_per_caller() {
+        let quiz = create(QuizPayload {
+            allow_anonymous: Some(true),
+            ..payload(&["A", "B"])
+        });
+        let ballot = |voter, nonce: &str| {
+            as_caller(voter, || {
+                answer_quiz_nonce(quiz.id, "B".to_string(), nonce.to_string())
+            })
+        };
+
+        ballot(user(1), "n-1").unwrap();
+        assert!(matches!(
+            ballot(user(1), "n-2"),
+            Err(Error::Conflict { .. })
+        ));
+        assert!(matches!(
+            ballot(Principal::anonymous(), "n-3"),
+            Err(Error::AnonymousNotAllowed { .. })
+        ));
+        assert_eq!(get_quiz(quiz.id).unwrap().answers.get("B"), Some(&1));
+    }
+
+    #[test]
+    fn trending_ranks_a_recent_surge_above_older_votes() {
+        let older = poll(&["A", "B"]);
+        for voter in 1..=5 {
+            vote(user(voter), older.id, "A").unwrap();
+        }
+        system::advance(2 * TRENDING_WINDOW_HOURS * NANOS_PER_HOUR);
+        decay_activity();
+        vote(user(6), older.id, "B").unwrap();
+
+        let surging = poll(&["A", "B"]);
+        for voter in 1..=3 {
+            vote(user(voter), surging.id, "B").unwrap();
+        }
+
+        let ids: Vec<u64> = get_trending_quizzes(10)
+            .into_iter()
+            .map(|quiz| quiz.id)
+            .collect();
+        assert_eq!(ids, vec![surging.id, older.id]);
+        // ties inside the window fall back to total votes
+        vote(user(7), older.id, "B").unwrap();
+        vote(user(8), older.id, "B").unwrap();
+        assert_eq!(get_trending_quizzes(1)[0].id, older.id);
+    }
+
+    #[test]
+    fn answer_many_reports_each_pair_in_place() {
+        let first = poll(&["A", "B"]);
+        let second = create(QuizPayload {
+            allow_vote_change: Some(false),
+            ..payload(&["Yes", "No"])
+        });
+
+        let results = as_caller(user(1), || {
+            answer_many(vec![
+                (first.id, 0, "A".to_string()),
+                (99, 0, "A".to_string()),
+                (second.id, 0, "Perhaps".to_string()),
+                (second.id, 0, "Yes".to_string()),
+                (second.id, 0, "No".to_string()),
+            ])
+        });
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].as_ref().unwrap().answers.get("A"), Some(&1));
+        assert!(matches!(
+            results[1],
+            Err(Error::NotFound {
+                resource: Resource::Quiz,
+                ..
+            })
+        ));
+        assert!(matches!(
+            results[2],
+            Err(Error::NotFound {
+                resource: Resource::Option,
+                ..
+            })
+        ));
+        assert!(results[3].is_ok());
+        // a quiz that won't take a changed vote still refuses one in a batch
+        assert!(matches!(results[4], Err(Error::AlreadyAnswered { .. })));
+        assert_eq!(get_quiz(second.id).unwrap().answers.get("Yes"), Some(&1));
+    }
+
+    #[test]
+    fn set_participation_tracks_partial_and_full_completion() {
+        let quizzes: Vec<Quiz> = (0..3).map(|_| poll(&["A", "B"])).collect();
+        let set = create_quiz_set(QuizSetPayload {
+            name: "Week 1".to_string(),
+            quiz_ids: quizzes.iter().map(|quiz| quiz.id).collect(),
+        })
+        .unwrap();
+
+        vote(user(1), quizzes[1].id, "A").unwrap();
+        let report = get_set_participation(set.id, user(1)).unwrap();
+        assert_eq!((report.answered, report.total), (1, 3));
+        assert_eq!(report.unanswered, vec![quizzes[0].id, quizzes[2].id]);
+
+        vote(user(1), quizzes[0].id, "B").unwrap();
+        vote(user(1), quizzes[2].id, "B").unwrap();
+        let report = get_set_participation(set.id, user(1)).unwrap();
+        assert_eq!((report.answered, report.total), (3, 3));
+        assert!(report.unanswered.is_empty());
+
+        assert!(matches!(
+            get_set_participation(set.id + 1, user(1)),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn close_and_snapshot_freezes_live_counts_once() {
+        let quiz = poll(&["A", "B", "C"]);
+        vote(user(1), quiz.id, "B").unwrap();
+        vote(user(2), quiz.id, "B").unwrap();
+        vote(user(3), quiz.id, "C").unwrap();
+
+        let snapshot = close_and_snapshot(quiz.id).unwrap();
+        assert_eq!(snapshot.quiz_id, quiz.id);
+        assert_eq!(snapshot.total_votes, 3);
+        assert_eq!(
+            snapshot.options_with_counts,
+            get_answers_ordered(quiz.id).unwrap()
+        );
+        assert!(get_quiz(quiz.id).unwrap().status() == QuizStatus::Closed);
+        assert!(matches!(
+            vote(user(4), quiz.id, "A"),
+            Err(Error::QuizClosed { .. })
+        ));
+
+        // idempotent: the second call hands back the first snapshot
+        system::advance(NANOS_PER_HOUR);
+        let again = close_and_snapshot(quiz.id).unwrap();
+        assert_eq!(again.closed_at, snapshot.closed_at);
+        assert_eq!(again.options_with_counts, snapshot.options_with_counts);
+    }
+
+    #[test]
+    fn user_stats_count_distinct_quizzes() {
+        let first = poll(&["A", "B"]);
+        let second = poll(&["A", "B"]);
+
+        vote(user(1), first.id, "A").unwrap();
+        vote(user(1), first.id, "B").unwrap();
+        assert_eq!(get_user_stats(user(1)).quizzes_answered, 1);
+
+        system::advance(NANOS_PER_SEC);
+        vote(user(1), second.id, "A").unwrap();
+        let stats = get_user_stats(user(1));
+        assert_eq!(stats.quizzes_answered, 2);
+        assert_eq!(stats.last_answered_at, Some(time()));
+        assert_eq!(get_user_stats(user(2)).quizzes_answered, 0);
+    }
+
+    #[test]
+    fn quiz_cap_stops_creation_until_raised() {
+        as_caller(controller(), || set_max_quizzes(2)).unwrap();
+        assert!(matches!(
+            as_caller(user(1), || set_max_quizzes(0)),
+            Err(Error::Unauthorized { .. })
+        ));
+
+        poll(&["A", "B"]);
+        let results = create_quizzes(vec![payload(&["A", "B"]), payload(&["C", "D"])]);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(Error::StorageFull {
+                used: 2,
+                limit: 2,
+                ..
+            })
+        ));
+
+        as_caller(controller(), || set_max_quizzes(3)).unwrap();
+        assert!(create_quiz_as(owner(), payload(&["C", "D"])).is_ok());
+        assert!(create_quiz_as(owner(), payload(&["E", "F"])).is_err());
+
+        // 0 lifts the cap
+        as_caller(controller(), || set_max_quizzes(0)).unwrap();
+        assert!(create_quiz_as(owner(), payload(&["E", "F"])).is_ok());
+    }
+
+    #[test]
+    fn corrupt_record_is_counted_not_trapped_on() {
+        let healthy = poll(&["A", "B"]);
+        let garbage = vec![0xde, 0xad, 0xbe, 0xef];
+        assert!(matches!(
+            StoredQuiz::from_bytes(Cow::Borrowed(&garbage)),
+            StoredQuiz::Corrupt(_)
+        ));
+
+        STORAGE.with(|service| {
+            service
+                .borrow_mut()
+                .insert(healthy.id + 1, StoredQuiz::Corrupt(garbage))
+        });
+        assert_eq!(count_corrupt_entries(), 1);
+        assert_eq!(get_quiz(healthy.id).unwrap().question, healthy.question);
+    }
+
+    #[test]
+    fn rebuild_answer_counts_restores_the_vote_log_totals() {
+        let quiz = poll(&["A", "B"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "B").unwrap();
+        vote(user(3), quiz.id, "B").unwrap();
+
+        let mut drifted = get_quiz(quiz.id).unwrap();
+        drifted.answers.insert("A".to_string(), 40);
+        drifted.answers.insert("B".to_string(), 0);
+        do_insert(&drifted);
+
+        assert!(matches!(
+            rebuild_answer_counts(quiz.id),
+            Err(Error::Unauthorized { .. })
+        ));
+        let rebuilt = as_caller(controller(), || rebuild_answer_counts(quiz.id)).unwrap();
+        assert_eq!(rebuilt.answers.get("A"), Some(&1));
+        assert_eq!(rebuilt.answers.get("B"), Some(&2));
+        assert_eq!(get_quiz(quiz.id).unwrap().total_votes(), 3);
+    }
+
+    #[test]
+    fn candid_dump_round_trips_through_load() {
+        graded(&["A", "B"], 0);
+        let quiz = poll(&["Yes", "No", "Maybe"]);
+        vote(user(1), quiz.id, "Maybe").unwrap();
+        let before: Vec<Quiz> = with_quizzes(|quizzes| quizzes.collect());
+
+        assert!(matches!(
+            as_caller(user(1), dump_quizzes_candid),
+            Err(Error::Unauthorized { .. })
+        ));
+        let blob = as_caller(controller(), dump_quizzes_candid).unwrap();
+        let loaded = as_caller(controller(), || load_quizzes_candid(blob)).unwrap();
+        assert_eq!(loaded, 2);
+
+        // same content under fresh ids
+        let after: Vec<Quiz> = with_quizzes(|quizzes| quizzes.collect());
+        assert_eq!(after.len(), before.len());
+        for (before, after) in before.iter().zip(&after) {
+            assert!(after.id > quiz.id);
+            assert_eq!(after.question, before.question);
+            assert_eq!(after.options, before.options);
+            assert_eq!(after.answers, before.answers);
+            assert_eq!(after.correct_options, before.correct_options);
+        }
+    }
+
+    #[test]
+    fn can_vote_names_each_reason() {
+        let reason_for = |change: fn(&mut Quiz), voter: Principal| {
+            let mut quiz = poll(&["A", "B"]);
+            change(&mut quiz);
+            do_insert(&quiz);
+            can_vote(quiz.id, voter).unwrap().reason
+        };
+
+        let open = poll(&["A", "B"]);
+        let eligibility = can_vote(open.id, user(1)).unwrap();
+        assert!(eligibility.eligible && eligibility.reason.is_none());
+        assert!(matches!(can_vote(99, user(1)), Err(Error::NotFound { .. })));
+
+        as_caller(controller(), || ban_voter(user(9))).unwrap();
+        assert!(matches!(
+            reason_for(|_| {}, user(9)),
+            Some(IneligibleReason::Banned)
+        ));
+        assert!(matches!(
+            reason_for(|quiz| quiz.status = Some(QuizStatus::Closed), user(1)),
+            Some(IneligibleReason::Closed)
+        ));
+        assert!(matches!(
+            reason_for(|quiz| quiz.status = Some(QuizStatus::Draft), user(1)),
+            Some(IneligibleReason::NotStarted)
+        ));
+        assert!(matches!(
+            reason_for(|quiz| quiz.expires_at = Some(time()), user(1)),
+            Some(IneligibleReason::Expired)
+        ));
+        assert!(matches!(
+            reason_for(|quiz| quiz.allowed_voters = Some(vec![]), user(1)),
+            Some(IneligibleReason::NotAllowed)
+        ));
+        assert!(matches!(
+            reason_for(|_| {}, Principal::anonymous()),
+            Some(IneligibleReason::Anonymous)
+        ));
+
+        let once = create(QuizPayload {
+            allow_vote_change: Some(false),
+            max_total_votes: Some(1),
+            ..payload(&["A", "B"])
+        });
+        vote(user(1), once.id, "A").unwrap();
+        assert!(matches!(
+            can_vote(once.id, user(1)).unwrap().reason,
+            Some(IneligibleReason::AlreadyVoted)
+        ));
+        assert!(matches!(
+            can_vote(once.id, user(2)).unwrap().reason,
+            Some(IneligibleReason::Full)
+        ));
+    }
+
+    #[test]
+    fn branch_unlocks_only_after_the_matching_answer() {
+        let intro = poll(&["Cats", "Dogs"]);
+        let dogs_only = poll(&["Walks", "Fetch"]);
+        let closing = poll(&["Good", "Bad"]);
+        set_prerequisite(dogs_only.id, Some((intro.id, "Dogs".to_string()))).unwrap();
+        let set = create_quiz_set(QuizSetPayload {
+            name: "Pets".to_string(),
+            quiz_ids: vec![intro.id, dogs_only.id, closing.id],
+        })
+        .unwrap();
+        let next = |voter| {
+            as_caller(voter, || get_next_eligible_in_set(set.id, voter))
+                .unwrap()
+                .map(|quiz| quiz.id)
+        };
+
+        assert_eq!(next(user(1)), Some(intro.id));
+        vote(user(1), intro.id, "Cats").unwrap();
+        assert_eq!(next(user(1)), Some(closing.id));
+
+        vote(user(2), intro.id, "Dogs").unwrap();
+        assert_eq!(next(user(2)), Some(dogs_only.id));
+        vote(user(2), dogs_only.id, "Fetch").unwrap();
+        vote(user(2), closing.id, "Good").unwrap();
+        assert_eq!(next(user(2)), None);
+
+        assert!(matches!(
+            set_prerequisite(dogs_only.id, Some((intro.id, "Fish".to_string()))),
+            Err(Error::NotFound {
+                resource: Resource::Option,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn history_records_each_edit_and_keeps_the_newest() {
+        let quiz = poll(&["Yes", "No"]);
+        let base = get_quiz_history(quiz.id).unwrap().len();
+
+        update_quiz(quiz.id, payload(&["Yes", "Maybe"])).unwrap();
+        update_quiz(
+            quiz.id,
+            QuizPayload {
+                question: "Which one, really?".to_string(),
+                ..payload(&["Yes", "Maybe"])
+            },
+        )
+        .unwrap();
+        add_options(quiz.id, strings(&["Later"])).unwrap();
+        edit_option(
+            quiz.id,
+            0,
+            OptionPatch {
+                label: Some("Sure".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let history = get_quiz_history(quiz.id).unwrap();
+        let descriptions: Vec<&str> = history[base..]
+            .iter()
+            .map(|entry| entry.description.as_str())
+            .collect();
+        assert_eq!(
+            descriptions,
+            vec![
+                "options added: 'Maybe'; options removed: 'No'",
+                "question edited",
+                "options added: Later",
+                "option 0 edited",
+            ]
+        );
+        assert!(history.iter().all(|entry| entry.caller == owner()));
+
+        for i in 0..MAX_HISTORY_ENTRIES + 5 {
+            journal(quiz.id, format!("edit {}", i));
+        }
+        let history = get_quiz_history(quiz.id).unwrap();
+        assert_eq!(history.len() as u64, MAX_HISTORY_ENTRIES);
+        assert_eq!(history[0].description, "edit 5");
+        assert_eq!(
+            history.last().unwrap().description,
+            format!("edit {}", MAX_HISTORY_ENTRIES + 4)
+        );
+    }
+
+    #[test]
+    fn merge_voter_keeps_the_target_choice_and_moves_the_rest() {
+        let both = poll(&["A", "B"]);
+        let only_from = poll(&["A", "B"]);
+        let (from, into) = (user(1), user(2));
+        vote(from, both.id, "A").unwrap();
+        vote(into, both.id, "B").unwrap();
+        vote(from, only_from.id, "A").unwrap();
+
+        assert!(matches!(
+            merge_voter(from, into),
+            Err(Error::Unauthorized { .. })
+        ));
+        let touched = as_caller(controller(), || merge_voter(from, into)).unwrap();
+        assert_eq!(touched, 2);
+
+        let both = get_quiz(both.id).unwrap();
+        assert_eq!(both.answers.get("A"), Some(&0));
+        assert_eq!(both.answers.get("B"), Some(&1));
+        assert_eq!(votes_of(both.id, &into).len(), 1);
+        assert!(!has_voted(both.id, &from));
+
+        assert_eq!(get_quiz(only_from.id).unwrap().answers.get("A"), Some(&1));
+        assert!(has_voted(only_from.id, &into));
+        assert!(!has_voted(only_from.id, &from));
+        assert_eq!(get_user_stats(into).quizzes_answered, 2);
+        assert_eq!(get_user_stats(from).quizzes_answered, 0);
+    }
+
+    #[test]
+    fn large_quiz_round_trips_through_stable_memory() {
+        let options: Vec<String> = (0..MAX_OPTIONS)
+            .map(|i| {
+                format!(
+                    "{:02}{}",
+                    i,
+                    "x".repeat(DEFAULT_MAX_OPTION_LEN as usize - 2)
+                )
+            })
+            .collect();
+        let quiz = create(QuizPayload {
+            question: "q".repeat(DEFAULT_MAX_QUESTION_LEN as usize),
+            options: options.clone(),
+            ..Default::default()
+        });
+        for (i, option) in options.iter().enumerate() {
+            vote(user(i as u8), quiz.id, option).unwrap();
+        }
+
+        let stored = get_quiz(quiz.id).unwrap();
+        assert!(stored.to_bytes().len() > 2 * 1024);
+        assert_eq!(stored.question, quiz.question);
+        assert_eq!(stored.options, options);
+        assert!(stored.answers.values().all(|count| *count == 1));
+
+        let StoredQuiz::Quiz(decoded) = StoredQuiz::from_bytes(stored.to_bytes()) else {
+            panic!("a large quiz should decode");
+        };
+        assert_eq!(decoded.options, options);
+    }
+
+    #[test]
+    fn import_rejects_a_bad_record_and_writes_nothing() {
+        let quiz = poll(&["A", "B"]);
+        let export = |quiz: Quiz| QuizExport {
+            quiz,
+            votes: Vec::new(),
+        };
+        let mut duplicate_options =
+            Quiz::new(quiz.id + 1, "Fine?".to_string(), strings(&["A", "a"]));
+        duplicate_options.owner = Some(owner());
+        let mut long_question = duplicate_options.clone();
+        long_question.options = strings(&["A", "B"]);
+        long_question.question = "q".repeat(DEFAULT_MAX_QUESTION_LEN as usize + 1);
+        let mut too_many_tags = long_question.clone();
+        too_many_tags.question = "Fine?".to_string();
+        too_many_tags.tags = Some((0..=MAX_TAGS).map(|i| format!("tag{}", i)).collect());
+
+        let field_of = |record: Quiz| {
+            let replacement = Quiz::new(quiz.id, "Replaced?".to_string(), strings(&["C", "D"]));
+            match as_caller(controller(), || {
+                import_quizzes(vec![export(replacement), export(record)])
+            }) {
+                Err(Error::ValidationFailed { field, .. }) => field,
+                _ => panic!("expected a validation failure"),
+            }
+        };
+        assert_eq!(field_of(duplicate_options), "exports[1].options[1]");
+        assert_eq!(field_of(long_question), "exports[1].question");
+        assert_eq!(field_of(too_many_tags), "exports[1].tags");
+        assert_eq!(get_quiz(quiz.id).unwrap().question, quiz.question);
+    }
+
+    #[test]
+    fn import_drops_state_left_by_the_replaced_quiz() {
+        let quiz = create(QuizPayload {
+            tags: Some(strings(&["old"])),
+            allow_anonymous: Some(true),
+            ..payload(&["A", "B"])
+        });
+        vote(user(1), quiz.id, "A").unwrap();
+        let nonce_ballot = || {
+            as_caller(user(2), || {
+                answer_quiz_nonce(quiz.id, "C".to_string(), "n-1".to_string())
+            })
+        };
+        as_caller(user(2), || {
+            answer_quiz_nonce(quiz.id, "A".to_string(), "n-1".to_string())
+        })
+        .unwrap();
+        close_and_snapshot(quiz.id).unwrap();
+        let key = (quiz.id, StorablePrincipal(user(1)));
+        PAYOUTS.with(|payouts| {
+            payouts.borrow_mut().insert(
+                key,
+                Payout {
+                    amount: Nat::from(10u64),
+                    status: PayoutStatus::Pending,
+                    attempts: 0,
+                    created_at_time: time(),
+                },
+            )
+        });
+        BADGES.with(|badges| {
+            badges.borrow_mut().insert(
+                key,
+                Badge {
+                    collection: controller(),
+                    token_id: Nat::from(1u64),
+                    status: BadgeStatus::Minting,
+                    attempts: 1,
+                },
+            )
+        });
+        assert!(latest_final_result(quiz.id).is_some());
+        assert!(latest_snapshot(quiz.id).is_some());
+        assert!(!get_quiz_history(quiz.id).unwrap().is_empty());
+
+        let mut replacement = Quiz::new(quiz.id, "Replaced?".to_string(), strings(&["C", "D"]));
+        replacement.owner = Some(owner());
+        replacement.tags = Some(strings(&["new"]));
+        replacement.allow_anonymous = Some(true);
+        let imported = as_caller(controller(), || {
+            import_quizzes(vec![QuizExport {
+                quiz: replacement,
+                votes: Vec::new(),
+            }])
+        });
+        assert_eq!(imported.unwrap(), 1);
+
+        assert!(latest_final_result(quiz.id).is_none());
+        assert!(latest_snapshot(quiz.id).is_none());
+        assert!(PAYOUTS.with(|payouts| payouts.borrow().get(&key).is_none()));
+        assert!(BADGES.with(|badges| badges.borrow().get(&key).is_none()));
+        assert!(get_quiz_history(quiz.id).unwrap().is_empty());
+        assert!(!has_voted(quiz.id, &user(1)));
+        assert!(get_trending_quizzes(10).is_empty());
+        assert_eq!(list_quizzes_by_tag("old".to_string(), 0, 10).total, 0);
+        assert_eq!(list_quizzes_by_tag("new".to_string(), 0, 10).total, 1);
+        // the earlier anonymous voter and nonce aren't held against the new quiz
+        assert_eq!(nonce_ballot().unwrap().answers.get("C"), Some(&1));
+    }
+
+    #[test]
+    fn normalize_options_merges_counts_once_per_voter() {
+        let mut quiz = Quiz::new(
+            next_quiz_id(),
+            "Ready?".to_string(),
+            strings(&["Yes ", "Yes", "No"]),
+        );
+        quiz.owner = Some(owner());
+        quiz.multi_select = Some(true);
+        do_insert(&quiz);
+        vote(user(1), quiz.id, "Yes ").unwrap();
+        vote(user(2), quiz.id, "Yes").unwrap();
+        vote(user(3), quiz.id, "No").unwrap();
+        let both = strings(&["Yes ", "Yes"]);
+        as_caller(user(4), || cast_vote(quiz.id, 0, both, 1, user(4))).unwrap();
+
+        let merged = normalize_options(quiz.id).unwrap();
+        assert_eq!(merged.options, strings(&["Yes", "No"]));
+        assert_eq!(merged.answers.get("Yes"), Some(&3));
+        assert_eq!(merged.answers.get("No"), Some(&1));
+        assert_eq!(merged.answers.len(), 2);
+        assert_eq!(votes_of(quiz.id, &user(4)).len(), 1);
+        assert!(votes_of(quiz.id, &user(1))
+            .iter()
+            .all(|(_, vote)| vote.option == "Yes"));
+
+        // the cache and the log agree afterwards
+        let rebuilt = as_caller(controller(), || rebuild_answer_counts(quiz.id)).unwrap();
+        assert_eq!(rebuilt.answers, merged.answers);
+    }
+
+    #[test]
+    fn normalize_options_keeps_at_least_two_options() {
+        let mut quiz = Quiz::new(
+            next_quiz_id(),
+            "Ready?".to_string(),
+            strings(&["Yes", " yes", "YES "]),
+        );
+        quiz.owner = Some(owner());
+        do_insert(&quiz);
+
+        assert!(matches!(
+            normalize_options(quiz.id),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert_eq!(get_quiz(quiz.id).unwrap().options.len(), 3);
+    }
+
+    #[test]
+    fn clone_carries_the_answer_key_only_for_managers() {
+        let quiz = graded(&["A", "B"], 1);
+
+        let theirs = as_caller(user(1), || clone_quiz(quiz.id, None)).unwrap();
+        assert_eq!(theirs.owner, Some(user(1)));
+        assert!(theirs.correct_options.is_none());
+        let stored = as_caller(user(1), || get_quiz(theirs.id).unwrap().redacted());
+        assert!(stored.correct_options.is_none());
+
+        let mine = clone_quiz(quiz.id, None).unwrap();
+        assert_eq!(mine.correct_options, Some(strings(&["B"])));
+    }
+
+    #[test]
+    fn clone_into_set_adds_an_independent_copy() {
+        let source = poll(&["A", "B"]);
+        vote(user(1), source.id, "A").unwrap();
+        let set = create_quiz_set(QuizSetPayload {
+            name: "Copies".to_string(),
+            quiz_ids: vec![source.id],
+        })
+        .unwrap();
+
+        let clone = as_caller(user(2), || clone_into_set(source.id, set.id)).unwrap();
+        assert_eq!(
+            get_quiz_set(set.id).unwrap().quiz_ids,
+            vec![source.id, clone.id]
+        );
+        assert_eq!(clone.total_votes(), 0);
+
+        vote(user(3), clone.id, "B").unwrap();
+        vote(user(4), source.id, "A").unwrap();
+        assert_eq!(get_quiz(source.id).unwrap().answers.get("A"), Some(&2));
+        assert_eq!(get_quiz(source.id).unwrap().answers.get("B"), Some(&0));
+        assert_eq!(get_quiz(clone.id).unwrap().answers.get("A"), Some(&0));
+        assert_eq!(get_quiz(clone.id).unwrap().answers.get("B"), Some(&1));
+    }
+
+    #[test]
+    fn clone_into_set_needs_access_and_drops_the_answer_key() {
+        let hidden = create(QuizPayload {
+            visibility: Some(QuizVisibility::Private),
+            ..payload(&["A", "B"])
+        });
+        let key = graded(&["A", "B"], 0);
+        let set = create_quiz_set(QuizSetPayload {
+            name: "Copies".to_string(),
+            quiz_ids: Vec::new(),
+        })
+        .unwrap();
+
+        assert!(matches!(
+            as_caller(user(1), || clone_into_set(hidden.id, set.id)),
+            Err(Error::AccessDenied { .. })
+        ));
+        let clone = as_caller(user(1), || clone_into_set(key.id, set.id)).unwrap();
+        assert!(clone.correct_options.is_none());
+    }
+
+    #[test]
+    fn voter_activity_and_history_stay_private() {
+        let quiz = create(QuizPayload {
+            visibility: Some(QuizVisibility::Private),
+            ..payload(&["A", "B"])
+        });
+        vote(owner(), quiz.id, "A").unwrap();
+        take_snapshot(quiz.id).unwrap();
+
+        as_caller(user(1), || {
+            assert!(matches!(
+                get_last_voter(quiz.id),
+                Err(Error::AccessDenied { .. })
+            ));
+            assert!(get_voter_cohorts(quiz.id).is_err());
+            assert!(get_time_to_first_vote(quiz.id).is_err());
+            assert!(get_peak_voting_window(quiz.id, NANOS_PER_HOUR).is_err());
+            assert!(get_quiz_history(quiz.id).is_err());
+            assert!(get_snapshot(quiz.id).is_err());
+            assert!(get_decisiveness_trend(quiz.id).is_err());
+        });
+        assert_eq!(get_last_voter(quiz.id).unwrap(), Some(owner()));
+        assert!(get_snapshot(quiz.id).is_ok());
+    }
+
+    #[test]
+    fn snapshots_wait_for_results_visibility() {
+        let quiz = create(QuizPayload {
+            results_visibility: Some(ResultsVisibility::AfterClose),
+            ..payload(&["A", "B"])
+        });
+        vote(user(1), quiz.id, "A").unwrap();
+        take_snapshot(quiz.id).unwrap();
+        vote(user(2), quiz.id, "A").unwrap();
+        take_snapshot(quiz.id).unwrap();
+
+        as_caller(user(1), || {
+            assert!(matches!(
+                get_snapshot(quiz.id),
+                Err(Error::Unauthorized { .. })
+            ));
+            assert!(diff_snapshots(quiz.id, 0, 1).is_err());
+            assert!(get_decisiveness_trend(quiz.id).is_err());
+        });
+        assert!(diff_snapshots(quiz.id, 0, 1).is_ok());
+
+        close_and_snapshot(quiz.id).unwrap();
+        as_caller(user(1), || {
+            assert!(get_snapshot(quiz.id).is_ok());
+            assert!(diff_snapshots(quiz.id, 0, 1).is_ok());
+            assert_eq!(get_decisiveness_trend(quiz.id).unwrap().len(), 3);
+        });
+    }
+
+    #[test]
+    fn migrate_folds_the_old_answer_key_into_correct_options() {
+        let mut quiz = Quiz::new(1, "Q?".to_string(), strings(&["A", "B"]));
+        quiz.schema_version = Some(2);
+        quiz.correct_option = Some("B".to_string());
+
+        let migrated = migrate(quiz);
+        assert!(migrated.correct_option.is_none());
+        assert_eq!(migrated.correct_options, Some(strings(&["B"])));
+    }
+
+    #[test]
+    fn one_answer_key_for_polls_and_graded_quizzes() {
+        let poll_quiz = poll(&["A", "B"]);
+        set_correct_option(poll_quiz.id, "A".to_string()).unwrap();
+        let seen = as_caller(user(1), || get_quiz(poll_quiz.id).unwrap().redacted());
+        assert!(seen.correct_options.is_none());
+
+        let hard = graded(&["A", "B"], 0);
+        vote(user(1), hard.id, "B").unwrap();
+        vote(user(2), hard.id, "B").unwrap();
+        vote(user(3), hard.id, "A").unwrap();
+        let low: Vec<u64> = get_low_accuracy_quizzes(0.5, 1)
+            .unwrap()
+            .into_iter()
+            .map(|quiz| quiz.id)
+            .collect();
+        assert_eq!(low, vec![hard.id]);
+    }
+
+    #[test]
+    fn candid_load_checks_records_and_the_cap_before_replacing_anything() {
+        let kept = poll(&["A", "B"]);
+        vote(user(1), kept.id, "A").unwrap();
+        let kept = get_quiz(kept.id).unwrap();
+        let mut broken = kept.clone();
+        broken.options = strings(&["A"]);
+        let blob = Encode!(&vec![kept.clone(), broken]).unwrap();
+        assert!(matches!(
+            as_caller(controller(), || load_quizzes_candid(blob)),
+            Err(Error::ValidationFailed { field, .. }) if field.starts_with("quizzes[1].")
+        ));
+
+        as_caller(controller(), || set_max_quizzes(1)).unwrap();
+        let blob = Encode!(&vec![kept.clone(), kept.clone()]).unwrap();
+        assert!(matches!(
+            as_caller(controller(), || load_quizzes_candid(blob)),
+            Err(Error::StorageFull {
+                used: 2,
+                limit: 1,
+                ..
+            })
+        ));
+        assert_eq!(get_quiz(kept.id).unwrap().total_votes(), 1);
+
+        let blob = Encode!(&vec![kept.clone()]).unwrap();
+        assert!(matches!(
+            as_caller(controller(), || load_quizzes_candid(blob)),
+            Ok(1)
+        ));
+        assert!(get_quiz(kept.id).is_err());
+        assert_eq!(get_quiz(kept.id + 1).unwrap().total_votes(), 1);
+    }
+
+    #[test]
+    fn purging_a_quiz_drops_its_votes_and_snapshots() {
+        let quiz = poll(&["A", "B"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        close_and_snapshot(quiz.id).unwrap();
+        assert!(matches!(
+            as_caller(user(1), || purge_quiz(quiz.id)),
+            Err(Error::Unauthorized { .. })
+        ));
+
+        as_caller(controller(), || purge_quiz(quiz.id)).unwrap();
+        assert!(get_quiz(quiz.id).is_err());
+        let left = VOTE_LOG.with(|log| {
+            log.borrow()
+                .range((quiz.id, 0)..=(quiz.id, u64::MAX))
+                .count()
+        });
+        assert_eq!(left, 0);
+        let snapshots = SNAPSHOTS.with(|snapshots| {
+            snapshots
+                .borrow()
+                .range((quiz.id, 0)..=(quiz.id, u64::MAX))
+                .count()
+        });
+        assert_eq!(snapshots, 0);
+    }
+
+    #[test]
+    fn ranked_feed_scores_hidden_tallies_as_no_votes() {
+        let hidden = create(QuizPayload {
+            results_visibility: Some(ResultsVisibility::AfterClose),
+            ..payload(&["A", "B"])
+        });
+        let shown = poll(&["C", "D"]);
+        for voter in 1..=3 {
+            vote(user(voter), hidden.id, "A").unwrap();
+        }
+        vote(user(1), shown.id, "C").unwrap();
+
+        let ids = |viewer| {
+            as_caller(viewer, || get_ranked_feed(u64::MAX))
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(user(5)), vec![shown.id, hidden.id]);
+        assert_eq!(ids(owner()), vec![hidden.id, shown.id]);
+    }
+
+    #[test]
+    fn controversial_in_tag_leaves_out_hidden_tallies() {
+        let tagged = |visibility| {
+            create(QuizPayload {
+                tags: Some(strings(&["rust"])),
+                results_visibility: Some(visibility),
+                ..payload(&["A", "B"])
+            })
+        };
+        let hidden = tagged(ResultsVisibility::AfterClose);
+        let shown = tagged(ResultsVisibility::Always);
+        for quiz in [&hidden, &shown] {
+            vote(user(1), quiz.id, "A").unwrap();
+            vote(user(2), quiz.id, "B").unwrap();
+        }
+
+        let ids = |viewer| {
+            as_caller(viewer, || get_controversial_in_tag("rust".to_string(), 10))
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(user(5)), vec![shown.id]);
+        assert_eq!(ids(owner()), vec![hidden.id, shown.id]);
+    }
+
+    #[test]
+    fn similar_quizzes_rank_by_shared_options() {
+        let target = poll(&["Yes", "No", "Maybe"]);
+        let partial = poll(&["yes", "Never"]);
+        let unrelated = poll(&["Red", "Blue"]);
+        let same = poll(&["Maybe", "No", "Yes"]);
+
+        let ids: Vec<u64> = get_similar_quizzes(target.id, u64::MAX)
+            .unwrap()
+            .iter()
+            .map(|quiz| quiz.id)
+            .collect();
+        assert_eq!(ids, vec![same.id, partial.id]);
+        assert!(!ids.contains(&unrelated.id));
+    }
+
+    #[test]
+    fn purge_orphans_removes_only_references_to_missing_quizzes() {
+        let gone = poll(&["A", "B"]);
+        let kept = poll(&["C", "D"]);
+        vote(user(1), gone.id, "A").unwrap();
+        vote(user(1), kept.id, "C").unwrap();
+        let set = create_quiz_set(QuizSetPayload {
+            name: "Mixed".to_string(),
+            quiz_ids: vec![gone.id, kept.id],
+        })
+        .unwrap();
+        // left behind the way a removal that predates the cleanup would
+        STORAGE.with(|service| service.borrow_mut().remove(&gone.id));
+
+        assert!(matches!(
+            as_caller(user(1), purge_orphans),
+            Err(Error::Unauthorized { .. })
+        ));
+        let summary = as_caller(controller(), purge_orphans).unwrap();
+        assert_eq!(summary.set_refs_removed, 1);
+        assert_eq!(summary.vote_records_removed, 1);
+        assert_eq!(get_quiz_set(set.id).unwrap().quiz_ids, vec![kept.id]);
+        assert_eq!(get_quiz(kept.id).unwrap().total_votes(), 1);
+        let kept_votes = VOTE_LOG.with(|log| {
+            log.borrow()
+                .range((kept.id, 0)..=(kept.id, u64::MAX))
+                .count()
+        });
+        assert_eq!(kept_votes, 1);
+
+        let again = as_caller(controller(), purge_orphans).unwrap();
+        assert_eq!(again.set_refs_removed + again.vote_records_removed, 0);
+    }
+
+    #[test]
+    fn integrity_check_reports_orphaned_votes_and_set_references() {
+        let gone = poll(&["A", "B"]);
+        let kept = poll(&["C", "D"]);
+        vote(user(1), gone.id, "B").unwrap();
+        vote(user(1), kept.id, "C").unwrap();
+        let set = create_quiz_set(QuizSetPayload {
+            name: "Mixed".to_string(),
+            quiz_ids: vec![kept.id, gone.id],
+        })
+        .unwrap();
+
+        let clean = as_caller(controller(), check_integrity).unwrap();
+        assert!(clean.orphaned_set_refs.is_empty() && clean.orphaned_vote_keys.is_empty());
+
+        STORAGE.with(|service| service.borrow_mut().remove(&gone.id));
+        let report = as_caller(controller(), check_integrity).unwrap();
+        assert_eq!(report.orphaned_set_refs, vec![(set.id, gone.id)]);
+        assert_eq!(report.orphaned_vote_keys.len(), 1);
+        assert_eq!(report.orphaned_vote_keys[0].0, gone.id);
+        assert!(matches!(
+            as_caller(user(1), check_integrity),
+            Err(Error::Unauthorized { .. })
+        ));
+    }
+
+    #[test]
+    fn vote_cap_fills_up_and_none_is_unlimited() {
+        let capped = create(QuizPayload {
+            max_total_votes: Some(2),
+            ..payload(&["A", "B"])
+        });
+        vote(user(1), capped.id, "A").unwrap();
+        vote(user(2), capped.id, "B").unwrap();
+        assert!(matches!(
+            vote(user(3), capped.id, "A"),
+            Err(Error::Full { .. })
+        ));
+        // a changed vote replaces the old one, so it still fits
+        let quiz = vote(user(1), capped.id, "B").unwrap();
+        assert_eq!(quiz.answers.get("B"), Some(&2));
+
+        let open = poll(&["A", "B"]);
+        for voter in 1..=5 {
+            vote(user(voter), open.id, "A").unwrap();
+        }
+        assert_eq!(get_quiz(open.id).unwrap().total_votes(), 5);
+    }
+
+    #[test]
+    fn allowlist_admits_only_listed_voters() {
+        let listed = create(QuizPayload {
+            allowed_voters: Some(vec![user(1)]),
+            ..payload(&["A", "B"])
+        });
+        vote(user(1), listed.id, "A").unwrap();
+        assert!(matches!(
+            vote(user(2), listed.id, "A"),
+            Err(Error::Unauthorized { .. })
+        ));
+
+        add_allowed_voter(listed.id, user(2)).unwrap();
+        vote(user(2), listed.id, "B").unwrap();
+
+        let open = poll(&["A", "B"]);
+        vote(user(3), open.id, "A").unwrap();
+    }
+
+    #[test]
+    fn new_round_clears_votes_and_keeps_earlier_snapshots() {
+        let quiz = poll(&["A", "B"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        assert!(matches!(
+            start_new_round(quiz.id),
+            Err(Error::Conflict { .. })
+        ));
+        close_and_snapshot(quiz.id).unwrap();
+
+        let quiz = start_new_round(quiz.id).unwrap();
+        assert_eq!(quiz.round(), 1);
+        assert_eq!(quiz.total_votes(), 0);
+        assert!(quiz.status() == QuizStatus::Open);
+        assert!(!has_voted(quiz.id, &user(1)));
+        let snapshot = get_snapshot(quiz.id).unwrap();
+        assert_eq!(snapshot.total_votes, 1);
+
+        // the same voter can take part again in the new round
+        vote(user(1), quiz.id, "B").unwrap();
+        close_and_snapshot(quiz.id).unwrap();
+        assert_eq!(start_new_round(quiz.id).unwrap().round(), 2);
+        let kept = SNAPSHOTS.with(|snapshots| {
+            snapshots
+                .borrow()
+                .range((quiz.id, 0)..=(quiz.id, u64::MAX))
+                .count()
+        });
+        assert_eq!(kept, 2);
+    }
+
+    #[test]
+    fn split_moves_counts_and_votes_with_the_options() {
+        let quiz = poll(&["A", "B", "C", "D"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "C").unwrap();
+        vote(user(3), quiz.id, "D").unwrap();
+        assert!(matches!(
+            split_quiz(quiz.id, strings(&["A"])),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            split_quiz(quiz.id, strings(&["A", "B", "C"])),
+            Err(Error::InvalidInput { .. })
+        ));
+
+        let (kept, forked) = split_quiz(quiz.id, strings(&["C", "D"])).unwrap();
+        assert_eq!(kept.options, strings(&["A", "B"]));
+        assert_eq!(kept.total_votes(), 1);
+        assert_eq!(forked.options, strings(&["C", "D"]));
+        assert_eq!(forked.answers.get("C"), Some(&1));
+        assert_eq!(forked.answers.get("D"), Some(&1));
+        assert!(has_voted(kept.id, &user(1)));
+        assert!(!has_voted(kept.id, &user(2)));
+        assert!(has_voted(forked.id, &user(2)) && has_voted(forked.id, &user(3)));
+    }
+
+    #[test]
+    fn results_csv_import_sets_counts_or_rejects_the_whole_file() {
+        let quiz = poll(&["Yes, please", "No"]);
+        let import = |csv: &str| {
+            as_caller(controller(), || {
+                import_results_csv(quiz.id, csv.to_string())
+            })
+        };
+
+        let quiz = import("option,votes\n\nYes, please,7\nno, 3\n").unwrap();
+        assert_eq!(quiz.answers.get("Yes, please"), Some(&7));
+        assert_eq!(quiz.answers.get("No"), Some(&3));
+
+        assert!(matches!(
+            import("Yes, please,1\nMaybe,2"),
+            Err(Error::InvalidInput { msg }) if msg.starts_with("line 2:")
+        ));
+        assert!(matches!(
+            import("No 4"),
+            Err(Error::InvalidInput { msg }) if msg.starts_with("line 1:")
+        ));
+        assert!(matches!(import("No,many"), Err(Error::InvalidInput { .. })));
+        assert_eq!(
+            get_quiz(quiz.id).unwrap().answers.get("Yes, please"),
+            Some(&7)
+        );
+        assert!(matches!(
+            import_results_csv(quiz.id, "No,1".to_string()),
+            Err(Error::Unauthorized { .. })
+        ));
+    }
+
+    #[test]
+    fn bulk_status_change_reports_each_id_on_its_own() {
+        let mine = poll(&["A", "B"]);
+        let theirs = create_quiz_as(user(1), payload(&["C", "D"])).unwrap();
+
+        let results = set_status_bulk(vec![mine.id, theirs.id, 999], QuizStatus::Closed);
+        assert!(matches!(results[0], Ok(id) if id == mine.id));
+        assert!(matches!(results[1], Err(Error::Unauthorized { .. })));
+        assert!(matches!(
+            results[2],
+            Err(Error::NotFound {
+                resource: Resource::Quiz,
+                ..
+            })
+        ));
+        assert!(get_quiz(mine.id).unwrap().status() == QuizStatus::Closed);
+        assert!(get_quiz(theirs.id).unwrap().status() == QuizStatus::Open);
+
+        let results = set_status_bulk(vec![mine.id], QuizStatus::Draft);
+        assert!(matches!(results[0], Err(Error::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn banned_voters_are_turned_away_until_unbanned() {
+        let quiz = poll(&["A", "B"]);
+        assert!(matches!(
+            as_caller(user(1), || ban_voter(user(2))),
+            Err(Error::Unauthorized { .. })
+        ));
+        as_caller(controller(), || ban_voter(user(2))).unwrap();
+        assert!(is_banned(user(2)));
+        assert!(matches!(
+            vote(user(2), quiz.id, "A"),
+            Err(Error::Unauthorized { .. })
+        ));
+        vote(user(3), quiz.id, "A").unwrap();
+
+        as_caller(controller(), || unban_voter(user(2))).unwrap();
+        assert!(!is_banned(user(2)));
+        vote(user(2), quiz.id, "B").unwrap();
+    }
+
+    #[test]
+    fn vote_change_follows_the_allow_vote_change_toggle() {
+        let quiz = poll(&["A", "B"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        let quiz = vote(user(1), quiz.id, "B").unwrap();
+        assert_eq!(quiz.answers.get("A"), Some(&0));
+        assert_eq!(quiz.answers.get("B"), Some(&1));
+
+        let toggle = |allow| {
+            patch_quiz(
+                quiz.id,
+                UpdateQuizPayload {
+                    allow_vote_change: Some(allow),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        toggle(false);
+        assert!(matches!(
+            vote(user(1), quiz.id, "A"),
+            Err(Error::AlreadyAnswered { .. })
+        ));
+        toggle(true);
+        assert_eq!(
+            vote(user(1), quiz.id, "A").unwrap().answers.get("A"),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn multi_select_toggle_controls_ballots_with_several_options() {
+        let quiz = poll(&["A", "B", "C"]);
+        let ballot =
+            |voter, options: &[&str]| as_caller(voter, || vote_multiple(quiz.id, strings(options)));
+        assert!(matches!(
+            ballot(user(1), &["A", "B"]),
+            Err(Error::InvalidInput { .. })
+        ));
+
+        let quiz = set_multi_select(quiz.id, true).unwrap();
+        assert!(quiz.is_multi_select());
+        let quiz = ballot(user(1), &["A", "B"]).unwrap();
+        assert_eq!(quiz.answers.get("A"), Some(&1));
+        assert_eq!(quiz.answers.get("B"), Some(&1));
+        assert!(matches!(
+            ballot(user(2), &["A", "a"]),
+            Err(Error::InvalidInput { .. })
+        ));
+
+        // counts cast while it was multi-select stay
+        let quiz = set_multi_select(quiz.id, false).unwrap();
+        assert_eq!(quiz.total_votes(), 2);
+        assert!(matches!(
+            ballot(user(2), &["B", "C"]),
+            Err(Error::InvalidInput { .. })
+        ));
+        vote(user(2), quiz.id, "C").unwrap();
+        assert!(matches!(
+            as_caller(user(1), || set_multi_select(quiz.id, true)),
+            Err(Error::Unauthorized { .. })
+        ));
+    }
+
+    #[test]
+    fn lock_on_first_vote_freezes_edits_once_someone_votes() {
+        let quiz = create(QuizPayload {
+            lock_on_first_vote: Some(true),
+            ..payload(&["A", "B"])
+        });
+        let rename = |question: &str| {
+            patch_quiz(
+                quiz.id,
+                UpdateQuizPayload {
+                    question: Some(question.to_string()),
+                    ..Default::default()
+                },
+            )
+        };
+        rename("Before any vote?").unwrap();
+        add_options(quiz.id, strings(&["C"])).unwrap();
+
+        vote(user(1), quiz.id, "A").unwrap();
+        assert!(matches!(rename("After a vote?"), Err(Error::Locked { .. })));
+        assert!(matches!(
+            add_options(quiz.id, strings(&["D"])),
+            Err(Error::Locked { .. })
+        ));
+        assert_eq!(get_quiz(quiz.id).unwrap().question, "Before any vote?");
+
+        // without the flag, votes don't lock anything
+        let open = poll(&["A", "B"]);
+        vote(user(1), open.id, "A").unwrap();
+        add_options(open.id, strings(&["C"])).unwrap();
+    }
+
+    #[test]
+    fn correct_option_is_set_checked_and_overwritten() {
+        let quiz = poll(&["A", "B"]);
+        let quiz = set_correct_option(quiz.id, "A".to_string()).unwrap();
+        assert_eq!(quiz.correct_options, Some(strings(&["A"])));
+        assert!(matches!(
+            set_correct_option(quiz.id, "Z".to_string()),
+            Err(Error::NotFound {
+                resource: Resource::Option,
+                ..
+            })
+        ));
+        assert_eq!(
+            get_quiz(quiz.id).unwrap().correct_options,
+            Some(strings(&["A"]))
+        );
+
+        let quiz = set_correct_option(quiz.id, "B".to_string()).unwrap();
+        assert_eq!(quiz.correct_options, Some(strings(&["B"])));
+        assert!(matches!(
+            as_caller(user(1), || set_correct_option(quiz.id, "A".to_string())),
+            Err(Error::Unauthorized { .. })
+        ));
+    }
+
+    #[test]
+    fn add_options_takes_the_whole_batch_or_none_of_it() {
+        let quiz = poll(&["A", "B"]);
+        let quiz = add_options(quiz.id, strings(&[" C ", "D"])).unwrap();
+        assert_eq!(quiz.options, strings(&["A", "B", "C", "D"]));
+        assert_eq!(quiz.answers.get("C"), Some(&0));
+
+        assert!(matches!(
+            add_options(quiz.id, strings(&["E", "e"])),
+            Err(Error::ValidationFailed { .. })
+        ));
+        assert!(matches!(
+            add_options(quiz.id, strings(&["E", "A"])),
+            Err(Error::ValidationFailed { .. })
+        ));
+        let too_many: Vec<String> = (0..=MAX_OPTIONS - 4)
+            .map(|i| format!("Extra {}", i))
+            .collect();
+        assert!(matches!(
+            add_options(quiz.id, too_many),
+            Err(Error::ValidationFailed { .. })
+        ));
+        assert_eq!(get_quiz(quiz.id).unwrap().options.len(), 4);
+
+        let fill: Vec<String> = (0..MAX_OPTIONS - 4)
+            .map(|i| format!("Extra {}", i))
+            .collect();
+        assert_eq!(
+            add_options(quiz.id, fill).unwrap().options.len(),
+            MAX_OPTIONS
+        );
+    }
+
+    #[test]
+    fn option_can_be_added_before_the_first_vote_only() {
+        let quiz = poll(&["A", "B"]);
+        let quiz = add_option_pre_vote(quiz.id, "C".to_string()).unwrap();
+        assert_eq!(quiz.options, strings(&["A", "B", "C"]));
+
+        vote(user(1), quiz.id, "C").unwrap();
+        assert!(matches!(
+            add_option_pre_vote(quiz.id, "D".to_string()),
+            Err(Error::Locked { .. })
+        ));
+        assert_eq!(get_quiz(quiz.id).unwrap().options.len(), 3);
+    }
+
+    #[test]
+    fn archive_older_than_takes_only_quizzes_before_the_cutoff() {
+        let old = poll(&["A", "B"]);
+        let older = poll(&["C", "D"]);
+        system::advance(NANOS_PER_HOUR);
+        let cutoff = time();
+        let new = poll(&["E", "F"]);
+
+        assert!(matches!(
+            as_caller(user(1), || archive_older_than(cutoff)),
+            Err(Error::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            as_caller(controller(), || archive_older_than(cutoff)),
+            Ok(2)
+        ));
+        assert!(get_quiz(old.id).unwrap().is_archived());
+        assert!(get_quiz(older.id).unwrap().is_archived());
+        assert!(!get_quiz(new.id).unwrap().is_archived());
+
+        // already archived ones aren't counted again
+        assert!(matches!(
+            as_caller(controller(), || archive_older_than(cutoff)),
+            Ok(0)
+        ));
+    }
+
+    #[test]
+    fn featured_quizzes_list_most_recently_featured_first() {
+        let first = poll(&["A", "B"]);
+        let second = poll(&["C", "D"]);
+        poll(&["E", "F"]);
+        assert!(matches!(
+            feature_quiz(first.id),
+            Err(Error::Unauthorized { .. })
+        ));
+
+        as_caller(controller(), || feature_quiz(first.id)).unwrap();
+        system::advance(1);
+        as_caller(controller(), || feature_quiz(second.id)).unwrap();
+        let ids = || {
+            get_featured_quizzes()
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(), vec![second.id, first.id]);
+
+        as_caller(controller(), || unfeature_quiz(second.id)).unwrap();
+        assert_eq!(ids(), vec![first.id]);
+        archive_quiz(first.id).unwrap();
+        assert!(ids().is_empty());
+    }
+
+    #[test]
+    fn option_color_is_set_checked_and_cleared() {
+        let quiz = poll(&["A", "B"]);
+        let color = |option: &str, color: Option<&str>| {
+            set_option_color(quiz.id, option.to_string(), color.map(str::to_string))
+        };
+        let quiz = color("A", Some("#1a2B3c")).unwrap();
+        assert_eq!(
+            quiz.option_colors
+                .as_ref()
+                .and_then(|colors| colors.get("A")),
+            Some(&"#1a2B3c".to_string())
+        );
+        for invalid in ["1a2b3c", "#12345", "#12345g", "red"] {
+            assert!(matches!(
+                color("A", Some(invalid)),
+                Err(Error::InvalidInput { .. })
+            ));
+        }
+        assert!(matches!(
+            color("Z", Some("#000000")),
+            Err(Error::NotFound { .. })
+        ));
+
+        let quiz = color("A", None).unwrap();
+        assert!(quiz.option_colors.unwrap_or_default().is_empty());
+    }
+
+    #[test]
+    fn metadata_is_stored_as_given_and_rejected_unless_json() {
+        let quiz = poll(&["A", "B"]);
+        let json = r#"{"theme":"dark","external_id":42}"#;
+        set_metadata(quiz.id, json.to_string()).unwrap();
+        assert_eq!(get_quiz(quiz.id).unwrap().metadata.as_deref(), Some(json));
+
+        for invalid in [
+            "{theme: dark}".to_string(),
+            format!("\"{}\"", "x".repeat(MAX_METADATA_LEN)),
+        ] {
+            assert!(matches!(
+                set_metadata(quiz.id, invalid),
+                Err(Error::ValidationFailed { field, .. }) if field == "metadata"
+            ));
+        }
+        assert!(matches!(
+            as_caller(user(1), || set_metadata(quiz.id, "{}".to_string())),
+            Err(Error::Unauthorized { .. })
+        ));
+        assert_eq!(get_quiz(quiz.id).unwrap().metadata.as_deref(), Some(json));
+    }
+
+    #[test]
+    fn default_expiry_applies_unless_the_payload_sets_one() {
+        let day = 24 * NANOS_PER_HOUR;
+        assert_eq!(poll(&["A", "B"]).expires_at, None);
+
+        as_caller(controller(), || set_default_expiry(Some(day))).unwrap();
+        let inherited = poll(&["A", "B"]);
+        assert_eq!(inherited.expires_at, Some(inherited.created_at + day));
+        let overridden = create(QuizPayload {
+            expires_at: Some(time() + NANOS_PER_HOUR),
+            ..payload(&["A", "B"])
+        });
+        assert_eq!(overridden.expires_at, Some(time() + NANOS_PER_HOUR));
+
+        as_caller(controller(), || set_default_expiry(None)).unwrap();
+        assert_eq!(poll(&["A", "B"]).expires_at, None);
+    }
+
+    #[test]
+    fn closing_notifies_the_configured_target_only() {
+        let first = poll(&["A", "B"]);
+        close_and_snapshot(first.id).unwrap();
+        assert!(system::notified().is_empty());
+
+        let target = user(9);
+        as_caller(controller(), || set_close_notification_target(Some(target))).unwrap();
+        let second = poll(&["A", "B"]);
+        close_and_snapshot(second.id).unwrap();
+        assert_eq!(
+            system::notified(),
+            vec![(target, "on_quiz_closed".to_string())]
+        );
+    }
+
+    #[test]
+    fn vote_shares_round_to_the_configured_precision() {
+        let quiz = poll(&["A", "B"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "A").unwrap();
+        vote(user(3), quiz.id, "B").unwrap();
+        let shares = || {
+            get_vote_shares(quiz.id)
+                .unwrap()
+                .iter()
+                .map(|share| share.share)
+                .collect::<Vec<_>>()
+        };
+
+        as_caller(controller(), || set_result_precision(0)).unwrap();
+        assert_eq!(shares(), vec![1.0, 0.0]);
+        as_caller(controller(), || set_result_precision(4)).unwrap();
+        assert_eq!(shares(), vec![0.6667, 0.3333]);
+
+        let config = as_caller(controller(), || {
+            set_result_precision(MAX_RESULT_PRECISION + 3)
+        })
+        .unwrap();
+        assert_eq!(config.result_precision, Some(MAX_RESULT_PRECISION));
+    }
+
+    #[test]
+    fn fuzzy_search_finds_near_misses_closest_first() {
+        let exact = create(QuizPayload {
+            question: "Best programming language?".to_string(),
+            ..payload(&["Rust", "Go"])
+        });
+        let typo = create(QuizPayload {
+            question: "Favourite langauge for scripts?".to_string(),
+            ..payload(&["Python", "Ruby"])
+        });
+        create(QuizPayload {
+            question: "Tea or coffee?".to_string(),
+            ..payload(&["Tea", "Coffee"])
+        });
+
+        let ids = |query: &str, max_distance| {
+            search_quizzes_fuzzy(query.to_string(), max_distance)
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("Language", 2), vec![exact.id, typo.id]);
+        assert_eq!(ids("language", 0), vec![exact.id]);
+        assert_eq!(ids("pythn", 1), vec![typo.id]);
+        assert!(ids("zebra", 1).is_empty());
+        assert!(ids("  ", 3).is_empty());
+    }
+
+    #[test]
+    fn expiring_soon_lists_open_quizzes_inside_the_window() {
+        let expiring = |in_ns| {
+            create(QuizPayload {
+                expires_at: Some(time() + in_ns),
+                ..payload(&["A", "B"])
+            })
+        };
+        let later = expiring(3 * NANOS_PER_HOUR);
+        let soon = expiring(NANOS_PER_HOUR);
+        let outside = expiring(24 * NANOS_PER_HOUR);
+        poll(&["A", "B"]);
+        let closed = expiring(NANOS_PER_HOUR);
+        close_and_snapshot(closed.id).unwrap();
+
+        let ids = |within_ns| {
+            get_expiring_soon(within_ns)
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(3 * NANOS_PER_HOUR), vec![soon.id, later.id]);
+        assert_eq!(
+            ids(24 * NANOS_PER_HOUR),
+            vec![soon.id, later.id, outside.id]
+        );
+
+        // already expired ones are past saving
+        system::advance(2 * NANOS_PER_HOUR);
+        assert_eq!(ids(3 * NANOS_PER_HOUR), vec![later.id]);
+    }
+
+    #[test]
+    fn created_within_lists_recent_quizzes_newest_first() {
+        assert!(matches!(
+            get_created_within(0),
+            Err(Error::InvalidInput { .. })
+        ));
+        let old = poll(&["A", "B"]);
+        system::advance(2 * NANOS_PER_HOUR);
+        let recent = poll(&["C", "D"]);
+        system::advance(2);
+        let newest = poll(&["E", "F"]);
+
+        let ids = |window_ns| {
+            get_created_within(window_ns)
+                .unwrap()
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(NANOS_PER_HOUR), vec![newest.id, recent.id]);
+        assert_eq!(ids(3 * NANOS_PER_HOUR), vec![newest.id, recent.id, old.id]);
+        assert_eq!(ids(1), vec![newest.id]);
+    }
+
+    #[test]
+    fn creation_histogram_counts_quizzes_per_bucket() {
+        assert!(matches!(
+            get_creation_histogram(0),
+            Err(Error::InvalidInput { .. })
+        ));
+        let start = time() - time() % NANOS_PER_HOUR;
+        poll(&["A", "B"]);
+        poll(&["C", "D"]);
+        system::advance(2 * NANOS_PER_HOUR);
+        poll(&["E", "F"]);
+
+        let buckets: Vec<(u64, u64)> = get_creation_histogram(NANOS_PER_HOUR)
+            .unwrap()
+            .iter()
+            .map(|bucket| (bucket.bucket_start, bucket.count))
+            .collect();
+        // empty buckets are left out
+        assert_eq!(buckets, vec![(start, 2), (start + 2 * NANOS_PER_HOUR, 1)]);
+    }
+
+    #[test]
+    fn storage_info_grows_with_each_quiz() {
+        let empty = get_storage_info();
+        assert_eq!((empty.quiz_count, empty.total_bytes), (0, 0));
+
+        poll(&["A", "B"]);
+        let one = get_storage_info();
+        assert_eq!(one.quiz_count, 1);
+        assert!(one.total_bytes > 0);
+        assert_eq!(one.largest_quiz_bytes, one.total_bytes);
+
+        create(QuizPayload {
+            question: "q".repeat(200),
+            ..payload(&["C", "D"])
+        });
+        let two = get_storage_info();
+        assert_eq!(two.quiz_count, 2);
+        assert!(two.total_bytes > one.total_bytes);
+        assert!(two.largest_quiz_bytes > one.largest_quiz_bytes);
+    }
+
+    #[test]
+    fn vote_shares_add_up_to_one() {
+        let quiz = poll(&["A", "B", "C"]);
+        assert!(get_vote_shares(quiz.id)
+            .unwrap()
+            .iter()
+            .all(|share| share.share == 0.0));
+        for (voter, option) in [(1, "A"), (2, "B"), (3, "C"), (4, "C"), (5, "A"), (6, "C")] {
+            vote(user(voter), quiz.id, option).unwrap();
+        }
+
+        let shares = get_vote_shares(quiz.id).unwrap();
+        let votes: Vec<u32> = shares.iter().map(|share| share.votes).collect();
+        assert_eq!(votes, vec![2, 1, 3]);
+        let sum: f64 = shares.iter().map(|share| share.share).sum();
+        assert!((sum - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn context_tells_the_caller_about_their_own_vote() {
+        let quiz = create(QuizPayload {
+            allow_vote_change: Some(false),
+            ..payload(&["A", "B"])
+        });
+        vote(user(1), quiz.id, "B").unwrap();
+        let context = |viewer| as_caller(viewer, || get_quiz_with_context(quiz.id, None)).unwrap();
+
+        let voter = context(user(1));
+        assert!(voter.has_voted);
+        assert_eq!(voter.my_option.as_deref(), Some("B"));
+        assert!(!voter.votable);
+        let newcomer = context(user(2));
+        assert!(!newcomer.has_voted && newcomer.my_option.is_none());
+        assert!(newcomer.votable);
+
+        close_and_snapshot(quiz.id).unwrap();
+        let voter = context(user(1));
+        assert_eq!(voter.my_option.as_deref(), Some("B"));
+        assert!(!voter.votable);
+        assert!(!context(user(2)).votable);
+    }
+
+    #[test]
+    fn summaries_follow_the_quizzes_without_hidden_tallies() {
+        let shown = poll(&["A", "B", "C"]);
+        let hidden = create(QuizPayload {
+            results_visibility: Some(ResultsVisibility::AfterClose),
+            ..payload(&["D", "E"])
+        });
+        vote(user(1), shown.id, "A").unwrap();
+        vote(user(1), hidden.id, "D").unwrap();
+
+        let page = as_caller(user(2), || list_quiz_summaries(0, 10));
+        assert_eq!(page.total, 2);
+        let summaries: Vec<(u64, &str, u64, u64)> = page
+            .items
+            .iter()
+            .map(|summary| {
+                let question = summary.question.as_str();
+                (
+                    summary.id,
+                    question,
+                    summary.option_count,
+                    summary.total_votes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summaries,
+            vec![
+                (shown.id, "Which one?", 3, 1),
+                (hidden.id, "Which one?", 2, 0)
+            ]
+        );
+        assert_eq!(list_quiz_summaries(0, 10).items[1].total_votes, 1);
+    }
+
+    #[test]
+    fn display_order_is_stable_and_votes_still_go_by_text() {
+        let options = strings(&["A", "B", "C", "D", "E", "F", "G", "H"]);
+        let quiz = create(payload(&["A", "B", "C", "D", "E", "F", "G", "H"]));
+        let shown = get_quiz_display(quiz.id, None).unwrap().options;
+        assert_eq!(get_quiz_display(quiz.id, None).unwrap().options, shown);
+        let mut sorted = shown.clone();
+        sorted.sort();
+        assert_eq!(sorted, options);
+
+        let first = shown[0].clone();
+        let quiz = vote(user(1), quiz.id, &first).unwrap();
+        assert_eq!(quiz.answers.get(&first), Some(&1));
+        assert_eq!(quiz.options, options);
+    }
+
+    #[test]
+    fn engagement_is_compared_to_the_mean_over_all_quizzes() {
+        let busy = poll(&["A", "B"]);
+        let quiet = poll(&["C", "D"]);
+        let untouched = poll(&["E", "F"]);
+        assert!(get_engagement_comparison(busy.id)
+            .unwrap()
+            .ratio_to_mean
+            .is_none());
+        for voter in 1..=5 {
+            vote(user(voter), busy.id, "A").unwrap();
+        }
+        vote(user(1), quiet.id, "C").unwrap();
+
+        let busy = get_engagement_comparison(busy.id).unwrap();
+        assert_eq!(busy.total_votes, 5);
+        assert_eq!(busy.mean_total_votes, 2.0);
+        assert_eq!(busy.ratio_to_mean, Some(2.5));
+        assert_eq!(
+            get_engagement_comparison(quiet.id).unwrap().ratio_to_mean,
+            Some(0.5)
+        );
+        assert_eq!(
+            get_engagement_comparison(untouched.id)
+                .unwrap()
+                .ratio_to_mean,
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn option_count_stats_take_the_middle_pair_for_an_even_count() {
+        let stats = get_option_count_stats();
+        assert_eq!((stats.min, stats.max, stats.median), (0, 0, 0.0));
+
+        poll(&["A", "B"]);
+        poll(&["A", "B", "C", "D", "E"]);
+        poll(&["A", "B", "C"]);
+        let odd = get_option_count_stats();
+        assert_eq!((odd.min, odd.max), (2, 5));
+        assert_eq!(odd.median, 3.0);
+        assert!((odd.mean - 10.0 / 3.0).abs() < 1e-9);
+
+        poll(&["A", "B", "C", "D"]);
+        let even = get_option_count_stats();
+        assert_eq!(even.median, 3.5);
+        assert_eq!(even.mean, 3.5);
+    }
+
+    #[test]
+    fn answers_json_has_every_option_in_key_order() {
+        let quiz = poll(&["Zebra", "Apple", "Mango"]);
+        vote(user(1), quiz.id, "Zebra").unwrap();
+        vote(user(2), quiz.id, "Zebra").unwrap();
+        vote(user(3), quiz.id, "Mango").unwrap();
+
+        let json = get_answers_json(quiz.id).unwrap();
+        assert_eq!(json, r#"{"Apple":0,"Mango":1,"Zebra":2}"#);
+        let parsed: BTreeMap<String, u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, get_quiz(quiz.id).unwrap().answers);
+    }
+
+    #[test]
+    fn bulk_tagging_reports_each_id_and_skips_existing_tags() {
+        let tagged = create(QuizPayload {
+            tags: Some(strings(&["rust"])),
+            ..payload(&["A", "B"])
+        });
+        let plain = poll(&["C", "D"]);
+        let theirs = create_quiz_as(user(1), payload(&["E", "F"])).unwrap();
+
+        let results = add_tag_to_quizzes(
+            vec![tagged.id, plain.id, 999, theirs.id],
+            " Rust ".to_string(),
+        );
+        assert!(matches!(results[0], Ok(id) if id == tagged.id));
+        assert!(matches!(results[1], Ok(id) if id == plain.id));
+        assert!(matches!(results[2], Err(Error::NotFound { .. })));
+        assert!(matches!(results[3], Err(Error::Unauthorized { .. })));
+        assert_eq!(get_quiz(tagged.id).unwrap().tags(), strings(&["rust"]));
+        assert_eq!(get_quiz(plain.id).unwrap().tags(), strings(&["rust"]));
+        assert!(get_quiz(theirs.id).unwrap().tags().is_empty());
+
+        let results = add_tag_to_quizzes(vec![plain.id], "  ".to_string());
+        assert!(matches!(results[0], Err(Error::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn winner_margin_for_decisive_narrow_and_tied_results() {
+        let margin = |votes: &[&str]| {
+            let quiz = poll(&["A", "B", "C"]);
+            for (voter, option) in votes.iter().enumerate() {
+                vote(user(voter as u8 + 1), quiz.id, option).unwrap();
+            }
+            get_winner_margin(quiz.id).unwrap()
+        };
+
+        let empty = margin(&[]);
+        assert!(empty.winners.is_empty());
+
+        let decisive = margin(&["A", "A", "A", "B"]);
+        assert_eq!(decisive.winners, strings(&["A"]));
+        assert_eq!(
+            (
+                decisive.winner_votes,
+                decisive.runner_up_votes,
+                decisive.margin_votes
+            ),
+            (3, 1, 2)
+        );
+        assert!((decisive.winner_share - 0.75).abs() < 1e-9);
+        assert!((decisive.margin_percent - 50.0).abs() < 1e-9);
+
+        let narrow = margin(&["A", "B", "B", "A", "C", "B"]);
+        assert_eq!(narrow.winners, strings(&["B"]));
+        assert_eq!((narrow.runner_up_votes, narrow.margin_votes), (2, 1));
+
+        let tie = margin(&["C", "A", "A", "C"]);
+        assert_eq!(tie.winners, strings(&["A", "C"]));
+        assert_eq!((tie.margin_votes, tie.margin_percent), (0, 0.0));
+    }
+
+    #[test]
+    fn markdown_has_a_heading_and_one_row_per_option() {
+        let quiz = create(QuizPayload {
+            question: "Best *editor*?".to_string(),
+            ..payload(&["Vim", "Emacs", "Nano"])
+        });
+        vote(user(1), quiz.id, "Vim").unwrap();
+        vote(user(2), quiz.id, "Vim").unwrap();
+        vote(user(3), quiz.id, "Emacs").unwrap();
+        vote(user(4), quiz.id, "Vim").unwrap();
+
+        let markdown = render_quiz_markdown(quiz.id).unwrap();
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(lines[0], r"## Best \*editor\*?");
+        assert_eq!(
+            &lines[2..],
+            [
+                "| Option | Votes | Percent |",
+                "| --- | ---: | ---: |",
+                "| Vim | 3 | 75.0% |",
+                "| Emacs | 1 | 25.0% |",
+                "| Nano | 0 | 0.0% |",
+            ]
+        );
+    }
+
+    #[test]
+    fn common_option_sets_put_the_most_shared_pair_first() {
+        poll(&["Yes", "No"]);
+        poll(&["no", "Maybe", " yes"]);
+        poll(&["Yes", "No", "Maybe"]);
+        poll(&["Red", "Blue"]);
+
+        let sets: Vec<(Vec<String>, u64)> = get_common_option_sets(3)
+            .into_iter()
+            .map(|set| (set.options, set.quiz_count))
+            .collect();
+        assert_eq!(
+            sets,
+            vec![
+                (strings(&["no", "yes"]), 3),
+                (strings(&["maybe", "no"]), 2),
+                (strings(&["maybe", "yes"]), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_diff_gives_per_option_deltas() {
+        let quiz = poll(&["A", "B"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "A").unwrap();
+        assert_eq!(take_snapshot(quiz.id).unwrap(), 0);
+        vote(user(2), quiz.id, "B").unwrap();
+        vote(user(3), quiz.id, "B").unwrap();
+        vote(user(4), quiz.id, "B").unwrap();
+        assert_eq!(take_snapshot(quiz.id).unwrap(), 1);
+
+        let diff = diff_snapshots(quiz.id, 0, 1).unwrap();
+        assert_eq!(
+            diff.deltas,
+            vec![("A".to_string(), -1), ("B".to_string(), 3)]
+        );
+        assert_eq!(diff.total_delta, 2);
+        assert!(matches!(
+            diff_snapshots(quiz.id, 1, 1),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            diff_snapshots(quiz.id, 0, 2),
+            Err(Error::NotFound {
+                resource: Resource::Snapshot,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn own_unanswered_lists_the_creators_quizzes_they_skipped() {
+        let creator = user(1);
+        let answered = create_quiz_as(creator, payload(&["A", "B"])).unwrap();
+        let skipped = create_quiz_as(creator, payload(&["C", "D"])).unwrap();
+        poll(&["E", "F"]);
+        vote(creator, answered.id, "A").unwrap();
+        // someone else's vote doesn't count as the creator's
+        vote(user(2), skipped.id, "C").unwrap();
+
+        let ids: Vec<u64> = get_own_unanswered(creator)
+            .iter()
+            .map(|quiz| quiz.id)
+            .collect();
+        assert_eq!(ids, vec![skipped.id]);
+        assert!(get_own_unanswered(user(3)).is_empty());
+    }
+
+    #[test]
+    fn tags_are_counted_across_quizzes_most_used_first() {
+        let tagged = |tags: &[&str]| {
+            create(QuizPayload {
+                tags: Some(strings(tags)),
+                ..payload(&["A", "B"])
+            })
+        };
+        tagged(&["rust", "web"]);
+        tagged(&["Web", "go"]);
+        tagged(&["rust", "web", "cli"]);
+        let archived = tagged(&["cli", "go"]);
+        archive_quiz(archived.id).unwrap();
+
+        assert_eq!(
+            list_tags(),
+            vec![
+                ("web".to_string(), 3),
+                ("rust".to_string(), 2),
+                ("cli".to_string(), 1),
+                ("go".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn lopsided_quizzes_compare_the_longest_option_to_the_shortest() {
+        let even = poll(&["Cats", "Dogs"]);
+        let lopsided = poll(&["No", "Yes, and I would do it again tomorrow"]);
+        let borderline = poll(&["Tea", "Coffee"]);
+        assert!(matches!(
+            get_lopsided_quizzes(0.5),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert!(get_lopsided_quizzes(f64::NAN).is_err());
+
+        let ids = |ratio| {
+            get_lopsided_quizzes(ratio)
+                .unwrap()
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(2.0), vec![lopsided.id]);
+        assert_eq!(ids(1.5), vec![lopsided.id, borderline.id]);
+        assert_eq!(ids(1.0), vec![lopsided.id, borderline.id]);
+        assert!(!ids(1.0).contains(&even.id));
+    }
+
+    #[test]
+    fn quiz_at_position_counts_in_id_order() {
+        let first = poll(&["A", "B"]);
+        poll(&["C", "D"]);
+        let last = poll(&["E", "F"]);
+
+        assert_eq!(get_quiz_at_position(0).unwrap().id, first.id);
+        assert_eq!(get_quiz_at_position(2).unwrap().id, last.id);
+        assert!(matches!(
+            get_quiz_at_position(3),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn import_dry_run_reports_each_rejected_entry() {
+        let report = validate_import_json(
+            r#"[
+                {"question": "Tea or coffee?", "options": ["Tea", "Coffee"]},
+                {"question": "Lonely?", "options": ["Yes"]},
+                {"question": 42},
+                {"question": "Cats or dogs?", "options": ["Cats", "Dogs"]}
+            ]"#
+            .to_string(),
+        );
+        assert!(report.parse_error.is_none());
+        assert_eq!(report.valid_count, 2);
+        let failures: Vec<u64> = report.failures.iter().map(|(index, _)| *index).collect();
+        assert_eq!(failures, vec![1, 2]);
+        assert!(matches!(
+            report.failures[0].1,
+            Error::ValidationFailed { .. }
+        ));
+        assert!(matches!(report.failures[1].1, Error::InvalidInput { .. }));
+        assert_eq!(get_quiz_count(), 0);
+
+        let broken = validate_import_json("[{".to_string());
+        assert!(broken.parse_error.is_some());
+        assert_eq!(broken.valid_count, 0);
+        assert!(broken.failures.is_empty());
+    }
+
+    #[test]
+    fn rank_movement_compares_against_the_latest_snapshot() {
+        let quiz = poll(&["A", "B", "C"]);
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "A").unwrap();
+        vote(user(3), quiz.id, "B").unwrap();
+        assert!(get_rank_movement(quiz.id)
+            .unwrap()
+            .iter()
+            .all(|row| matches!(row.movement, Movement::New) && row.previous_rank.is_none()));
+
+        take_snapshot(quiz.id).unwrap();
+        for voter in 4..=6 {
+            vote(user(voter), quiz.id, "B").unwrap();
+        }
+        let rows = get_rank_movement(quiz.id).unwrap();
+        let ranks: Vec<(&str, u64, Option<u64>)> = rows
+            .iter()
+            .map(|row| (row.option.as_str(), row.current_rank, row.previous_rank))
+            .collect();
+        assert_eq!(
+            ranks,
+            vec![("A", 2, Some(1)), ("B", 1, Some(2)), ("C", 3, Some(3))]
+        );
+        assert!(matches!(rows[0].movement, Movement::Down));
+        assert!(matches!(rows[1].movement, Movement::Up));
+        assert!(matches!(rows[2].movement, Movement::Same));
+    }
+
+    #[test]
+    fn peak_window_finds_the_busiest_stretch() {
+        let quiz = poll(&["A", "B"]);
+        assert!(matches!(
+            get_peak_voting_window(quiz.id, 0),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert_eq!(
+            get_peak_voting_window(quiz.id, NANOS_PER_HOUR)
+                .unwrap()
+                .vote_count,
+            0
+        );
+
+        vote(user(1), quiz.id, "A").unwrap();
+        system::advance(3 * NANOS_PER_HOUR);
+        let burst = time();
+        for voter in 2..=4 {
+            vote(user(voter), quiz.id, "B").unwrap();
+            system::advance(10 * 60 * 1_000_000_000);
+        }
+        system::advance(3 * NANOS_PER_HOUR);
+        vote(user(5), quiz.id, "A").unwrap();
+
+        let peak = get_peak_voting_window(quiz.id, NANOS_PER_HOUR).unwrap();
+        assert_eq!(peak.vote_count, 3);
+        assert_eq!((peak.start, peak.end), (burst, burst + NANOS_PER_HOUR));
+    }
+
+    #[test]
+    fn vote_entropy_runs_from_unanimous_to_even() {
+        let quiz = poll(&["A", "B", "C", "D"]);
+        assert_eq!(get_vote_entropy(quiz.id).unwrap(), 0.0);
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "A").unwrap();
+        assert_eq!(get_vote_entropy(quiz.id).unwrap(), 0.0);
+
+        vote(user(3), quiz.id, "B").unwrap();
+        vote(user(4), quiz.id, "C").unwrap();
+        vote(user(2), quiz.id, "D").unwrap();
+        assert!((get_vote_entropy(quiz.id).unwrap() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn results_hash_is_stable_until_a_vote_changes_the_tallies() {
+        let quiz = poll(&["A", "B"]);
+        let empty = get_results_hash(quiz.id).unwrap();
+        assert_eq!(empty.len(), 64);
+        assert_eq!(get_results_hash(quiz.id).unwrap(), empty);
+
+        vote(user(1), quiz.id, "A").unwrap();
+        let one_vote = get_results_hash(quiz.id).unwrap();
+        assert_ne!(one_vote, empty);
+        assert_eq!(get_results_hash(quiz.id).unwrap(), one_vote);
+
+        // same tallies in another quiz, same hash
+        let twin = poll(&["B", "A"]);
+        vote(user(2), twin.id, "A").unwrap();
+        assert_eq!(get_results_hash(twin.id).unwrap(), one_vote);
+    }
+
+    #[test]
+    fn low_accuracy_lists_hard_quizzes_hardest_first() {
+        let hard = graded(&["A", "B"], 0);
+        let harder = graded(&["C", "D"], 0);
+        let easy = graded(&["E", "F"], 0);
+        poll(&["G", "H"]);
+        for (voter, (hard_pick, harder_pick, easy_pick)) in [
+            ("A", "D", "E"),
+            ("B", "D", "E"),
+            ("B", "D", "E"),
+            ("B", "C", "F"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let voter = user(voter as u8 + 1);
+            vote(voter, hard.id, hard_pick).unwrap();
+            vote(voter, harder.id, harder_pick).unwrap();
+            vote(voter, easy.id, easy_pick).unwrap();
+        }
+
+        let ids = |max_accuracy, min_votes| {
+            get_low_accuracy_quizzes(max_accuracy, min_votes)
+                .unwrap()
+                .iter()
+                .map(|quiz| quiz.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(0.5, 1), vec![hard.id, harder.id]);
+        assert_eq!(ids(1.0, 1), vec![hard.id, harder.id, easy.id]);
+        assert!(ids(0.5, 5).is_empty());
+        assert!(get_low_accuracy_quizzes(1.5, 0).is_err());
+    }
+
+    #[test]
+    fn participation_funnel_counts_each_stage() {
+        let popular = poll(&["A", "B"]);
+        let some = poll(&["C", "D"]);
+        poll(&["E", "F"]);
+        let closed = poll(&["G", "H"]);
+        for voter in 1..=10 {
+            vote(user(voter), popular.id, "A").unwrap();
+        }
+        vote(user(1), some.id, "C").unwrap();
+        close_and_snapshot(closed.id).unwrap();
+
+        let funnel = get_participation_funnel();
+        assert_eq!(funnel.total_quizzes, 4);
+        assert_eq!(funnel.with_any_votes, 2);
+        assert_eq!(funnel.with_ten_votes, 1);
+        assert_eq!(funnel.closed_or_expired, 1);
+    }
+
+    #[test]
+    fn runner_up_needs_a_clear_first_place() {
+        let runner_up = |votes: &[&str]| {
+            let quiz = poll(&["A", "B", "C"]);
+            for (voter, option) in votes.iter().enumerate() {
+                vote(user(voter as u8 + 1), quiz.id, option).unwrap();
+            }
+            get_runner_up(quiz.id)
+                .unwrap()
+                .map(|result| (result.option, result.votes))
+        };
+
+        assert_eq!(
+            runner_up(&["A", "A", "A", "C", "C", "B"]),
+            Some(("C".to_string(), 2))
+        );
+        // a tie for second goes to the option listed first
+        assert_eq!(runner_up(&["C", "C", "B", "A"]), Some(("A".to_string(), 1)));
+        assert_eq!(runner_up(&["A", "B", "B", "A"]), None);
+        assert_eq!(runner_up(&["B", "B"]), None);
+        assert_eq!(runner_up(&[]), None);
+    }
+
+    #[test]
+    fn vote_gini_is_zero_when_even_and_high_when_dominated() {
+        let quiz = poll(&["A", "B", "C", "D"]);
+        assert_eq!(get_vote_gini(quiz.id).unwrap(), 0.0);
+        for (voter, option) in ["A", "B", "C", "D"].into_iter().enumerate() {
+            vote(user(voter as u8 + 1), quiz.id, option).unwrap();
+        }
+        assert_eq!(get_vote_gini(quiz.id).unwrap(), 0.0);
+
+        let dominated = poll(&["A", "B", "C", "D"]);
+        for voter in 1..=5 {
+            vote(user(voter), dominated.id, "B").unwrap();
+        }
+        assert!((get_vote_gini(dominated.id).unwrap() - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn next_id_is_the_one_create_will_assign() {
+        let next = get_next_id();
+        assert_eq!(poll(&["A", "B"]).id, next);
+        assert_eq!(get_next_id(), next + 1);
+        // a rejected create doesn't use up an id
+        assert!(create_quiz_as(owner(), payload(&["A"])).is_err());
+        assert_eq!(poll(&["C", "D"]).id, next + 1);
+    }
+
+    #[test]
+    fn time_to_first_vote_measures_from_creation() {
+        let quiz = poll(&["A", "B"]);
+        assert_eq!(get_time_to_first_vote(quiz.id).unwrap(), None);
+
+        system::advance(90);
+        vote(user(1), quiz.id, "A").unwrap();
+        system::advance(500);
+        vote(user(2), quiz.id, "B").unwrap();
+        assert_eq!(get_time_to_first_vote(quiz.id).unwrap(), Some(90));
+        assert!(matches!(
+            get_time_to_first_vote(999),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn turnout_counts_allowlisted_voters_only_when_there_is_an_allowlist() {
+        let open = poll(&["A", "B"]);
+        vote(user(1), open.id, "A").unwrap();
+        let turnout = get_turnout(open.id).unwrap();
+        assert_eq!(turnout.voters, 1);
+        assert_eq!(turnout.allowed, None);
+        assert_eq!(turnout.turnout, None);
+
+        let restricted = poll(&["A", "B"]);
+        for voter in 1..=4 {
+            add_allowed_voter(restricted.id, user(voter)).unwrap();
+        }
+        vote(user(2), restricted.id, "B").unwrap();
+        let turnout = get_turnout(restricted.id).unwrap();
+        assert_eq!(turnout.voters, 1);
+        assert_eq!(turnout.allowed, Some(4));
+        assert_eq!(turnout.turnout, Some(0.25));
+    }
+
+    #[test]
+    fn voter_cohorts_bucket_distinct_voters_by_first_byte() {
+        let quiz = poll(&["A", "B"]);
+        assert!(get_voter_cohorts(quiz.id).unwrap().is_empty());
+
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "B").unwrap();
+        vote(Principal::from_slice(&[3, 1]), quiz.id, "A").unwrap();
+        let cohorts: Vec<_> = get_voter_cohorts(quiz.id)
+            .unwrap()
+            .into_iter()
+            .map(|cohort| (cohort.prefix, cohort.voters))
+            .collect();
+        assert_eq!(cohorts, vec![(3, 1), (7, 2)]);
+    }
+
+    #[test]
+    fn ascii_chart_draws_the_leader_with_the_longest_bar() {
+        let quiz = poll(&["Yes", "No"]);
+        vote(user(1), quiz.id, "No").unwrap();
+        for voter in 2..=4 {
+            vote(user(voter), quiz.id, "Yes").unwrap();
+        }
+        assert_eq!(
+            render_quiz_ascii(quiz.id, 20).unwrap(),
+            "Which one?\nYes |###############     | 3\nNo  |#####               | 1\n"
+        );
+        // narrow widths are clamped up to the minimum
+        assert_eq!(
+            render_quiz_ascii(quiz.id, 0).unwrap(),
+            render_quiz_ascii(quiz.id, MIN_ASCII_CHART_WIDTH).unwrap()
+        );
+    }
+
+    #[test]
+    fn last_voter_follows_the_latest_ballot() {
+        let quiz = poll(&["A", "B"]);
+        let other = poll(&["A", "B"]);
+        assert_eq!(get_last_voter(quiz.id).unwrap(), None);
+
+        vote(user(1), quiz.id, "A").unwrap();
+        assert_eq!(get_last_voter(quiz.id).unwrap(), Some(user(1)));
+        vote(user(2), quiz.id, "B").unwrap();
+        // ballots on another quiz don't leak across
+        vote(user(3), other.id, "A").unwrap();
+        assert_eq!(get_last_voter(quiz.id).unwrap(), Some(user(2)));
+        assert_eq!(get_last_voter(other.id).unwrap(), Some(user(3)));
+    }
+
+    #[test]
+    fn decisiveness_trend_tracks_the_leader_share_per_snapshot() {
+        let quiz = poll(&["A", "B"]);
+        assert!(get_decisiveness_trend(quiz.id).unwrap().is_empty());
+
+        take_snapshot(quiz.id).unwrap();
+        vote(user(1), quiz.id, "A").unwrap();
+        vote(user(2), quiz.id, "B").unwrap();
+        take_snapshot(quiz.id).unwrap();
+        vote(user(3), quiz.id, "A").unwrap();
+        vote(user(4), quiz.id, "A").unwrap();
+        take_snapshot(quiz.id).unwrap();
+        assert_eq!(
+            get_decisiveness_trend(quiz.id).unwrap(),
+            vec![0.0, 0.5, 0.75]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+// Checks on what callers send in, run before any state is touched. Failures
+// name the offending field, e.g. "options[2]" or "questions[0].question".
+
+use crate::{
+    get_config, normalize_option, normalize_tag, normalize_tags, Error, QuestionPayload, QuizKind,
+    DEFAULT_MAX_OPTION_LEN, DEFAULT_MAX_QUESTION_LEN, MAX_CATEGORY_LEN, MAX_IMAGE_URL_LEN,
+    MAX_LOCALE_LEN, MAX_METADATA_LEN, MAX_OPTIONS, MAX_OPTION_DESCRIPTION_LEN, MAX_QUESTIONS,
+    MAX_TAGS, MIN_OPTIONS, MIN_OPTION_LEN,
+};
+
+fn failed(field: &str, msg: String) -> Error {
+    Error::ValidationFailed {
+        field: field.to_string(),
+        msg,
+    }
+}
+
+pub(crate) fn validate_question(field: &str, question: &str) -> Result<(), Error> {
+    if question.trim().is_empty() {
+        return Err(failed(field, "question must not be empty".to_string()));
+    }
+    let max_len = get_config()
+        .max_question_len
+        .unwrap_or(DEFAULT_MAX_QUESTION_LEN);
+    if question.chars().count() as u64 > max_len {
+        return Err(failed(
+            field,
+            format!("question must be at most {} characters", max_len),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_options(field: &str, options: &[String]) -> Result<(), Error> {
+    if options.len() < MIN_OPTIONS || options.len() > MAX_OPTIONS {
+        return Err(failed(
+            field,
+            format!(
+                "a question needs between {} and {} options",
+                MIN_OPTIONS, MAX_OPTIONS
+            ),
+        ));
+    }
+    let normalized: Vec<String> = options
+        .iter()
+        .map(|option| normalize_option(option))
+        .collect();
+    for (i, option) in normalized.iter().enumerate() {
+        let field = format!("{}[{}]", field, i);
+        validate_option_label(&field, &options[i])?;
+        if normalized[..i].contains(option) {
+            return Err(failed(
+                &field,
+                format!("option '{}' duplicates an earlier option", options[i]),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// the length checks on one option, measured after normalizing
+pub(crate) fn validate_option_label(field: &str, option: &str) -> Result<(), Error> {
+    let config = get_config();
+    let min_len = config.min_option_len.unwrap_or(MIN_OPTION_LEN);
+    let max_len = config.max_option_len.unwrap_or(DEFAULT_MAX_OPTION_LEN);
+    let len = normalize_option(option).chars().count() as u64;
+    if len < min_len {
+        return Err(failed(
+            field,
+            format!("option '{}' is shorter than {} characters", option, min_len),
+        ));
+    }
+    if len > max_len {
+        return Err(failed(
+            field,
+            format!("option '{}' is longer than {} characters", option, max_len),
+        ));
+    }
+    Ok(())
+}
+
+// open-text quizzes are the one kind without options
+pub(crate) fn validate_options_for(kind: QuizKind, options: &[String]) -> Result<(), Error> {
+    match kind {
+        QuizKind::OpenText if options.is_empty() => Ok(()),
+        QuizKind::OpenText => Err(failed(
+            "options",
+            "an open-text quiz has no options".to_string(),
+        )),
+        _ => validate_options("options", options),
+    }
+}
+
+pub(crate) fn validate_extra_questions(questions: &[QuestionPayload]) -> Result<(), Error> {
+    if questions.len() >= MAX_QUESTIONS {
+        return Err(failed(
+            "questions",
+            format!("a quiz can have at most {} questions", MAX_QUESTIONS),
+        ));
+    }
+    for (i, question) in questions.iter().enumerate() {
+        validate_question(&format!("questions[{}].question", i), &question.question)?;
+        validate_options(&format!("questions[{}].options", i), &question.options)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_metadata(json: &str) -> Result<(), Error> {
+    if json.len() > MAX_METADATA_LEN {
+        return Err(failed(
+            "metadata",
+            format!("metadata must be at most {} bytes", MAX_METADATA_LEN),
+        ));
+    }
+    serde_json::from_str::<serde_json::Value>(json)
+        .map(|_| ())
+        .map_err(|e| failed("metadata", format!("metadata is not valid JSON: {}", e)))
+}
+
+// counted after normalizing, so "Rust" and " rust" take one slot
+pub(crate) fn validate_tags(tags: &[String]) -> Result<(), Error> {
+    let normalized = normalize_tags(tags.to_vec());
+    if normalized.len() > MAX_TAGS {
+        return Err(failed(
+            "tags",
+            format!("a quiz can have at most {} tags", MAX_TAGS),
+        ));
+    }
+    match tags.iter().position(|tag| normalize_tag(tag).is_empty()) {
+        Some(i) => Err(failed(
+            &format!("tags[{}]", i),
+            "tag must not be empty".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn validate_category(category: &str) -> Result<(), Error> {
+    let category = normalize_tag(category);
+    if category.is_empty() {
+        return Err(failed("category", "category must not be empty".to_string()));
+    }
+    if category.chars().count() > MAX_CATEGORY_LEN {
+        return Err(failed(
+            "category",
+            format!("category must be at most {} characters", MAX_CATEGORY_LEN),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_max_selections(
+    max_selections: u32,
+    option_count: usize,
+) -> Result<(), Error> {
+    if max_selections == 0 || max_selections as usize > option_count {
+        return Err(failed(
+            "max_selections",
+            format!("max_selections must be between 1 and {}", option_count),
+        ));
+    }
+    Ok(())
+}
+
+// `expires_at` is whichever one the quiz will end up with
+pub(crate) fn validate_opens_at(
+    opens_at: u64,
+    expires_at: Option<u64>,
+    now: u64,
+) -> Result<(), Error> {
+    if opens_at <= now {
+        return Err(failed(
+            "opens_at",
+            "opens_at must be in the future".to_string(),
+        ));
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= opens_at) {
+        return Err(failed(
+            "opens_at",
+            "opens_at must come before expires_at".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// empty is allowed: it clears the description
+pub(crate) fn validate_option_description(description: &str) -> Result<(), Error> {
+    if description.chars().count() > MAX_OPTION_DESCRIPTION_LEN {
+        return Err(failed(
+            "description",
+            format!(
+                "description must be at most {} characters",
+                MAX_OPTION_DESCRIPTION_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_image_url(url: &str) -> Result<(), Error> {
+    if !url.is_empty() && (!url.starts_with("https://") || url.len() > MAX_IMAGE_URL_LEN) {
+        return Err(failed(
+            "image_url",
+            format!(
+                "an image url must be https and at most {} bytes",
+                MAX_IMAGE_URL_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a BCP 47-style tag such as "en" or "pt-BR"; "_" is accepted for "-"
+pub(crate) fn validate_locale(locale: &str) -> Result<(), Error> {
+    let locale = locale.trim();
+    let well_formed = locale.len() >= 2
+        && locale.len() <= MAX_LOCALE_LEN
+        && locale.starts_with(|c: char| c.is_ascii_alphabetic())
+        && locale
+            .split(['-', '_'])
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !well_formed {
+        return Err(failed(
+            "locale",
+            format!("'{}' is not a locale tag like \"en\" or \"pt-BR\"", locale),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn field_of(result: Result<(), Error>) -> String {
+        match result {
+            Err(Error::ValidationFailed { field, .. }) => field,
+            _ => panic!("expected a validation failure"),
+        }
+    }
+
+    #[test]
+    fn question_must_be_present_and_short_enough() {
+        assert!(validate_question("question", "Tea or coffee?").is_ok());
+        assert_eq!(field_of(validate_question("question", "   ")), "question");
+        let long = "x".repeat(DEFAULT_MAX_QUESTION_LEN as usize + 1);
+        assert_eq!(field_of(validate_question("question", &long)), "question");
+    }
+
+    #[test]
+    fn options_are_counted_and_deduplicated_after_normalizing() {
+        assert!(validate_options("options", &strings(&["Yes", "No"])).is_ok());
+        assert_eq!(
+            field_of(validate_options("options", &strings(&["Yes"]))),
+            "options"
+        );
+        let too_many: Vec<String> = (0..=MAX_OPTIONS).map(|i| i.to_string()).collect();
+        assert_eq!(field_of(validate_options("options", &too_many)), "options");
+        assert_eq!(
+            field_of(validate_options(
+                "options",
+                &strings(&["Yes", "No", " yes"])
+            )),
+            "options[2]"
+        );
+        assert_eq!(
+            field_of(validate_options("options", &strings(&["Yes", "  "]))),
+            "options[1]"
+        );
+    }
+
+    #[test]
+    fn open_text_quizzes_take_no_options() {
+        assert!(validate_options_for(QuizKind::OpenText, &[]).is_ok());
+        assert!(validate_options_for(QuizKind::OpenText, &strings(&["A", "B"])).is_err());
+        assert!(validate_options_for(QuizKind::Poll, &[]).is_err());
+    }
+
+    #[test]
+    fn extra_questions_name_the_failing_entry() {
+        let questions = vec![
+            QuestionPayload {
+                question: "First?".to_string(),
+                options: strings(&["A", "B"]),
+            },
+            QuestionPayload {
+                question: "Second?".to_string(),
+                options: strings(&["A", "a"]),
+            },
+        ];
+        assert_eq!(
+            field_of(validate_extra_questions(&questions)),
+            "questions[1].options[1]"
+        );
+    }
+
+    #[test]
+    fn tags_collapse_before_counting() {
+        let same: Vec<String> = (0..MAX_TAGS + 5).map(|_| " Rust".to_string()).collect();
+        assert!(validate_tags(&same).is_ok());
+        let distinct: Vec<String> = (0..=MAX_TAGS).map(|i| format!("tag{}", i)).collect();
+        assert_eq!(field_of(validate_tags(&distinct)), "tags");
+        assert_eq!(field_of(validate_tags(&strings(&["ok", " "]))), "tags[1]");
+    }
+
+    #[test]
+    fn metadata_must_be_json() {
+        assert!(validate_metadata(r#"{"level": 2}"#).is_ok());
+        assert_eq!(field_of(validate_metadata("{level")), "metadata");
+    }
+
+    #[test]
+    fn scheduling_and_selection_bounds() {
+        assert!(validate_max_selections(2, 3).is_ok());
+        assert!(validate_max_selections(0, 3).is_err());
+        assert!(validate_max_selections(4, 3).is_err());
+        assert!(validate_opens_at(20, Some(30), 10).is_ok());
+        assert!(validate_opens_at(10, None, 10).is_err());
+        assert!(validate_opens_at(20, Some(20), 10).is_err());
+    }
+
+    #[test]
+    fn urls_and_locales() {
+        assert!(validate_image_url("").is_ok());
+        assert!(validate_image_url("https://example.com/a.png").is_ok());
+        assert!(validate_image_url("http://example.com/a.png").is_err());
+        for locale in ["en", "pt-BR", "zh_Hant"] {
+            assert!(validate_locale(locale).is_ok(), "{}", locale);
+        }
+        for locale in ["e", "1en", "en--US", "en US"] {
+            assert!(validate_locale(locale).is_err(), "{}", locale);
+        }
+    }
+
+    #[test]
+    fn question_length_counts_characters_up_to_the_limit() {
+        let max = DEFAULT_MAX_QUESTION_LEN as usize;
+        assert!(validate_question("question", &"q".repeat(max)).is_ok());
+        assert!(validate_question("question", &"q".repeat(max + 1)).is_err());
+        // two bytes each, but one character
+        assert!(validate_question("question", &"é".repeat(max)).is_ok());
+        assert!(validate_question("question", &"é".repeat(max + 1)).is_err());
+
+        crate::write_config(|config| config.max_question_len = Some(3));
+        assert!(validate_question("question", "猫か犬").is_ok());
+        assert_eq!(
+            field_of(validate_question("question", "猫か犬?")),
+            "question"
+        );
+    }
+
+    #[test]
+    fn option_length_has_a_configurable_minimum() {
+        assert!(validate_option_label("options[0]", "A").is_ok());
+        assert!(validate_option_label("options[0]", "  ").is_err());
+
+        crate::write_config(|config| config.min_option_len = Some(3));
+        assert!(validate_option_label("options[0]", " Yes ").is_ok());
+        assert!(validate_option_label("options[0]", "Ünö").is_ok());
+        assert_eq!(
+            field_of(validate_option_label("options[0]", "No")),
+            "options[0]"
+        );
+        assert_eq!(
+            field_of(validate_options("options", &strings(&["Yes", "No"]))),
+            "options[1]"
+        );
+    }
+}